@@ -0,0 +1,250 @@
+//! Derive macro for [axum-jwks](https://docs.rs/axum-jwks) claims types.
+//!
+//! Enable the `derive` feature of `axum-jwks` instead of depending on this
+//! crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, spanned::Spanned, Data, DeriveInput, Expr, ExprArray, ExprLit, Fields,
+    Ident, Lit, LitStr, Type,
+};
+
+/// Implement `ParseTokenClaims` for a claims type.
+///
+/// Without attributes, the claims are accepted once the token is validated and
+/// rejected with `ClaimsRejection`. The following attributes add checks.
+///
+/// On the type:
+/// * `#[jwt(issuer = "...")]` requires the `iss` claim to match. Repeat it to
+///   allow any of several issuers.
+/// * `#[jwt(audience = "...")]` requires the `aud` claim to contain the
+///   audience. Repeat it to allow any of several audiences.
+/// * `#[jwt(require_scope = "...")]` requires the `scope` or `scp` claim to
+///   grant the scope. Repeat it to require several scopes.
+/// * `#[jwt(rejection = MyError)]` rejects requests with `MyError` instead,
+///   which must implement `From<TokenError>`, `From<ClaimsRejection>` and
+///   `IntoResponse`.
+///
+/// On fields:
+/// * `#[jwt(one_of = ["...", "..."])]` requires a string field to have one of
+///   the given values. For an `Option`, a missing value is accepted, and for a
+///   `Vec`, every item must be allowed.
+///
+/// The checks run in every extractor that is generic over the claims type,
+/// such as `Claims`, `DPoP` or `Enriched`.
+///
+/// # Example
+/// ```
+/// use axum_jwks::{Claims, ParseTokenClaims};
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize, ParseTokenClaims)]
+/// #[jwt(issuer = "https://auth.example.com", audience = "my-api")]
+/// #[jwt(require_scope = "read")]
+/// struct TokenClaims {
+///     sub: String,
+///     #[jwt(one_of = ["admin", "editor"])]
+///     role: String,
+/// }
+///
+/// async fn handler(Claims(claims): Claims<TokenClaims>) -> String {
+///     claims.sub
+/// }
+/// ```
+#[proc_macro_derive(ParseTokenClaims, attributes(jwt))]
+pub fn derive_parse_token_claims(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct Container {
+    issuers: Vec<LitStr>,
+    audiences: Vec<LitStr>,
+    scopes: Vec<LitStr>,
+    rejection: Option<Type>,
+}
+
+struct OneOf {
+    field: Ident,
+    allowed: Vec<LitStr>,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let container = parse_container(&input)?;
+    let one_of = parse_fields(&input)?;
+
+    let name = &input.ident;
+    let mut generics = input.generics.clone();
+    generics
+        .params
+        .push(syn::parse_quote!(__S: ::core::marker::Send + ::core::marker::Sync));
+    let (impl_generics, _, _) = generics.split_for_impl();
+    let (_, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let rejection = container
+        .rejection
+        .map(|rejection| quote!(#rejection))
+        .unwrap_or_else(|| quote!(::axum_jwks::ClaimsRejection));
+
+    let mut claim_checks = Vec::new();
+    if !container.issuers.is_empty() {
+        let issuers = &container.issuers;
+        claim_checks.push(quote! {
+            ::axum_jwks::__private::check_issuer(claims, &[#(#issuers),*])?;
+        });
+    }
+    if !container.audiences.is_empty() {
+        let audiences = &container.audiences;
+        claim_checks.push(quote! {
+            ::axum_jwks::__private::check_audience(claims, &[#(#audiences),*])?;
+        });
+    }
+    if !container.scopes.is_empty() {
+        let scopes = &container.scopes;
+        claim_checks.push(quote! {
+            ::axum_jwks::__private::check_scopes(claims, &[#(#scopes),*])?;
+        });
+    }
+
+    let check_claims = (!claim_checks.is_empty()).then(|| {
+        quote! {
+            fn check_claims(
+                claims: &::axum_jwks::RawClaims,
+            ) -> ::core::result::Result<(), Self::Rejection> {
+                let claims = &::axum_jwks::__private::checked_claims(claims)?;
+                #(#claim_checks)*
+
+                ::core::result::Result::Ok(())
+            }
+        }
+    });
+
+    let validate = (!one_of.is_empty()).then(|| {
+        let checks = one_of.iter().map(|OneOf { field, allowed }| {
+            let claim = field.to_string();
+
+            quote! {
+                ::axum_jwks::__private::check_one_of(&self.#field, #claim, &[#(#allowed),*])?;
+            }
+        });
+
+        quote! {
+            fn validate(
+                &self,
+                _state: &__S,
+            ) -> impl ::core::future::Future<
+                Output = ::core::result::Result<(), Self::Rejection>,
+            > + ::core::marker::Send {
+                let check = || -> ::core::result::Result<(), ::axum_jwks::ClaimsRejection> {
+                    #(#checks)*
+
+                    ::core::result::Result::Ok(())
+                };
+
+                ::core::future::ready(check().map_err(::core::convert::From::from))
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::axum_jwks::ParseTokenClaims<__S> for #name #ty_generics #where_clause {
+            type Rejection = #rejection;
+
+            #check_claims
+            #validate
+        }
+    })
+}
+
+fn parse_container(input: &DeriveInput) -> syn::Result<Container> {
+    let mut container = Container::default();
+
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("jwt"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("issuer") {
+                container.issuers.push(meta.value()?.parse()?);
+            } else if meta.path.is_ident("audience") {
+                container.audiences.push(meta.value()?.parse()?);
+            } else if meta.path.is_ident("require_scope") {
+                container.scopes.push(meta.value()?.parse()?);
+            } else if meta.path.is_ident("rejection") {
+                if container.rejection.is_some() {
+                    return Err(meta.error("duplicate `rejection` attribute"));
+                }
+                container.rejection = Some(meta.value()?.parse()?);
+            } else {
+                return Err(meta.error("unsupported `jwt` attribute"));
+            }
+
+            Ok(())
+        })?;
+    }
+
+    Ok(container)
+}
+
+fn parse_fields(input: &DeriveInput) -> syn::Result<Vec<OneOf>> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(
+            input.span(),
+            "`ParseTokenClaims` can only be derived for structs",
+        ));
+    };
+
+    let mut checks = Vec::new();
+    for field in &data.fields {
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("jwt"))
+        {
+            let Fields::Named(_) = data.fields else {
+                return Err(syn::Error::new(
+                    attr.span(),
+                    "field checks are only supported on named fields",
+                ));
+            };
+
+            attr.parse_nested_meta(|meta| {
+                if !meta.path.is_ident("one_of") {
+                    return Err(meta.error("unsupported `jwt` attribute"));
+                }
+
+                let values: ExprArray = meta.value()?.parse()?;
+                let allowed = values
+                    .elems
+                    .iter()
+                    .map(|value| match value {
+                        Expr::Lit(ExprLit {
+                            lit: Lit::Str(value),
+                            ..
+                        }) => Ok(value.clone()),
+                        other => Err(syn::Error::new(other.span(), "expected a string literal")),
+                    })
+                    .collect::<syn::Result<_>>()?;
+
+                checks.push(OneOf {
+                    field: field
+                        .ident
+                        .clone()
+                        .expect("named fields have an identifier"),
+                    allowed,
+                });
+
+                Ok(())
+            })?;
+        }
+    }
+
+    Ok(checks)
+}
@@ -0,0 +1,307 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    extract::Request,
+    http::{header::HOST, request::Parts},
+};
+use tower_layer::Layer;
+use tower_service::Service;
+use tracing::debug;
+
+use crate::ValidationOptions;
+
+/// A [`Layer`] that selects the expected audience of tokens from the host a
+/// request is addressed to.
+///
+/// The host is read from the request's authority, which holds the `:authority`
+/// pseudo-header for HTTP/2, or from the `Host` header. Ports are ignored and
+/// hosts are compared case-insensitively. The audience is stored as
+/// [`ValidationOptions`] in the request's extensions, which the extractors in
+/// this crate use when validating the request's token, apart from the
+/// exceptions listed there.
+///
+/// Tokens sent to hosts without a configured audience are rejected, since the
+/// `Host` header is chosen by the client. Configure an audience for those
+/// hosts with [`fallback`][Self::fallback].
+///
+/// To select the audience by route instead, add [`ValidationOptions`] to the
+/// routes with [`Extension`][axum::Extension].
+///
+/// # Example
+/// ```
+/// use axum::{routing::get, Extension, Router};
+/// use axum_jwks::{AudienceLayer, Jwks, ValidationOptions};
+///
+/// # fn app(jwks: Jwks) -> Router {
+/// let admin = Router::new()
+///     .route("/users", get(|| async { "ok" }))
+///     .layer(Extension(
+///         ValidationOptions::default().audience(["https://admin.example.com"]),
+///     ));
+///
+/// Router::new()
+///     .route("/", get(|| async { "ok" }))
+///     .nest("/admin", admin)
+///     .layer(
+///         AudienceLayer::new()
+///             .host("api.a.com", "https://api.a.com")
+///             .host("api.b.com", "https://api.b.com")
+///             .fallback("https://api.example.com"),
+///     )
+///     .with_state(jwks)
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct AudienceLayer {
+    hosts: Arc<HashMap<String, String>>,
+    fallback: Option<String>,
+}
+
+impl AudienceLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expect tokens for requests to `host` to be issued for `audience`.
+    pub fn host(mut self, host: impl Into<String>, audience: impl Into<String>) -> Self {
+        Arc::make_mut(&mut self.hosts).insert(host.into().to_ascii_lowercase(), audience.into());
+        self
+    }
+
+    /// Expect tokens for requests to any other host to be issued for
+    /// `audience`.
+    pub fn fallback(mut self, audience: impl Into<String>) -> Self {
+        self.fallback = Some(audience.into());
+        self
+    }
+
+    fn audience(&self, parts: &Parts) -> Option<&String> {
+        let host = parts.uri.host().or_else(|| {
+            parts
+                .headers
+                .get(HOST)
+                .and_then(|host| host.to_str().ok())
+                .and_then(|host| host.split(':').next())
+        });
+
+        host.and_then(|host| self.hosts.get(&host.to_ascii_lowercase()))
+            .or(self.fallback.as_ref())
+    }
+}
+
+impl<I> Layer<I> for AudienceLayer {
+    type Service = AudienceService<I>;
+
+    fn layer(&self, inner: I) -> Self::Service {
+        AudienceService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// The [`Service`] created by [`AudienceLayer`].
+#[derive(Clone, Debug)]
+pub struct AudienceService<I> {
+    inner: I,
+    layer: AudienceLayer,
+}
+
+impl<I> Service<Request> for AudienceService<I>
+where
+    I: Service<Request>,
+{
+    type Response = I::Response;
+    type Error = I::Error;
+    type Future = I::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let (mut parts, body) = request.into_parts();
+
+        // Without an audience, no token can be valid for the request.
+        let audiences = match self.layer.audience(&parts) {
+            Some(audience) => {
+                debug!(%audience, "Selected audience from the request's host.");
+
+                vec![audience.clone()]
+            }
+            None => {
+                debug!("No audience is configured for the request's host.");
+
+                Vec::new()
+            }
+        };
+
+        // Keep options that were set by an outer layer.
+        let options = parts
+            .extensions
+            .remove::<ValidationOptions>()
+            .unwrap_or_default()
+            .audience(audiences);
+        parts.extensions.insert(options);
+
+        self.inner.call(Request::from_parts(parts, body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, StatusCode},
+        routing::get,
+        Extension, Router,
+    };
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{test_util, Claims, Jwks, ParseTokenClaims, TokenError};
+
+    #[derive(serde::Deserialize)]
+    struct TestClaims {}
+
+    impl<S: Send + Sync> ParseTokenClaims<S> for TestClaims {
+        type Rejection = TokenError;
+    }
+
+    #[test]
+    fn validate_claims_with_overrides_audience() {
+        let jwks = test_util::jwks(Some("https://default.example.com"));
+        let token = test_util::sign(&json!({
+            "aud": "https://api.a.com",
+            "exp": test_util::timestamp(60),
+        }));
+
+        assert!(jwks.validate_claims::<Value>(&token).is_err());
+
+        let options = ValidationOptions::default().audience(["https://api.a.com"]);
+        assert!(jwks.validate_claims_with::<Value>(&token, &options).is_ok());
+
+        let options = ValidationOptions::default().audience(["https://api.b.com"]);
+        assert!(jwks
+            .validate_claims_with::<Value>(&token, &options)
+            .is_err());
+    }
+
+    async fn status(router: &mut Router, uri: &str, host: &str, audience: &str) -> StatusCode {
+        let token = test_util::sign(&json!({
+            "aud": audience,
+            "exp": test_util::timestamp(60),
+        }));
+        let request = Request::builder()
+            .uri(uri)
+            .header(HOST, host)
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+
+        router.call(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn audience_from_host_and_route() {
+        let jwks: Jwks = test_util::jwks(Some("https://default.example.com"));
+        let handler = get(|_: Claims<TestClaims>| async {});
+        let admin = Router::new()
+            .route("/users", handler.clone())
+            .layer(Extension(
+                ValidationOptions::default().audience(["https://admin.example.com"]),
+            ));
+        let mut router = Router::new()
+            .route("/", handler)
+            .nest("/admin", admin)
+            .layer(AudienceLayer::new().host("API.A.com", "https://api.a.com"))
+            .with_state(jwks);
+
+        let cases = [
+            ("/", "api.a.com:443", "https://api.a.com", StatusCode::OK),
+            (
+                "/",
+                "api.a.com",
+                "https://default.example.com",
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                "/",
+                "other.com",
+                "https://default.example.com",
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                "/",
+                "other.com",
+                "https://api.a.com",
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                "/admin/users",
+                "other.com",
+                "https://admin.example.com",
+                StatusCode::OK,
+            ),
+            (
+                "/admin/users",
+                "other.com",
+                "https://default.example.com",
+                StatusCode::UNAUTHORIZED,
+            ),
+        ];
+
+        for (uri, host, audience, expected) in cases {
+            assert_eq!(
+                expected,
+                status(&mut router, uri, host, audience).await,
+                "{uri} {host} {audience}"
+            );
+        }
+    }
+    #[tokio::test]
+    async fn reject_unmapped_host() {
+        let jwks: Jwks = test_util::jwks(None);
+        let handler = get(|_: Claims<TestClaims>| async {});
+        let layer = AudienceLayer::new().host("api.a.com", "https://api.a.com");
+        let mut router = Router::new()
+            .route("/", handler.clone())
+            .layer(layer.clone())
+            .with_state(jwks.clone());
+
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            status(&mut router, "/", "other.com", "https://other.com").await
+        );
+
+        let token = test_util::sign(&json!({ "exp": test_util::timestamp(60) }));
+        let request = Request::builder()
+            .uri("/")
+            .header(HOST, "other.com")
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            router.call(request).await.unwrap().status()
+        );
+
+        let mut router = Router::new()
+            .route("/", handler)
+            .layer(layer.fallback("https://other.com"))
+            .with_state(jwks);
+
+        assert_eq!(
+            StatusCode::OK,
+            status(&mut router, "/", "other.com", "https://other.com").await
+        );
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            status(&mut router, "/", "other.com", "https://api.a.com").await
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use thiserror::Error;
+use tracing::debug;
+
+use crate::{scope::missing_scopes, RawClaims, ScopeRejection, TokenError};
+
+/// The rejection for claims types that derive `ParseTokenClaims` with the
+/// `derive` feature.
+///
+/// Derived implementations with a custom `rejection` type convert this
+/// rejection with [`From`].
+#[derive(Debug, Error, PartialEq)]
+pub enum ClaimsRejection {
+    #[error(transparent)]
+    Token(#[from] TokenError),
+
+    /// The token's `iss` claim is not one of the required issuers.
+    #[error("the token was issued by an unexpected issuer")]
+    InvalidIssuer,
+
+    /// The token's `aud` claim does not contain one of the required
+    /// audiences.
+    #[error("the token is not intended for this audience")]
+    InvalidAudience,
+
+    /// The token does not grant the required scopes.
+    #[error("the token is missing the scopes {missing:?}")]
+    InsufficientScope { missing: Vec<String> },
+
+    /// A claim does not have one of its allowed values.
+    #[error("the claim {0:?} does not have an allowed value")]
+    InvalidClaim(&'static str),
+}
+
+impl IntoResponse for ClaimsRejection {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Token(error) => error.into_response(),
+            Self::InvalidIssuer | Self::InvalidAudience => StatusCode::UNAUTHORIZED.into_response(),
+            Self::InsufficientScope { missing } => {
+                ScopeRejection::InsufficientScope { missing }.into_response()
+            }
+            Self::InvalidClaim(_) => StatusCode::FORBIDDEN.into_response(),
+        }
+    }
+}
+
+/// Parse only the claims that derived checks look at.
+pub fn checked_claims(claims: &RawClaims) -> Result<Value, ClaimsRejection> {
+    #[derive(Deserialize)]
+    struct Checked {
+        iss: Option<Value>,
+        aud: Option<Value>,
+        scope: Option<Value>,
+        scp: Option<Value>,
+    }
+
+    let Checked {
+        iss,
+        aud,
+        scope,
+        scp,
+    } = claims.deserialize()?;
+    let claims: Map<String, Value> = [("iss", iss), ("aud", aud), ("scope", scope), ("scp", scp)]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name.to_owned(), value?)))
+        .collect();
+
+    Ok(Value::Object(claims))
+}
+
+/// Require the `iss` claim to be one of the given issuers.
+pub fn check_issuer(claims: &Value, issuers: &[&str]) -> Result<(), ClaimsRejection> {
+    let issuer = claims.get("iss").and_then(Value::as_str);
+
+    if !issuers.iter().any(|expected| issuer == Some(*expected)) {
+        debug!(?issuer, "Token was issued by an unexpected issuer.");
+
+        return Err(ClaimsRejection::InvalidIssuer);
+    }
+
+    Ok(())
+}
+
+/// Require the `aud` claim to contain one of the given audiences.
+pub fn check_audience(claims: &Value, audiences: &[&str]) -> Result<(), ClaimsRejection> {
+    let matches = match claims.get("aud") {
+        Some(Value::String(audience)) => audiences.contains(&audience.as_str()),
+        Some(Value::Array(audience)) => audience
+            .iter()
+            .filter_map(Value::as_str)
+            .any(|audience| audiences.contains(&audience)),
+        _ => false,
+    };
+
+    if !matches {
+        debug!("Token is not intended for this audience.");
+
+        return Err(ClaimsRejection::InvalidAudience);
+    }
+
+    Ok(())
+}
+
+/// Require the token to grant every given scope.
+pub fn check_scopes(claims: &Value, scopes: &[&str]) -> Result<(), ClaimsRejection> {
+    let missing = missing_scopes(claims, scopes);
+
+    if !missing.is_empty() {
+        debug!(?missing, "Token is missing required scopes.");
+
+        return Err(ClaimsRejection::InsufficientScope { missing });
+    }
+
+    Ok(())
+}
+
+/// Require a claim to have one of the allowed values.
+pub fn check_one_of<T>(
+    value: &T,
+    claim: &'static str,
+    allowed: &[&str],
+) -> Result<(), ClaimsRejection>
+where
+    T: OneOf + ?Sized,
+{
+    if !value.is_one_of(allowed) {
+        debug!(%claim, "Claim does not have an allowed value.");
+
+        return Err(ClaimsRejection::InvalidClaim(claim));
+    }
+
+    Ok(())
+}
+
+/// Claim values that can be restricted with `#[jwt(one_of = [...])]`.
+///
+/// Missing optional claims are accepted, and every item of a list must be
+/// allowed.
+pub trait OneOf {
+    fn is_one_of(&self, allowed: &[&str]) -> bool;
+}
+
+impl OneOf for str {
+    fn is_one_of(&self, allowed: &[&str]) -> bool {
+        allowed.contains(&self)
+    }
+}
+
+impl OneOf for String {
+    fn is_one_of(&self, allowed: &[&str]) -> bool {
+        self.as_str().is_one_of(allowed)
+    }
+}
+
+impl<T: OneOf> OneOf for Option<T> {
+    fn is_one_of(&self, allowed: &[&str]) -> bool {
+        match self {
+            Some(value) => value.is_one_of(allowed),
+            None => true,
+        }
+    }
+}
+
+impl<T: OneOf> OneOf for Vec<T> {
+    fn is_one_of(&self, allowed: &[&str]) -> bool {
+        self.iter().all(|value| value.is_one_of(allowed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn issuer_and_audience() {
+        let claims = json!({ "iss": "https://a.example.com", "aud": ["api", "other"] });
+
+        assert_eq!(
+            Ok(()),
+            check_issuer(&claims, &["https://b.example.com", "https://a.example.com"])
+        );
+        assert_eq!(
+            Err(ClaimsRejection::InvalidIssuer),
+            check_issuer(&claims, &["https://b.example.com"])
+        );
+        assert_eq!(Ok(()), check_audience(&claims, &["api"]));
+        assert_eq!(
+            Err(ClaimsRejection::InvalidAudience),
+            check_audience(&json!({ "aud": "web" }), &["api"])
+        );
+    }
+
+    #[test]
+    fn one_of() {
+        let allowed = ["admin", "editor"];
+
+        assert!("admin".is_one_of(&allowed));
+        assert!(!"viewer".to_owned().is_one_of(&allowed));
+        assert!(None::<String>.is_one_of(&allowed));
+        assert!(vec!["admin".to_owned(), "editor".to_owned()].is_one_of(&allowed));
+        assert!(!vec!["admin".to_owned(), "viewer".to_owned()].is_one_of(&allowed));
+        assert_eq!(
+            Err(ClaimsRejection::InvalidClaim("role")),
+            check_one_of("viewer", "role", &allowed)
+        );
+    }
+
+    #[cfg(feature = "derive")]
+    #[tokio::test]
+    async fn derived_checks() {
+        use axum::{
+            body::Body,
+            http::{header::AUTHORIZATION, Request},
+            routing::get,
+            Router,
+        };
+        use tower_service::Service;
+
+        use crate::{test_util, Claims, ParseTokenClaims};
+
+        #[derive(serde::Deserialize, ParseTokenClaims)]
+        #[jwt(issuer = "https://auth.example.com", require_scope = "read")]
+        struct TestClaims {
+            #[jwt(one_of = ["admin", "editor"])]
+            role: Option<String>,
+        }
+
+        let mut router = Router::new()
+            .route("/", get(|_: Claims<TestClaims>| async {}))
+            .with_state(test_util::jwks(None));
+        let cases = [
+            (
+                "https://auth.example.com",
+                "read write",
+                "admin",
+                StatusCode::OK,
+            ),
+            (
+                "https://evil.example.com",
+                "read",
+                "admin",
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                "https://auth.example.com",
+                "write",
+                "admin",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                "https://auth.example.com",
+                "read",
+                "viewer",
+                StatusCode::FORBIDDEN,
+            ),
+        ];
+
+        for (iss, scope, role, expected) in cases {
+            let token = test_util::sign(&json!({
+                "iss": iss,
+                "scope": scope,
+                "role": role,
+                "exp": test_util::timestamp(60),
+            }));
+            let request = Request::builder()
+                .header(AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap();
+
+            assert_eq!(
+                expected,
+                router.call(request).await.unwrap().status(),
+                "{iss} {scope} {role}"
+            );
+        }
+    }
+}
@@ -1,13 +1,27 @@
+use std::{
+    collections::HashSet,
+    future::Future,
+    sync::{Arc, OnceLock},
+};
+
 use axum::{
+    body::Bytes,
     extract::{FromRef, FromRequestParts},
     http::request::Parts,
     response::IntoResponse,
 };
+use jsonwebtoken::Header;
+use ring::digest::{digest, Digest, SHA256};
 use serde::de::DeserializeOwned;
+use serde_json::Value;
 
-use crate::{Jwks, Token, TokenError};
+use crate::{
+    replay::{token_id, DEFAULT_LEEWAY},
+    Introspection, Jwks, RawClaims, ReplayGuard, StatusListChecker, Token, TokenError,
+    TokenLocator, ValidationOptions,
+};
 
-pub struct Claims<C: DeserializeOwned + ParseTokenClaims>(pub C);
+pub struct Claims<C>(pub C);
 
 /// Trait indicating that the type can be parsed from a request.
 ///
@@ -22,6 +36,13 @@ pub struct Claims<C: DeserializeOwned + ParseTokenClaims>(pub C);
 /// }
 /// ```
 ///
+/// The trait is generic over the application state `S`, so
+/// [`validate`][Self::validate] can run checks of your own, such as looking
+/// up whether a user was disabled, with anything available through
+/// [`FromRef`]. Every extractor in this crate that is generic over the claims
+/// type enforces these checks, such as [`Claims`], [`DPoP`][crate::DPoP] or
+/// [`Enriched`][crate::Enriched].
+///
 /// The alternative to implementing this trait is to implement
 /// [`FromRequestParts`][axum::extract::FromRequestParts] directly for your
 /// token claims.
@@ -42,7 +63,7 @@ pub struct Claims<C: DeserializeOwned + ParseTokenClaims>(pub C);
 ///     sub: String,
 /// }
 ///
-/// impl ParseTokenClaims for TokenClaims {
+/// impl<S: Send + Sync> ParseTokenClaims<S> for TokenClaims {
 ///     type Rejection = TokenClaimsError;
 /// }
 ///
@@ -71,26 +92,539 @@ pub struct Claims<C: DeserializeOwned + ParseTokenClaims>(pub C);
 ///     }
 /// }
 /// ```
-pub trait ParseTokenClaims {
+///
+/// # Validating Claims
+/// ```
+/// use std::{
+///     collections::HashSet,
+///     sync::{Arc, RwLock},
+/// };
+///
+/// use axum::{
+///     extract::FromRef,
+///     http::StatusCode,
+///     response::{IntoResponse, Response},
+/// };
+/// use axum_jwks::{ParseTokenClaims, TokenError};
+/// use serde::Deserialize;
+///
+/// #[derive(Clone, Default)]
+/// struct DisabledUsers(Arc<RwLock<HashSet<String>>>);
+///
+/// #[derive(Deserialize)]
+/// struct TokenClaims {
+///     sub: String,
+/// }
+///
+/// impl<S> ParseTokenClaims<S> for TokenClaims
+/// where
+///     DisabledUsers: FromRef<S>,
+///     S: Send + Sync,
+/// {
+///     type Rejection = TokenClaimsError;
+///
+///     async fn validate(&self, state: &S) -> Result<(), Self::Rejection> {
+///         let DisabledUsers(disabled) = DisabledUsers::from_ref(state);
+///
+///         if disabled.read().unwrap().contains(&self.sub) {
+///             return Err(TokenClaimsError::Disabled);
+///         }
+///
+///         Ok(())
+///     }
+/// }
+///
+/// enum TokenClaimsError {
+///     Token(TokenError),
+///     Disabled,
+/// }
+///
+/// impl From<TokenError> for TokenClaimsError {
+///     fn from(error: TokenError) -> Self {
+///         Self::Token(error)
+///     }
+/// }
+///
+/// impl IntoResponse for TokenClaimsError {
+///     fn into_response(self) -> Response {
+///         match self {
+///             Self::Token(error) => error.into_response(),
+///             Self::Disabled => StatusCode::FORBIDDEN.into_response(),
+///         }
+///     }
+/// }
+/// ```
+pub trait ParseTokenClaims<S>: Sized {
     /// The type of error returned if the token claims cannot be parsed and
     /// validated from the request.
     type Rejection: IntoResponse + From<TokenError>;
+
+    /// Check the raw claims of a validated token before they are
+    /// deserialized.
+    ///
+    /// This is useful for checks on claims that are not part of the type,
+    /// such as `iss` or `aud`. Deserialize only the claims you check with
+    /// [`RawClaims::deserialize`]. By default, all claims are accepted.
+    fn check_claims(claims: &RawClaims) -> Result<(), Self::Rejection> {
+        let _ = claims;
+
+        Ok(())
+    }
+
+    /// Check the claims of a validated token against rules of the
+    /// application.
+    ///
+    /// This runs after the token's signature and standard claims were
+    /// validated. By default, all claims are accepted.
+    fn validate(&self, state: &S) -> impl Future<Output = Result<(), Self::Rejection>> + Send {
+        let _ = state;
+
+        async { Ok(()) }
+    }
+}
+
+/// Accept any claims without further checks.
+impl<S: Send + Sync> ParseTokenClaims<S> for Value {
+    type Rejection = TokenError;
 }
 
 impl<S, C> FromRequestParts<S> for Claims<C>
 where
-    C: DeserializeOwned + ParseTokenClaims,
+    C: DeserializeOwned + ParseTokenClaims<S> + Send,
     Jwks: FromRef<S>,
     S: Send + Sync,
 {
     type Rejection = C::Rejection;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        let jwks = Jwks::from_ref(state);
-        let token = Token::from_request_parts(parts, state).await?;
+        let verified = verified_token(parts, state).await?;
+        C::check_claims(verified.raw())?;
+        let claims: C = verified.raw().deserialize()?;
+        claims.validate(state).await?;
+        accept_token(parts, &verified)?;
+
+        Ok(Claims(claims))
+    }
+}
+
+/// A token that was validated for the current request.
+///
+/// The payload is only decoded once. Claims are parsed into a [`Value`] on
+/// demand for the checks and extractors that need them.
+#[derive(Clone)]
+pub(crate) struct VerifiedToken(Arc<VerifiedTokenInner>);
+
+struct VerifiedTokenInner {
+    header: Option<Header>,
+    raw: RawClaims,
+    leeway: u64,
+    claims: OnceLock<Arc<Value>>,
+    validated_with: ValidatedWith,
+}
+
+impl VerifiedToken {
+    fn new(
+        header: Option<Header>,
+        raw: RawClaims,
+        leeway: u64,
+        validated_with: ValidatedWith,
+    ) -> Self {
+        Self(Arc::new(VerifiedTokenInner {
+            header,
+            raw,
+            leeway,
+            claims: OnceLock::new(),
+            validated_with,
+        }))
+    }
+
+    fn from_claims(claims: Value, validated_with: ValidatedWith) -> Result<Self, TokenError> {
+        let payload =
+            serde_json::to_vec(&claims).map_err(|error| TokenError::Invalid(error.into()))?;
+        let leeway = validated_with
+            .options
+            .configured_leeway()
+            .unwrap_or(DEFAULT_LEEWAY);
+        let raw = RawClaims::new(Bytes::from(payload));
+        let verified = Self::new(None, raw, leeway, validated_with);
+        let _ = verified.0.claims.set(Arc::new(claims));
+
+        Ok(verified)
+    }
+
+    /// The token's header, or `None` for introspected tokens.
+    pub(crate) fn header(&self) -> Option<&Header> {
+        self.0.header.as_ref()
+    }
+
+    /// The token's payload.
+    pub(crate) fn raw(&self) -> &RawClaims {
+        &self.0.raw
+    }
+
+    /// The token's claims, parsed on first use.
+    pub(crate) fn claims(&self) -> Result<Arc<Value>, TokenError> {
+        if let Some(claims) = self.0.claims.get() {
+            return Ok(claims.clone());
+        }
+
+        let claims = Arc::new(self.0.raw.deserialize()?);
+
+        Ok(self.0.claims.get_or_init(|| claims).clone())
+    }
+}
+
+/// A digest of a token and the key set and options it was validated with.
+///
+/// Tokens cached in the request's extensions are only reused by extractors
+/// that read the same token and validate it with the same configuration, so
+/// a looser validation earlier in the request never satisfies a stricter
+/// one, and a token from another source is never mistaken for the one an
+/// extractor reads.
+#[derive(Clone)]
+struct ValidatedWith {
+    token: Digest,
+    jwks: Jwks,
+    options: ValidationOptions,
+}
+
+impl ValidatedWith {
+    fn new(parts: &Parts, jwks: &Jwks, token: &Token) -> Self {
+        Self {
+            token: digest(&SHA256, token.value().as_bytes()),
+            jwks: jwks.clone(),
+            options: parts
+                .extensions
+                .get::<ValidationOptions>()
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl PartialEq for ValidatedWith {
+    fn eq(&self, other: &Self) -> bool {
+        self.token.as_ref() == other.token.as_ref()
+            && self.jwks.same_keys(&other.jwks)
+            && self.options == other.options
+    }
+}
+
+/// Validate the request's token with the [`Jwks`] from the state.
+///
+/// [`ValidationOptions`] in the request's extensions override the validation
+/// configured on the key set.
+///
+/// The token is cached in the request's extensions, so any number of
+/// extractors can inspect its claims while it is only validated once.
+/// Extractors using a different key set or different options validate the
+/// token again.
+pub(crate) async fn verified_token<S>(
+    parts: &mut Parts,
+    state: &S,
+) -> Result<VerifiedToken, TokenError>
+where
+    Jwks: FromRef<S>,
+    S: Send + Sync,
+{
+    let jwks = Jwks::from_ref(state);
+    let locator = TokenLocator::from_extensions(parts);
+    let (source, token) = locator.locate_with_source(parts)?;
+    let validated_with = ValidatedWith::new(parts, &jwks, &token);
+    if let Some(cached) = parts
+        .extensions
+        .get::<VerifiedToken>()
+        .filter(|cached| cached.0.validated_with == validated_with)
+    {
+        return Ok(cached.clone());
+    }
+
+    let verified = validate_token(parts, &jwks, &token).await?;
+    if locator.checks_csrf() {
+        locator.check_csrf_claims(source, parts, &*verified.claims()?)?;
+    }
+
+    cache_token(parts, &jwks, verified).await
+}
+
+/// Validate a token, honoring [`ValidationOptions`] in the request's
+/// extensions.
+///
+/// With an [`Introspection`] in the request's extensions, tokens that are not
+/// JWTs are introspected instead.
+pub(crate) async fn validate_token(
+    parts: &Parts,
+    jwks: &Jwks,
+    token: &Token,
+) -> Result<VerifiedToken, TokenError> {
+    let validated_with = ValidatedWith::new(parts, jwks, token);
+    let options = &validated_with.options;
+    let introspection = parts.extensions.get::<Introspection>();
+    if let Some(introspection) = introspection.filter(|i| i.introspects_all()) {
+        let claims = introspection
+            .introspect_with(token.value(), options)
+            .await?;
+
+        return VerifiedToken::from_claims(claims, validated_with);
+    }
+
+    match (jwks.validate_raw(token.value(), options), introspection) {
+        (Ok((header, raw, leeway)), _) => Ok(VerifiedToken::new(
+            Some(header),
+            raw,
+            leeway,
+            validated_with,
+        )),
+        (Err(TokenError::InvalidHeader(_)), Some(introspection)) => {
+            let claims = introspection
+                .introspect_with(token.value(), options)
+                .await?;
+
+            VerifiedToken::from_claims(claims, validated_with)
+        }
+        (Err(error), _) => Err(error),
+    }
+}
+
+/// Share a validated token with every extractor for the current request.
+///
+/// This is the last step of validating a token. A [`StatusListChecker`] in
+/// the request's extensions checks whether the token was revoked. Extractors
+/// call [`accept_token`] once their own checks passed.
+pub(crate) async fn cache_token(
+    parts: &mut Parts,
+    jwks: &Jwks,
+    verified: VerifiedToken,
+) -> Result<VerifiedToken, TokenError> {
+    if let Some(checker) = parts.extensions.get::<StatusListChecker>() {
+        checker.check(jwks, &*verified.claims()?).await?;
+    }
+
+    parts.extensions.insert(verified.clone());
+
+    Ok(verified)
+}
+
+/// The token IDs a [`ReplayGuard`] recorded for the current request.
+#[derive(Clone, Default)]
+struct RecordedTokenIds(HashSet<String>);
+
+/// Accept a token that passed every check of an extractor.
+///
+/// A [`ReplayGuard`] in the request's extensions records the token's `jti`,
+/// rejecting tokens that were used before. Tokens accepted by an earlier
+/// extractor for the same request are not recorded again.
+pub(crate) fn accept_token(parts: &mut Parts, verified: &VerifiedToken) -> Result<(), TokenError> {
+    let Some(guard) = parts.extensions.get::<ReplayGuard>().cloned() else {
+        return Ok(());
+    };
+
+    let claims = verified.claims()?;
+    let token_id = token_id(&claims)?;
+    let recorded = parts.extensions.get_or_insert_default::<RecordedTokenIds>();
+    if !recorded.0.contains(&token_id) {
+        guard.record(&token_id, &claims, verified.0.leeway)?;
+        recorded.0.insert(token_id);
+    }
+
+    Ok(())
+}
+
+/// Deserialize validated claims into a specific type.
+pub(crate) fn deserialize_claims<C>(claims: &Value) -> Result<C, TokenError>
+where
+    C: DeserializeOwned,
+{
+    C::deserialize(claims).map_err(|error| TokenError::Invalid(error.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request, StatusCode},
+        response::Response,
+        routing::get,
+        Router,
+    };
+    use serde::Deserialize;
+    use serde_json::json;
+    use tower_service::Service;
+
+    use super::*;
+    use crate::{test_util, InMemoryReplayStore};
+
+    #[derive(Clone)]
+    struct State {
+        jwks: Jwks,
+        disabled: &'static str,
+    }
+
+    impl FromRef<State> for Jwks {
+        fn from_ref(state: &State) -> Self {
+            state.jwks.clone()
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct TestClaims {
+        sub: String,
+    }
+
+    impl ParseTokenClaims<State> for TestClaims {
+        type Rejection = TestRejection;
+
+        async fn validate(&self, state: &State) -> Result<(), Self::Rejection> {
+            if self.sub == state.disabled {
+                return Err(TestRejection(StatusCode::FORBIDDEN));
+            }
+
+            Ok(())
+        }
+    }
+
+    struct TestRejection(StatusCode);
+
+    impl From<TokenError> for TestRejection {
+        fn from(_: TokenError) -> Self {
+            Self(StatusCode::UNAUTHORIZED)
+        }
+    }
+
+    impl IntoResponse for TestRejection {
+        fn into_response(self) -> Response {
+            self.0.into_response()
+        }
+    }
+
+    #[tokio::test]
+    async fn claims_are_validated_with_state() {
+        let mut router = Router::new()
+            .route("/", get(|_: Claims<TestClaims>| async {}))
+            .with_state(State {
+                jwks: test_util::jwks(None),
+                disabled: "mallory",
+            });
+
+        for (sub, expected) in [
+            ("alice", StatusCode::OK),
+            ("mallory", StatusCode::FORBIDDEN),
+        ] {
+            let token = test_util::sign(&json!({ "sub": sub, "exp": test_util::timestamp(60) }));
+            let request = Request::builder()
+                .header(AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap();
+
+            assert_eq!(expected, router.call(request).await.unwrap().status());
+        }
+    }
+
+    #[tokio::test]
+    async fn claims_are_deserialized_from_payload() {
+        let token = test_util::sign(&json!({ "sub": "alice", "exp": test_util::timestamp(60) }));
+        let (mut parts, _) = Request::builder()
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .body(())
+            .unwrap()
+            .into_parts();
+        let state = State {
+            jwks: test_util::jwks(None),
+            disabled: "mallory",
+        };
+
+        let Ok(Claims(claims)) = Claims::<TestClaims>::from_request_parts(&mut parts, &state).await
+        else {
+            panic!("the token should be valid");
+        };
+
+        assert_eq!("alice", claims.sub);
+        // The claims were never parsed into a `Value`.
+        let cached = parts.extensions.get::<VerifiedToken>().unwrap();
+        assert!(cached.0.claims.get().is_none());
+    }
+
+    #[tokio::test]
+    async fn rejected_tokens_are_not_used_up() {
+        let guard = ReplayGuard::new(InMemoryReplayStore::new());
+        let router = |disabled| {
+            Router::new()
+                .route("/", get(|_: Claims<TestClaims>| async {}))
+                .layer(axum::Extension(guard.clone()))
+                .with_state(State {
+                    jwks: test_util::jwks(None),
+                    disabled,
+                })
+        };
+        let token = test_util::sign(&json!({
+            "sub": "mallory",
+            "jti": "1",
+            "exp": test_util::timestamp(60),
+        }));
+        let request = || {
+            Request::builder()
+                .header(AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // The token ID is only recorded once `validate` accepted the claims.
+        let mut rejecting = router("mallory");
+        assert_eq!(
+            StatusCode::FORBIDDEN,
+            rejecting.call(request()).await.unwrap().status()
+        );
+        let mut accepting = router("nobody");
+        assert_eq!(
+            StatusCode::OK,
+            accepting.call(request()).await.unwrap().status()
+        );
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            accepting.call(request()).await.unwrap().status()
+        );
+    }
+
+    async fn accept(parts: &mut Parts, jwks: &Jwks) -> Result<(), TokenError> {
+        let verified = verified_token(parts, jwks).await?;
+
+        accept_token(parts, &verified)
+    }
+
+    #[tokio::test]
+    async fn cached_claims_are_scoped_to_validation() {
+        let token = test_util::sign(&json!({
+            "aud": "other-api",
+            "jti": "1",
+            "exp": test_util::timestamp(60),
+        }));
+        let (mut parts, _) = Request::builder()
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .body(())
+            .unwrap()
+            .into_parts();
+        parts
+            .extensions
+            .insert(ReplayGuard::new(InMemoryReplayStore::new()));
+        let loose = test_util::jwks(None);
+        let strict = test_util::jwks(Some("my-api"));
 
-        let token_data = jwks.validate_claims(token.value())?;
+        // The token is only recorded once, so reusing the cached claims and
+        // validating again with another key set do not count as replays.
+        assert_eq!(Ok(()), accept(&mut parts, &loose).await);
+        assert_eq!(Ok(()), accept(&mut parts, &loose.clone()).await);
+        assert_eq!(Ok(()), accept(&mut parts, &test_util::jwks(None)).await);
 
-        Ok(Claims(token_data.claims))
+        assert!(matches!(
+            accept(&mut parts, &strict).await,
+            Err(TokenError::Invalid(_))
+        ));
+        parts
+            .extensions
+            .insert(ValidationOptions::default().audience(["my-api"]));
+        assert!(matches!(
+            accept(&mut parts, &loose).await,
+            Err(TokenError::Invalid(_))
+        ));
     }
 }
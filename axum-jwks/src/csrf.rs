@@ -0,0 +1,367 @@
+use axum::http::{header::ORIGIN, request::Parts, HeaderName, Method};
+use serde_json::Value;
+use tracing::debug;
+
+use crate::{locator::cookie_values, TokenError};
+
+/// Where the expected CSRF token of a request comes from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Expected {
+    /// A cookie that is sent along with the token cookie.
+    Cookie(String),
+
+    /// A claim of the token itself.
+    Claim(String),
+}
+
+/// Cross-site request forgery protection for tokens read from cookies.
+///
+/// Browsers attach cookies to cross-site requests, so a token read from a
+/// cookie does not prove that the request was made by the application. For
+/// requests with an unsafe method (`POST`, `PUT`, `PATCH` and `DELETE`), this
+/// requires a header with a CSRF token that a cross-site attacker cannot know.
+/// The expected value is either read from a second cookie (the double-submit
+/// pattern) or from a claim of the token.
+///
+/// Tokens from other sources are not affected. Enable the protection with
+/// [`TokenLocator::csrf`][crate::TokenLocator::csrf].
+///
+/// # Example
+/// ```
+/// use axum_jwks::{CsrfProtection, TokenLocator};
+///
+/// let locator = TokenLocator::new()
+///     .bearer()
+///     .cookie("access_token")
+///     .csrf(
+///         CsrfProtection::double_submit("csrf_token")
+///             .allowed_origin("https://app.example.com")
+///             .require_same_origin_fetch(),
+///     );
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct CsrfProtection {
+    header: HeaderName,
+    expected: Expected,
+    allowed_origins: Vec<String>,
+    same_origin_fetch: bool,
+}
+
+impl CsrfProtection {
+    /// Require the `X-CSRF-Token` header to match the value of a cookie.
+    pub fn double_submit(cookie: impl Into<String>) -> Self {
+        Self::new(Expected::Cookie(cookie.into()))
+    }
+
+    /// Require the `X-CSRF-Token` header to match a claim of the token.
+    ///
+    /// The claim can be a top-level name or a JSON pointer. Because the claim
+    /// is only trusted once the token is validated, this is checked by the
+    /// extractors that validate tokens, such as [`Claims`][crate::Claims],
+    /// but not by [`Token`][crate::Token].
+    pub fn claim(claim: impl Into<String>) -> Self {
+        Self::new(Expected::Claim(claim.into()))
+    }
+
+    fn new(expected: Expected) -> Self {
+        Self {
+            header: HeaderName::from_static("x-csrf-token"),
+            expected,
+            allowed_origins: Vec::new(),
+            same_origin_fetch: false,
+        }
+    }
+
+    /// Read the CSRF token from the given header instead of `X-CSRF-Token`.
+    pub fn header(mut self, name: HeaderName) -> Self {
+        self.header = name;
+        self
+    }
+
+    /// Reject requests with an `Origin` header that is not one of the allowed
+    /// origins.
+    ///
+    /// Calling this multiple times allows multiple origins. Requests without
+    /// an `Origin` header are not affected.
+    pub fn allowed_origin(mut self, origin: impl Into<String>) -> Self {
+        self.allowed_origins.push(origin.into());
+        self
+    }
+
+    /// Reject requests whose `Sec-Fetch-Site` header indicates that they were
+    /// made by another site.
+    ///
+    /// Requests without the header are not affected.
+    pub fn require_same_origin_fetch(mut self) -> Self {
+        self.same_origin_fetch = true;
+        self
+    }
+
+    /// Check everything that does not depend on the validated claims.
+    pub(crate) fn check_request(&self, parts: &Parts) -> Result<(), TokenError> {
+        if !is_unsafe(&parts.method) {
+            return Ok(());
+        }
+
+        if !self.allowed_origins.is_empty() {
+            if let Some(origin) = parts.headers.get(ORIGIN) {
+                let allowed = self
+                    .allowed_origins
+                    .iter()
+                    .any(|allowed| origin.as_bytes() == allowed.as_bytes());
+
+                if !allowed {
+                    debug!(?origin, "Request comes from an origin that is not allowed.");
+
+                    return Err(TokenError::CsrfCheckFailed);
+                }
+            }
+        }
+
+        if self.same_origin_fetch {
+            let site = parts
+                .headers
+                .get(HeaderName::from_static("sec-fetch-site"))
+                .map(|site| site.as_bytes());
+
+            if let Some(site) = site {
+                if site != b"same-origin" && site != b"none" {
+                    debug!("Request was made by another site.");
+
+                    return Err(TokenError::CsrfCheckFailed);
+                }
+            }
+        }
+
+        if let Expected::Cookie(cookie) = &self.expected {
+            let expected = cookie_values(parts, cookie).into_iter().next();
+
+            self.compare(parts, expected.as_deref())?;
+        }
+
+        Ok(())
+    }
+
+    /// Check the CSRF token against the validated claims.
+    pub(crate) fn check_claims(&self, parts: &Parts, claims: &Value) -> Result<(), TokenError> {
+        let Expected::Claim(claim) = &self.expected else {
+            return Ok(());
+        };
+
+        if !is_unsafe(&parts.method) {
+            return Ok(());
+        }
+
+        let expected = if claim.starts_with('/') {
+            claims.pointer(claim)
+        } else {
+            claims.get(claim)
+        };
+
+        self.compare(parts, expected.and_then(Value::as_str))
+    }
+
+    fn compare(&self, parts: &Parts, expected: Option<&str>) -> Result<(), TokenError> {
+        let provided = parts
+            .headers
+            .get(&self.header)
+            .map(|value| value.as_bytes());
+
+        match (provided, expected) {
+            (Some(provided), Some(expected))
+                if !expected.is_empty() && constant_time_eq(provided, expected.as_bytes()) =>
+            {
+                Ok(())
+            }
+            _ => {
+                debug!(header = %self.header, "CSRF token is missing or does not match.");
+
+                Err(TokenError::CsrfCheckFailed)
+            }
+        }
+    }
+}
+
+fn is_unsafe(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    )
+}
+
+/// Compare two values without leaking the position of the first difference
+/// through timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::COOKIE, Request, StatusCode},
+        routing::post,
+        Extension, Router,
+    };
+    use serde_json::json;
+    use tower_service::Service;
+
+    use super::*;
+    use crate::{test_util, Claims, ParseTokenClaims, TokenLocator};
+
+    #[derive(serde::Deserialize)]
+    struct TestClaims {}
+
+    impl<S: Send + Sync> ParseTokenClaims<S> for TestClaims {
+        type Rejection = TokenError;
+    }
+
+    fn token() -> String {
+        test_util::sign(&json!({
+            "csrf": "secret",
+            "exp": test_util::timestamp(60),
+        }))
+    }
+
+    fn parts(method: Method, headers: &[(&str, String)]) -> Parts {
+        let mut request = Request::builder().method(method);
+        for (name, value) in headers {
+            request = request.header(*name, value);
+        }
+
+        request.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn double_submit() {
+        let csrf = CsrfProtection::double_submit("csrf_token");
+        let cookie = (COOKIE.as_str(), "csrf_token=secret".to_owned());
+
+        let cases = [
+            (Method::GET, vec![], Ok(())),
+            (
+                Method::POST,
+                vec![cookie.clone(), ("x-csrf-token", "secret".to_owned())],
+                Ok(()),
+            ),
+            (
+                Method::DELETE,
+                vec![cookie.clone(), ("x-csrf-token", "other".to_owned())],
+                Err(TokenError::CsrfCheckFailed),
+            ),
+            (Method::PUT, vec![cookie], Err(TokenError::CsrfCheckFailed)),
+            (
+                Method::PATCH,
+                vec![("x-csrf-token", "secret".to_owned())],
+                Err(TokenError::CsrfCheckFailed),
+            ),
+        ];
+
+        for (method, headers, expected) in cases {
+            assert_eq!(
+                expected,
+                csrf.check_request(&parts(method.clone(), &headers)),
+                "{method} {headers:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn custom_header() {
+        let csrf = CsrfProtection::double_submit("csrf_token")
+            .header(HeaderName::from_static("x-xsrf-token"));
+        let cookie = (COOKIE.as_str(), "csrf_token=secret".to_owned());
+
+        assert_eq!(
+            Ok(()),
+            csrf.check_request(&parts(
+                Method::POST,
+                &[cookie.clone(), ("x-xsrf-token", "secret".to_owned())]
+            ))
+        );
+        assert_eq!(
+            Err(TokenError::CsrfCheckFailed),
+            csrf.check_request(&parts(
+                Method::POST,
+                &[cookie, ("x-csrf-token", "secret".to_owned())]
+            ))
+        );
+    }
+
+    #[test]
+    fn origin_and_fetch_site() {
+        let csrf = CsrfProtection::double_submit("csrf_token")
+            .allowed_origin("https://app.example.com")
+            .require_same_origin_fetch();
+        let submitted = [
+            (COOKIE.as_str(), "csrf_token=secret".to_owned()),
+            ("x-csrf-token", "secret".to_owned()),
+        ];
+
+        let with = |extra: (&'static str, &str)| {
+            let mut headers = submitted.to_vec();
+            headers.push((extra.0, extra.1.to_owned()));
+            parts(Method::POST, &headers)
+        };
+
+        assert!(csrf.check_request(&parts(Method::POST, &submitted)).is_ok());
+        assert!(csrf
+            .check_request(&with(("origin", "https://app.example.com")))
+            .is_ok());
+        assert!(csrf
+            .check_request(&with(("origin", "https://evil.example")))
+            .is_err());
+        assert!(csrf
+            .check_request(&with(("sec-fetch-site", "same-origin")))
+            .is_ok());
+        assert!(csrf
+            .check_request(&with(("sec-fetch-site", "cross-site")))
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn claims_extractor_checks_csrf_claim() {
+        let locator = TokenLocator::new()
+            .bearer()
+            .cookie("access_token")
+            .csrf(CsrfProtection::claim("csrf"));
+        let mut router = Router::new()
+            .route("/", post(|_: Claims<TestClaims>| async {}))
+            .layer(Extension(locator))
+            .with_state(test_util::jwks(None));
+
+        let cases = [
+            (
+                format!("access_token={}", token()),
+                "secret",
+                StatusCode::OK,
+            ),
+            (
+                format!("access_token={}", token()),
+                "other",
+                StatusCode::FORBIDDEN,
+            ),
+        ];
+
+        for (cookie, csrf, expected) in cases {
+            let request = Request::builder()
+                .method(Method::POST)
+                .header(COOKIE, cookie)
+                .header("x-csrf-token", csrf)
+                .body(Body::empty())
+                .unwrap();
+
+            assert_eq!(expected, router.call(request).await.unwrap().status());
+        }
+
+        // Bearer tokens are not sent automatically by browsers, so they don't
+        // need a CSRF token.
+        let request = Request::builder()
+            .method(Method::POST)
+            .header("authorization", format!("Bearer {}", token()))
+            .body(Body::empty())
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, router.call(request).await.unwrap().status());
+    }
+}
@@ -0,0 +1,697 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::{
+        header::{AUTHORIZATION, HOST, WWW_AUTHENTICATE},
+        request::Parts,
+        HeaderName, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{AlgorithmParameters, Jwk},
+    Algorithm, DecodingKey, Validation,
+};
+use ring::{
+    digest::{digest, SHA256},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use thiserror::Error;
+use tracing::debug;
+
+use crate::{
+    claims::{accept_token, cache_token, validate_token},
+    InMemoryReplayStore, Jwks, ParseTokenClaims, ReplayStore, Token, TokenError,
+};
+
+/// The header carrying DPoP proofs.
+const DPOP: HeaderName = HeaderName::from_static("dpop");
+/// The header carrying server-issued nonces.
+const DPOP_NONCE: HeaderName = HeaderName::from_static("dpop-nonce");
+
+/// How far in the future a proof's `iat` may be to allow for clock skew.
+const CLOCK_SKEW: Duration = Duration::from_secs(5);
+
+/// Verifies DPoP proofs as defined in [RFC 9449].
+///
+/// Make this available from the application state with
+/// [`FromRef`][axum::extract::FromRef] to use the [`DPoP`] extractor.
+///
+/// The `htu` claim of a proof must match the URL the client used. Behind a
+/// reverse proxy, configure the externally visible origin with
+/// [`public_origin`][Self::public_origin]. Otherwise the URL is built from the
+/// request's `Host` header with the `https` scheme.
+///
+/// [RFC 9449]: https://www.rfc-editor.org/rfc/rfc9449
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+///
+/// use axum_jwks::DPoPVerifier;
+///
+/// let verifier = DPoPVerifier::new()
+///     .public_origin("https://api.example.com")
+///     .max_age(Duration::from_secs(30))
+///     .require_nonce(Duration::from_secs(300));
+/// ```
+#[derive(Clone)]
+pub struct DPoPVerifier {
+    replay: Arc<dyn ReplayStore>,
+    nonces: Option<DPoPNonces>,
+    origin: Option<String>,
+    max_age: Duration,
+}
+
+impl DPoPVerifier {
+    /// A verifier that remembers proof IDs in memory and accepts proofs for
+    /// up to a minute.
+    pub fn new() -> Self {
+        Self {
+            replay: Arc::new(InMemoryReplayStore::new()),
+            nonces: None,
+            origin: None,
+            max_age: Duration::from_secs(60),
+        }
+    }
+
+    /// Remember proof IDs in the given store, e.g. to share them between
+    /// instances.
+    pub fn replay_store(mut self, store: impl ReplayStore + 'static) -> Self {
+        self.replay = Arc::new(store);
+        self
+    }
+
+    /// The scheme and authority clients use to reach the server, e.g.
+    /// `https://api.example.com`.
+    pub fn public_origin(mut self, origin: impl Into<String>) -> Self {
+        self.origin = Some(origin.into().trim_end_matches('/').to_owned());
+        self
+    }
+
+    /// How long after its `iat` a proof is accepted.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Require proofs to contain a nonce issued by the server.
+    ///
+    /// Requests without a current nonce are rejected with a `DPoP-Nonce`
+    /// header containing one. Nonces are replaced after the given interval,
+    /// and the previous nonce stays valid for another interval.
+    pub fn require_nonce(mut self, rotation: Duration) -> Self {
+        self.nonces = Some(DPoPNonces::new(rotation));
+        self
+    }
+
+    /// Verify the request's DPoP proof for an access token.
+    ///
+    /// The access token itself is not validated. On success, the
+    /// [thumbprint](jwk_thumbprint) of the proof's key is returned, which
+    /// must match the `cnf.jkt` claim of the access token.
+    pub fn verify(&self, parts: &Parts, access_token: &str) -> Result<String, DPoPRejection> {
+        let mut proofs = parts.headers.get_all(DPOP).iter();
+        let proof = match (proofs.next(), proofs.next()) {
+            (Some(proof), None) => proof.to_str().map_err(|_| invalid("malformed proof"))?,
+            (None, _) => return Err(invalid("missing proof")),
+            (Some(_), Some(_)) => return Err(invalid("more than one proof")),
+        };
+
+        let header = decode_header(proof).map_err(|_| invalid("malformed header"))?;
+        if header.typ.as_deref() != Some("dpop+jwt") {
+            return Err(invalid("wrong type"));
+        }
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(invalid("symmetric algorithm"));
+        }
+        if contains_private_key(proof) {
+            return Err(invalid("private key in header"));
+        }
+        let jwk = header.jwk.ok_or_else(|| invalid("missing key"))?;
+        let thumbprint = jwk_thumbprint(&jwk).ok_or_else(|| invalid("unsupported key"))?;
+        let key = DecodingKey::from_jwk(&jwk).map_err(|_| invalid("unsupported key"))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.validate_exp = false;
+        validation.validate_aud = false;
+        validation.required_spec_claims.clear();
+        let claims = decode::<ProofClaims>(proof, &key, &validation)
+            .map_err(|error| {
+                debug!(?error, "DPoP proof does not pass validation.");
+
+                invalid("invalid signature or claims")
+            })?
+            .claims;
+
+        if claims.jti.is_empty() {
+            return Err(invalid("missing jti"));
+        }
+        if claims.htm != parts.method.as_str() {
+            return Err(invalid("method mismatch"));
+        }
+        let url = self.request_url(parts);
+        if url.is_none() || normalize_url(&claims.htu) != url {
+            return Err(invalid("URL mismatch"));
+        }
+
+        let issued_at = SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(claims.iat));
+        let expires_at = issued_at.and_then(|issued_at| issued_at.checked_add(self.max_age));
+        let (Some(issued_at), Some(expires_at)) = (issued_at, expires_at) else {
+            return Err(invalid("expired or not yet valid"));
+        };
+        let now = SystemTime::now();
+        if issued_at > now + CLOCK_SKEW || expires_at < now {
+            return Err(invalid("expired or not yet valid"));
+        }
+
+        let expected_ath = URL_SAFE_NO_PAD.encode(digest(&SHA256, access_token.as_bytes()));
+        if claims.ath.as_deref() != Some(expected_ath.as_str()) {
+            return Err(invalid("access token hash mismatch"));
+        }
+
+        if let Some(nonces) = &self.nonces {
+            if !claims.nonce.is_some_and(|nonce| nonces.is_valid(&nonce)) {
+                return Err(DPoPRejection::UseNonce(nonces.current()));
+            }
+        }
+
+        let replay_key = format!("{thumbprint}:{}", claims.jti);
+        if !self.replay.insert(&replay_key, expires_at) {
+            debug!(jti = %claims.jti, "DPoP proof was replayed.");
+
+            return Err(invalid("replayed proof"));
+        }
+
+        Ok(thumbprint)
+    }
+
+    /// The URL of the request without query and fragment.
+    fn request_url(&self, parts: &Parts) -> Option<String> {
+        let origin = match &self.origin {
+            Some(origin) => origin.clone(),
+            None => {
+                let host = parts.uri.authority().map(|authority| authority.as_str());
+                let host =
+                    host.or_else(|| parts.headers.get(HOST).and_then(|h| h.to_str().ok()))?;
+
+                format!("https://{host}")
+            }
+        };
+
+        normalize_url(&format!("{origin}{}", parts.uri.path()))
+    }
+}
+
+impl Default for DPoPVerifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Lowercase the scheme and host of a URL and remove default ports, query and
+/// fragment.
+fn normalize_url(url: &str) -> Option<String> {
+    let url = url.split(['?', '#']).next()?;
+    let (scheme, rest) = url.split_once("://")?;
+    let scheme = scheme.to_ascii_lowercase();
+    let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let mut authority = authority.to_ascii_lowercase();
+
+    let default_port = match scheme.as_str() {
+        "https" => ":443",
+        "http" => ":80",
+        _ => "",
+    };
+    if !default_port.is_empty() && authority.ends_with(default_port) {
+        authority.truncate(authority.len() - default_port.len());
+    }
+
+    let path = if path.is_empty() { "/" } else { path };
+
+    Some(format!("{scheme}://{authority}{path}"))
+}
+
+/// Check whether the `jwk` in a token's header contains private key material.
+fn contains_private_key(token: &str) -> bool {
+    let header = token
+        .split('.')
+        .next()
+        .and_then(|header| URL_SAFE_NO_PAD.decode(header).ok())
+        .and_then(|header| serde_json::from_slice::<Value>(&header).ok());
+
+    header
+        .as_ref()
+        .and_then(|header| header.get("jwk"))
+        .is_some_and(|jwk| jwk.get("d").is_some())
+}
+
+fn invalid(reason: &'static str) -> DPoPRejection {
+    debug!(%reason, "Rejecting DPoP proof.");
+
+    DPoPRejection::InvalidProof(reason)
+}
+
+/// The [RFC 7638] thumbprint of a public key, as used in the `cnf.jkt` claim
+/// of DPoP-bound access tokens.
+///
+/// Returns `None` for symmetric keys.
+///
+/// [RFC 7638]: https://www.rfc-editor.org/rfc/rfc7638
+pub fn jwk_thumbprint(jwk: &Jwk) -> Option<String> {
+    // The members are serialized in lexicographic order without whitespace.
+    let members = match &jwk.algorithm {
+        AlgorithmParameters::RSA(rsa) => json!({ "e": rsa.e, "kty": "RSA", "n": rsa.n }),
+        AlgorithmParameters::EllipticCurve(ec) => {
+            json!({ "crv": ec.curve, "kty": "EC", "x": ec.x, "y": ec.y })
+        }
+        AlgorithmParameters::OctetKeyPair(okp) => {
+            json!({ "crv": okp.curve, "kty": "OKP", "x": okp.x })
+        }
+        AlgorithmParameters::OctetKey(_) => return None,
+    };
+    let members = serde_json::to_string(&members).ok()?;
+
+    Some(URL_SAFE_NO_PAD.encode(digest(&SHA256, members.as_bytes())))
+}
+
+#[derive(Deserialize)]
+struct ProofClaims {
+    jti: String,
+    htm: String,
+    htu: String,
+    iat: u64,
+    ath: Option<String>,
+    nonce: Option<String>,
+}
+
+/// Server-issued nonces that are replaced periodically.
+#[derive(Clone)]
+struct DPoPNonces {
+    rotation: Duration,
+    state: Arc<Mutex<NonceState>>,
+}
+
+struct NonceState {
+    current: String,
+    previous: Option<String>,
+    rotated_at: SystemTime,
+}
+
+impl DPoPNonces {
+    fn new(rotation: Duration) -> Self {
+        Self {
+            rotation,
+            state: Arc::new(Mutex::new(NonceState {
+                current: random_nonce(),
+                previous: None,
+                rotated_at: SystemTime::now(),
+            })),
+        }
+    }
+
+    fn rotate(&self) -> std::sync::MutexGuard<'_, NonceState> {
+        let mut state = self.state.lock().unwrap_or_else(|error| error.into_inner());
+        let now = SystemTime::now();
+        // Comparing the elapsed time instead of adding the rotation to the
+        // last rotation can't overflow for long rotation intervals.
+        let elapsed = now.duration_since(state.rotated_at).unwrap_or_default();
+
+        if elapsed >= self.rotation {
+            let expired = elapsed >= self.rotation.saturating_mul(2);
+            let previous = std::mem::replace(&mut state.current, random_nonce());
+            state.previous = (!expired).then_some(previous);
+            state.rotated_at = now;
+        }
+
+        state
+    }
+
+    fn current(&self) -> String {
+        self.rotate().current.clone()
+    }
+
+    fn is_valid(&self, nonce: &str) -> bool {
+        let state = self.rotate();
+
+        state.current == nonce || state.previous.as_deref() == Some(nonce)
+    }
+}
+
+fn random_nonce() -> String {
+    let mut bytes = [0; 16];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("system random number generator failed");
+
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// The claims of a DPoP-bound access token.
+///
+/// The access token is read from an `Authorization: DPoP` header and validated
+/// with the [`Jwks`] from the application state. The request's DPoP proof is
+/// verified with the [`DPoPVerifier`] from the application state, and the
+/// token's `cnf.jkt` claim must match the proof's key. The claims are then
+/// checked with [`ParseTokenClaims`] like [`Claims`][crate::Claims] does.
+#[derive(Debug)]
+pub struct DPoP<C = Value> {
+    pub claims: C,
+
+    /// The thumbprint of the key the token is bound to.
+    pub thumbprint: String,
+}
+
+impl<S, C> FromRequestParts<S> for DPoP<C>
+where
+    C: DeserializeOwned + ParseTokenClaims<S> + Send,
+    Jwks: FromRef<S>,
+    DPoPVerifier: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = DPoPRejection<C::Rejection>;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("dpop"))
+            .map(|(_, token)| Token::new(token.trim().to_owned()))
+            .ok_or(TokenError::Missing)?;
+
+        let jwks = Jwks::from_ref(state);
+        let verified = validate_token(parts, &jwks, &token).await?;
+        let thumbprint = DPoPVerifier::from_ref(state)
+            .verify(parts, token.value())
+            .map_err(DPoPRejection::for_claims)?;
+
+        let claims = verified.claims()?;
+        let bound_to = claims.pointer("/cnf/jkt").and_then(Value::as_str);
+        if bound_to != Some(thumbprint.as_str()) {
+            debug!(?bound_to, %thumbprint, "Access token is not bound to the proof key.");
+
+            return Err(DPoPRejection::KeyMismatch);
+        }
+
+        let verified = cache_token(parts, &jwks, verified).await?;
+        C::check_claims(verified.raw()).map_err(DPoPRejection::Claims)?;
+        let claims: C = verified.raw().deserialize()?;
+        claims
+            .validate(state)
+            .await
+            .map_err(DPoPRejection::Claims)?;
+        accept_token(parts, &verified)?;
+
+        Ok(Self { claims, thumbprint })
+    }
+}
+
+/// The rejection for [`DPoP`].
+///
+/// `R` is the rejection of the claims type's [`ParseTokenClaims`]
+/// implementation.
+#[derive(Debug, Error, PartialEq)]
+pub enum DPoPRejection<R = TokenError> {
+    #[error(transparent)]
+    Token(#[from] TokenError),
+
+    /// The claims were rejected by their [`ParseTokenClaims`] implementation.
+    #[error("the token's claims were rejected")]
+    Claims(R),
+
+    /// The DPoP proof is missing or invalid.
+    #[error("invalid DPoP proof: {0}")]
+    InvalidProof(&'static str),
+
+    /// The proof does not contain a current nonce. The new nonce is sent to
+    /// the client.
+    #[error("the DPoP proof must contain a server-issued nonce")]
+    UseNonce(String),
+
+    /// The access token is not bound to the key of the proof.
+    #[error("the access token is not bound to the DPoP proof key")]
+    KeyMismatch,
+}
+
+impl DPoPRejection {
+    /// Convert a rejection from verifying a proof for any claims type.
+    fn for_claims<R>(self) -> DPoPRejection<R> {
+        match self {
+            Self::Token(error) | Self::Claims(error) => DPoPRejection::Token(error),
+            Self::InvalidProof(reason) => DPoPRejection::InvalidProof(reason),
+            Self::UseNonce(nonce) => DPoPRejection::UseNonce(nonce),
+            Self::KeyMismatch => DPoPRejection::KeyMismatch,
+        }
+    }
+}
+
+impl<R: IntoResponse> IntoResponse for DPoPRejection<R> {
+    fn into_response(self) -> Response {
+        let (error, nonce) = match self {
+            Self::Claims(rejection) => return rejection.into_response(),
+            Self::Token(TokenError::Missing) => {
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    [(WWW_AUTHENTICATE, HeaderValue::from_static("DPoP"))],
+                )
+                    .into_response()
+            }
+            Self::Token(_) | Self::KeyMismatch => ("invalid_token", None),
+            Self::InvalidProof(_) => ("invalid_dpop_proof", None),
+            Self::UseNonce(nonce) => ("use_dpop_nonce", Some(nonce)),
+        };
+        let challenge = HeaderValue::from_str(&format!("DPoP error=\"{error}\""))
+            .expect("challenge is a valid header value");
+        let mut response =
+            (StatusCode::UNAUTHORIZED, [(WWW_AUTHENTICATE, challenge)]).into_response();
+
+        if let Some(Ok(nonce)) = nonce.map(|nonce| HeaderValue::from_str(&nonce)) {
+            response.headers_mut().insert(DPOP_NONCE, nonce);
+        }
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, Request};
+    use jsonwebtoken::Header;
+
+    use super::*;
+    use crate::test_util;
+
+    fn proof_key() -> Jwk {
+        let mut jwk = test_util::jwk_set().keys[1].clone();
+        jwk.common.key_id = None;
+        jwk
+    }
+
+    fn access_token() -> String {
+        let thumbprint = jwk_thumbprint(&proof_key()).unwrap();
+
+        test_util::sign(&json!({
+            "sub": "user",
+            "cnf": { "jkt": thumbprint },
+            "exp": test_util::timestamp(60),
+        }))
+    }
+
+    fn proof(claims: Value) -> String {
+        let mut header = Header::new(Algorithm::ES256);
+        header.typ = Some("dpop+jwt".to_owned());
+        header.jwk = Some(proof_key());
+
+        test_util::sign_with_header(&header, &claims)
+    }
+
+    fn proof_claims(token: &str, jti: &str) -> Value {
+        json!({
+            "jti": jti,
+            "htm": "POST",
+            "htu": "https://API.example.com:443/orders?page=2",
+            "iat": test_util::timestamp(0),
+            "ath": URL_SAFE_NO_PAD.encode(digest(&SHA256, token.as_bytes())),
+        })
+    }
+
+    fn parts(token: &str, proof: &str) -> Parts {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/orders")
+            .header(HOST, "api.example.com")
+            .header(AUTHORIZATION, format!("DPoP {token}"))
+            .header(DPOP, proof)
+            .body(())
+            .unwrap();
+
+        request.into_parts().0
+    }
+
+    #[derive(Clone)]
+    struct State {
+        jwks: Jwks,
+        verifier: DPoPVerifier,
+    }
+
+    impl FromRef<State> for Jwks {
+        fn from_ref(state: &State) -> Self {
+            state.jwks.clone()
+        }
+    }
+
+    impl FromRef<State> for DPoPVerifier {
+        fn from_ref(state: &State) -> Self {
+            state.verifier.clone()
+        }
+    }
+
+    fn state(verifier: DPoPVerifier) -> State {
+        State {
+            jwks: test_util::jwks(None),
+            verifier,
+        }
+    }
+
+    #[test]
+    fn rfc_7638_thumbprint() {
+        // The example from RFC 7638, section 3.1.
+        let jwk: Jwk = serde_json::from_value(json!({
+            "kty": "RSA",
+            "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+            "e": "AQAB",
+            "alg": "RS256",
+            "kid": "2011-04-29",
+        }))
+        .unwrap();
+
+        assert_eq!(
+            Some("NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs".to_owned()),
+            jwk_thumbprint(&jwk)
+        );
+    }
+
+    #[tokio::test]
+    async fn accept_valid_proof_once() {
+        let state = state(DPoPVerifier::new());
+        let token = access_token();
+        let proof = proof(proof_claims(&token, "1"));
+
+        let dpop = DPoP::<Value>::from_request_parts(&mut parts(&token, &proof), &state)
+            .await
+            .unwrap();
+
+        assert_eq!(jwk_thumbprint(&proof_key()).unwrap(), dpop.thumbprint);
+        assert_eq!("user", dpop.claims["sub"]);
+
+        let replayed = DPoP::<Value>::from_request_parts(&mut parts(&token, &proof), &state).await;
+
+        assert_eq!(
+            DPoPRejection::InvalidProof("replayed proof"),
+            replayed.unwrap_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn reject_invalid_proofs() {
+        let state = state(DPoPVerifier::new());
+        let token = access_token();
+
+        let cases = [
+            ("htm", json!("GET"), "method mismatch"),
+            (
+                "htu",
+                json!("https://api.example.com/other"),
+                "URL mismatch",
+            ),
+            (
+                "iat",
+                json!(test_util::timestamp(-120)),
+                "expired or not yet valid",
+            ),
+            ("iat", json!(u64::MAX), "expired or not yet valid"),
+            (
+                "iat",
+                json!(i64::MAX as u64 - 1),
+                "expired or not yet valid",
+            ),
+            ("ath", json!("wrong"), "access token hash mismatch"),
+        ];
+
+        for (index, (claim, value, reason)) in cases.into_iter().enumerate() {
+            let mut claims = proof_claims(&token, &index.to_string());
+            claims[claim] = value;
+
+            let result =
+                DPoP::<Value>::from_request_parts(&mut parts(&token, &proof(claims)), &state).await;
+
+            assert_eq!(DPoPRejection::InvalidProof(reason), result.unwrap_err());
+        }
+    }
+
+    #[test]
+    fn nonces_with_long_rotation() {
+        let nonces = DPoPNonces::new(Duration::MAX);
+        let nonce = nonces.current();
+
+        assert!(nonces.is_valid(&nonce));
+        assert_eq!(nonce, nonces.current());
+        assert!(!nonces.is_valid("other"));
+    }
+
+    #[tokio::test]
+    async fn reject_unbound_access_token() {
+        let state = state(DPoPVerifier::new());
+        let token = test_util::sign(&json!({ "exp": test_util::timestamp(60) }));
+        let proof = proof(proof_claims(&token, "1"));
+
+        let result = DPoP::<Value>::from_request_parts(&mut parts(&token, &proof), &state).await;
+
+        assert_eq!(DPoPRejection::KeyMismatch, result.unwrap_err());
+    }
+
+    #[tokio::test]
+    async fn nonce_challenge() {
+        let state = state(DPoPVerifier::new().require_nonce(Duration::from_secs(60)));
+        let token = access_token();
+        let proof_without_nonce = proof(proof_claims(&token, "1"));
+
+        let rejection =
+            DPoP::<Value>::from_request_parts(&mut parts(&token, &proof_without_nonce), &state)
+                .await
+                .unwrap_err();
+        let nonce = match &rejection {
+            DPoPRejection::UseNonce(nonce) => nonce.clone(),
+            other => panic!("expected a nonce challenge, got {other:?}"),
+        };
+
+        let response = rejection.into_response();
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+        assert_eq!(nonce, response.headers()[DPOP_NONCE]);
+        assert_eq!(
+            "DPoP error=\"use_dpop_nonce\"",
+            response.headers()[WWW_AUTHENTICATE]
+        );
+
+        let mut claims = proof_claims(&token, "2");
+        claims["nonce"] = json!(nonce);
+
+        let result =
+            DPoP::<Value>::from_request_parts(&mut parts(&token, &proof(claims)), &state).await;
+
+        assert!(result.is_ok());
+    }
+}
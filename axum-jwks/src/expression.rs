@@ -0,0 +1,922 @@
+use std::{fmt, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer};
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::{
+    policy::{Denial, Policy, PolicyRequest},
+    principal::string_list,
+};
+
+/// How deeply expressions may nest, counting parentheses, arrays, `!` and
+/// chained operators. This keeps compiling and evaluating an expression from
+/// overflowing the stack.
+const MAX_DEPTH: usize = 128;
+
+/// A claim requirement written as a boolean expression.
+///
+/// Expressions are compiled once, typically while loading configuration, and
+/// can then be evaluated against the claims of any validated token. An
+/// expression is also a [`Policy`], so it can be enforced with a
+/// [`PolicyLayer`][crate::PolicyLayer] or combined with other policies.
+///
+/// # Syntax
+/// * Claims are referenced by name (`iss`), with `.` for nested claims
+///   (`realm_access.roles`) and `["..."]` for names that are not
+///   identifiers (`claims["kubernetes.io"].namespace`). A missing claim is
+///   `null`.
+/// * Literals are strings (`"admin"`), numbers, `true`, `false`, `null` and
+///   arrays (`["a", "b"]`).
+/// * `==`, `!=`, `<`, `<=`, `>` and `>=` compare values; ordering only
+///   applies to numbers and strings.
+/// * `x in y` and `y contains x` check whether the array or space delimited
+///   string `y` contains `x`.
+/// * `+` and `-` work on numbers.
+/// * `&&`, `||`, `!` and parentheses combine conditions.
+///
+/// Expressions nested more than 128 levels deep, counting parentheses,
+/// arrays, `!` and chained operators, are rejected.
+///
+/// Expressions can be deserialized from a string, so they can be part of any
+/// configuration format supported by serde.
+///
+/// # Example
+/// ```
+/// use axum_jwks::Expression;
+/// use serde_json::json;
+///
+/// let expression: Expression =
+///     r#"iss == "https://auth.example.com/" && "admin" in roles && exp - iat <= 3600"#
+///         .parse()
+///         .unwrap();
+///
+/// let claims = json!({
+///     "iss": "https://auth.example.com/",
+///     "roles": ["admin"],
+///     "iat": 1000,
+///     "exp": 4000,
+/// });
+///
+/// assert_eq!(Ok(true), expression.evaluate(&claims));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+impl Expression {
+    /// Compile an expression.
+    pub fn compile(source: &str) -> Result<Self, ExpressionError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+            end: source.len(),
+            depth: 0,
+        };
+        let start = parser.offset();
+        let root = parser.expression()?;
+
+        if let Some(token) = parser.peek() {
+            return Err(ExpressionError::new(
+                token.offset,
+                format!("unexpected {}", token.kind),
+            ));
+        }
+
+        check_boolean(&root, start)?;
+
+        Ok(Self {
+            source: source.to_owned(),
+            root,
+        })
+    }
+
+    /// The source the expression was compiled from.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Evaluate the expression against the claims of a validated token.
+    pub fn evaluate(&self, claims: &Value) -> Result<bool, EvaluationError> {
+        match self.root.evaluate(claims)? {
+            Value::Bool(result) => Ok(result),
+            other => Err(EvaluationError::NotBoolean(other)),
+        }
+    }
+}
+
+impl FromStr for Expression {
+    type Err = ExpressionError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        Self::compile(source)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Expression {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let source = String::deserialize(deserializer)?;
+
+        Self::compile(&source)
+            .map_err(|error| de::Error::custom(format!("invalid expression {source:?}: {error}")))
+    }
+}
+
+impl Policy for Expression {
+    fn evaluate(&self, request: &PolicyRequest<'_>) -> Result<(), Denial> {
+        match Expression::evaluate(self, request.claims) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Denial::new(self.describe(), "the expression is false")),
+            Err(error) => Err(Denial::new(self.describe(), error.to_string())),
+        }
+    }
+
+    fn describe(&self) -> String {
+        self.source.clone()
+    }
+}
+
+/// An error compiling an [`Expression`].
+#[derive(Clone, Debug, Error, PartialEq)]
+#[error("{message} at column {column}")]
+pub struct ExpressionError {
+    /// The 1-based column the error was found at, counted in bytes.
+    pub column: usize,
+    pub message: String,
+}
+
+impl ExpressionError {
+    fn new(offset: usize, message: impl Into<String>) -> Self {
+        Self {
+            column: offset + 1,
+            message: message.into(),
+        }
+    }
+}
+
+/// An error evaluating an [`Expression`] against a set of claims.
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum EvaluationError {
+    #[error("cannot apply `{operator}` to {left} and {right}")]
+    TypeMismatch {
+        operator: &'static str,
+        left: Value,
+        right: Value,
+    },
+
+    #[error("expected a boolean but found {0}")]
+    NotBoolean(Value),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Identifier(String),
+    Literal(Value),
+    Operator(&'static str),
+    Dot,
+    Comma,
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Identifier(name) => write!(f, "identifier `{name}`"),
+            Self::Literal(value) => write!(f, "literal {value}"),
+            Self::Operator(operator) => write!(f, "`{operator}`"),
+            Self::Dot => f.write_str("`.`"),
+            Self::Comma => f.write_str("`,`"),
+            Self::OpenParen => f.write_str("`(`"),
+            Self::CloseParen => f.write_str("`)`"),
+            Self::OpenBracket => f.write_str("`[`"),
+            Self::CloseBracket => f.write_str("`]`"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    offset: usize,
+}
+
+const OPERATORS: [&str; 12] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "+", "-", "=",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ExpressionError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+
+    while let Some(&(offset, c)) = chars.peek() {
+        let kind = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '.' => TokenKind::Dot,
+            ',' => TokenKind::Comma,
+            '(' => TokenKind::OpenParen,
+            ')' => TokenKind::CloseParen,
+            '[' => TokenKind::OpenBracket,
+            ']' => TokenKind::CloseBracket,
+            '"' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped @ ('"' | '\\'))) => value.push(escaped),
+                            Some((escape_offset, other)) => {
+                                return Err(ExpressionError::new(
+                                    escape_offset,
+                                    format!("unknown escape `\\{other}`"),
+                                ))
+                            }
+                            None => {
+                                return Err(ExpressionError::new(offset, "unterminated string"))
+                            }
+                        },
+                        Some((_, c)) => value.push(c),
+                        None => return Err(ExpressionError::new(offset, "unterminated string")),
+                    }
+                }
+                tokens.push(Token {
+                    kind: TokenKind::Literal(Value::String(value)),
+                    offset,
+                });
+                continue;
+            }
+            c if c.is_ascii_digit() => {
+                let mut end = offset;
+                while let Some(&(index, c)) = chars.peek() {
+                    if !(c.is_ascii_digit() || c == '.') {
+                        break;
+                    }
+                    end = index + c.len_utf8();
+                    chars.next();
+                }
+                let text = &source[offset..end];
+                let number = text
+                    .parse::<i64>()
+                    .map(Value::from)
+                    .or_else(|_| text.parse::<f64>().map(Value::from))
+                    .map_err(|_| {
+                        ExpressionError::new(offset, format!("invalid number `{text}`"))
+                    })?;
+                tokens.push(Token {
+                    kind: TokenKind::Literal(number),
+                    offset,
+                });
+                continue;
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = offset;
+                while let Some(&(index, c)) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    end = index + c.len_utf8();
+                    chars.next();
+                }
+                let kind = match &source[offset..end] {
+                    "true" => TokenKind::Literal(Value::Bool(true)),
+                    "false" => TokenKind::Literal(Value::Bool(false)),
+                    "null" => TokenKind::Literal(Value::Null),
+                    "in" => TokenKind::Operator("in"),
+                    "contains" => TokenKind::Operator("contains"),
+                    name => TokenKind::Identifier(name.to_owned()),
+                };
+                tokens.push(Token { kind, offset });
+                continue;
+            }
+            _ => {
+                let rest = &source[offset..];
+                let operator = OPERATORS
+                    .iter()
+                    .find(|operator| rest.starts_with(**operator))
+                    .ok_or_else(|| ExpressionError::new(offset, format!("unexpected `{c}`")))?;
+                if *operator == "=" {
+                    return Err(ExpressionError::new(
+                        offset,
+                        "unexpected `=`, use `==` for comparisons",
+                    ));
+                }
+                for _ in 0..operator.len() {
+                    chars.next();
+                }
+                tokens.push(Token {
+                    kind: TokenKind::Operator(operator),
+                    offset,
+                });
+                continue;
+            }
+        };
+
+        chars.next();
+        tokens.push(Token { kind, offset });
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Literal(Value),
+    Claim(Vec<String>),
+    Array(Vec<Node>),
+    Not {
+        operand: Box<Node>,
+        /// Where the operand starts.
+        offset: usize,
+    },
+    Binary {
+        operator: &'static str,
+        left: Box<Node>,
+        right: Box<Node>,
+        offset: usize,
+    },
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    end: usize,
+    /// How deeply the node being parsed is nested.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+    fn offset(&self) -> usize {
+        self.peek().map_or(self.end, |token| token.offset)
+    }
+
+    fn eat_operator(&mut self, operators: &[&'static str]) -> Option<(&'static str, usize)> {
+        match self.peek() {
+            Some(Token {
+                kind: TokenKind::Operator(operator),
+                offset,
+            }) if operators.contains(operator) => {
+                let found = (*operator, *offset);
+                self.position += 1;
+                Some(found)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, expected: TokenKind) -> Result<(), ExpressionError> {
+        let offset = self.offset();
+
+        match self.next() {
+            Some(token) if token.kind == expected => Ok(()),
+            Some(token) => Err(ExpressionError::new(
+                offset,
+                format!("expected {expected} but found {}", token.kind),
+            )),
+            None => Err(ExpressionError::new(
+                offset,
+                format!("expected {expected} but the expression ended"),
+            )),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Node, ExpressionError> {
+        self.binary(0)
+    }
+
+    /// Go one level deeper, failing if the expression is nested too deeply.
+    fn descend(&mut self, offset: usize) -> Result<(), ExpressionError> {
+        if self.depth == MAX_DEPTH {
+            return Err(ExpressionError::new(
+                offset,
+                "the expression is nested too deeply",
+            ));
+        }
+
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Parse a node one level deeper.
+    fn nested(
+        &mut self,
+        offset: usize,
+        parse: impl FnOnce(&mut Self) -> Result<Node, ExpressionError>,
+    ) -> Result<Node, ExpressionError> {
+        self.descend(offset)?;
+        let node = parse(self)?;
+        self.depth -= 1;
+
+        Ok(node)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Node, ExpressionError> {
+        const LEVELS: [&[&str]; 4] = [
+            &["||"],
+            &["&&"],
+            &["==", "!=", "<", "<=", ">", ">=", "in", "contains"],
+            &["+", "-"],
+        ];
+
+        let Some(operators) = LEVELS.get(level) else {
+            return self.unary();
+        };
+
+        let depth = self.depth;
+        let mut left = self.binary(level + 1)?;
+        while let Some((operator, offset)) = self.eat_operator(operators) {
+            // Chained operators nest to the left, so every operator adds a
+            // level.
+            self.descend(offset)?;
+
+            let right = self.binary(level + 1)?;
+            left = Node::Binary {
+                operator,
+                left: Box::new(left),
+                right: Box::new(right),
+                offset,
+            };
+
+            // Comparisons don't chain, so `a == b == c` is an error rather
+            // than a surprise.
+            if level == 2 {
+                if let Some((operator, offset)) = self.eat_operator(operators) {
+                    return Err(ExpressionError::new(
+                        offset,
+                        format!("unexpected `{operator}`, comparisons cannot be chained"),
+                    ));
+                }
+            }
+        }
+        self.depth = depth;
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, ExpressionError> {
+        if let Some((_, offset)) = self.eat_operator(&["!"]) {
+            let start = self.offset();
+            let operand = self.nested(offset, Self::unary)?;
+
+            return Ok(Node::Not {
+                operand: Box::new(operand),
+                offset: start,
+            });
+        }
+
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Node, ExpressionError> {
+        let offset = self.offset();
+        let Some(token) = self.next().cloned() else {
+            return Err(ExpressionError::new(offset, "the expression ended early"));
+        };
+
+        match token.kind {
+            TokenKind::Literal(value) => Ok(Node::Literal(value)),
+            TokenKind::Identifier(name) => self.claim(name),
+            TokenKind::OpenParen => {
+                let node = self.nested(token.offset, Self::expression)?;
+                self.expect(TokenKind::CloseParen)?;
+                Ok(node)
+            }
+            TokenKind::OpenBracket => self.nested(token.offset, Self::array),
+            other => Err(ExpressionError::new(
+                token.offset,
+                format!("expected a value but found {other}"),
+            )),
+        }
+    }
+
+    fn array(&mut self) -> Result<Node, ExpressionError> {
+        let mut items = Vec::new();
+        if !matches!(
+            self.peek(),
+            Some(Token {
+                kind: TokenKind::CloseBracket,
+                ..
+            })
+        ) {
+            loop {
+                items.push(self.expression()?);
+                if matches!(
+                    self.peek(),
+                    Some(Token {
+                        kind: TokenKind::Comma,
+                        ..
+                    })
+                ) {
+                    self.position += 1;
+                } else {
+                    break;
+                }
+            }
+        }
+        self.expect(TokenKind::CloseBracket)?;
+        Ok(Node::Array(items))
+    }
+
+    fn claim(&mut self, first: String) -> Result<Node, ExpressionError> {
+        // `claims` refers to the whole claim set, which allows indexing
+        // claims whose names aren't identifiers.
+        let mut path = if first == "claims" {
+            Vec::new()
+        } else {
+            vec![first]
+        };
+
+        loop {
+            match self.peek().map(|token| &token.kind) {
+                Some(TokenKind::Dot) => {
+                    self.position += 1;
+                    let offset = self.offset();
+                    match self.next() {
+                        Some(Token {
+                            kind: TokenKind::Identifier(name),
+                            ..
+                        }) => path.push(name.clone()),
+                        _ => {
+                            return Err(ExpressionError::new(
+                                offset,
+                                "expected a claim name after `.`",
+                            ))
+                        }
+                    }
+                }
+                Some(TokenKind::OpenBracket) => {
+                    self.position += 1;
+                    let offset = self.offset();
+                    match self.next() {
+                        Some(Token {
+                            kind: TokenKind::Literal(Value::String(name)),
+                            ..
+                        }) => path.push(name.clone()),
+                        _ => {
+                            return Err(ExpressionError::new(
+                                offset,
+                                "expected a quoted claim name after `[`",
+                            ))
+                        }
+                    }
+                    self.expect(TokenKind::CloseBracket)?;
+                }
+                _ => break,
+            }
+        }
+
+        Ok(Node::Claim(path))
+    }
+}
+
+/// The type of a node, where it can be known before evaluation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum StaticType {
+    Boolean,
+    Number,
+    Other,
+    Unknown,
+}
+
+fn static_type(node: &Node) -> Result<StaticType, ExpressionError> {
+    match node {
+        Node::Literal(Value::Bool(_)) => Ok(StaticType::Boolean),
+        Node::Literal(Value::Number(_)) => Ok(StaticType::Number),
+        Node::Literal(_) | Node::Array(_) => Ok(StaticType::Other),
+        Node::Claim(_) => Ok(StaticType::Unknown),
+        Node::Not { operand, offset } => {
+            check_boolean(operand, *offset)?;
+            Ok(StaticType::Boolean)
+        }
+        Node::Binary {
+            operator,
+            left,
+            right,
+            offset,
+        } => {
+            let (left, right) = (static_type(left)?, static_type(right)?);
+            match *operator {
+                "&&" | "||" => {
+                    for operand in [left, right] {
+                        if !matches!(operand, StaticType::Boolean | StaticType::Unknown) {
+                            return Err(ExpressionError::new(
+                                *offset,
+                                format!("`{operator}` requires boolean operands"),
+                            ));
+                        }
+                    }
+                    Ok(StaticType::Boolean)
+                }
+                "+" | "-" => {
+                    for operand in [left, right] {
+                        if !matches!(operand, StaticType::Number | StaticType::Unknown) {
+                            return Err(ExpressionError::new(
+                                *offset,
+                                format!("`{operator}` requires numeric operands"),
+                            ));
+                        }
+                    }
+                    Ok(StaticType::Number)
+                }
+                _ => Ok(StaticType::Boolean),
+            }
+        }
+    }
+}
+
+/// Require a node that starts at `offset` to be a boolean.
+fn check_boolean(node: &Node, offset: usize) -> Result<(), ExpressionError> {
+    match static_type(node)? {
+        StaticType::Boolean | StaticType::Unknown => Ok(()),
+        _ => Err(ExpressionError::new(
+            offset,
+            "the expression must evaluate to a boolean",
+        )),
+    }
+}
+
+impl Node {
+    fn evaluate(&self, claims: &Value) -> Result<Value, EvaluationError> {
+        match self {
+            Self::Literal(value) => Ok(value.clone()),
+            Self::Claim(path) => Ok(path
+                .iter()
+                .try_fold(claims, |value, name| value.get(name))
+                .cloned()
+                .unwrap_or(Value::Null)),
+            Self::Array(items) => items
+                .iter()
+                .map(|item| item.evaluate(claims))
+                .collect::<Result<_, _>>()
+                .map(Value::Array),
+            Self::Not { operand, .. } => match operand.evaluate(claims)? {
+                Value::Bool(value) => Ok(Value::Bool(!value)),
+                other => Err(EvaluationError::NotBoolean(other)),
+            },
+            Self::Binary {
+                operator,
+                left,
+                right,
+                ..
+            } => {
+                let left = left.evaluate(claims)?;
+
+                // Short circuit so that guards like `x != null && x > 1`
+                // work.
+                match (*operator, &left) {
+                    ("&&", Value::Bool(false)) => return Ok(Value::Bool(false)),
+                    ("||", Value::Bool(true)) => return Ok(Value::Bool(true)),
+                    _ => {}
+                }
+
+                let right = right.evaluate(claims)?;
+                binary(operator, left, right)
+            }
+        }
+    }
+}
+
+fn binary(operator: &'static str, left: Value, right: Value) -> Result<Value, EvaluationError> {
+    let mismatch = |left: Value, right: Value| EvaluationError::TypeMismatch {
+        operator,
+        left,
+        right,
+    };
+
+    let result = match operator {
+        "==" => Value::Bool(values_equal(&left, &right)),
+        "!=" => Value::Bool(!values_equal(&left, &right)),
+        "&&" | "||" => match (&left, &right) {
+            (Value::Bool(_), Value::Bool(right)) => Value::Bool(*right),
+            _ => return Err(mismatch(left, right)),
+        },
+        "in" => Value::Bool(contains(&right, &left)),
+        "contains" => Value::Bool(contains(&left, &right)),
+        "+" | "-" => match (left.as_f64(), right.as_f64()) {
+            (Some(l), Some(r)) => {
+                let result = if operator == "+" { l + r } else { l - r };
+                match (left.as_i64(), right.as_i64()) {
+                    (Some(l), Some(r)) => {
+                        let exact = if operator == "+" {
+                            l.checked_add(r)
+                        } else {
+                            l.checked_sub(r)
+                        };
+                        exact.map(Value::from).unwrap_or(Value::from(result))
+                    }
+                    _ => Value::from(result),
+                }
+            }
+            _ => return Err(mismatch(left, right)),
+        },
+        _ => {
+            let ordering = match (&left, &right) {
+                (Value::Number(l), Value::Number(r)) => l
+                    .as_f64()
+                    .zip(r.as_f64())
+                    .and_then(|(l, r)| l.partial_cmp(&r)),
+                (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+                _ => None,
+            };
+            let Some(ordering) = ordering else {
+                return Err(mismatch(left, right));
+            };
+            Value::Bool(match operator {
+                "<" => ordering.is_lt(),
+                "<=" => ordering.is_le(),
+                ">" => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            })
+        }
+    };
+
+    Ok(result)
+}
+
+fn values_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => l.as_f64() == r.as_f64(),
+        _ => left == right,
+    }
+}
+
+fn contains(haystack: &Value, needle: &Value) -> bool {
+    match (haystack, needle) {
+        (Value::Array(items), needle) => items.iter().any(|item| values_equal(item, needle)),
+        (Value::String(_), Value::String(needle)) => {
+            string_list(Some(haystack)).any(|item| item == *needle)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn evaluate(source: &str, claims: Value) -> Result<bool, EvaluationError> {
+        Expression::compile(source).unwrap().evaluate(&claims)
+    }
+
+    #[test]
+    fn evaluate_comparisons_and_logic() {
+        let claims = json!({
+            "iss": "https://auth.example.com/",
+            "aud": ["api", "web"],
+            "scope": "read write",
+            "roles": ["admin"],
+            "iat": 1000,
+            "exp": 4600,
+            "realm_access": { "roles": ["editor"] },
+            "kubernetes.io": { "namespace": "prod" },
+        });
+
+        let cases = [
+            (r#"iss == "https://auth.example.com/""#, true),
+            (r#""admin" in roles && aud contains "api""#, true),
+            ("exp - iat <= 3600", true),
+            ("exp - iat < 3600", false),
+            (r#""write" in scope && !("delete" in scope)"#, true),
+            (r#""editor" in realm_access.roles"#, true),
+            (r#"claims["kubernetes.io"].namespace == "prod""#, true),
+            (r#"missing == null || missing > 3"#, true),
+            (r#"aud == ["api", "web"]"#, true),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(Ok(expected), evaluate(source, claims.clone()), "{source}");
+        }
+    }
+
+    #[test]
+    fn evaluation_type_errors() {
+        assert_eq!(
+            Err(EvaluationError::TypeMismatch {
+                operator: ">",
+                left: json!("a"),
+                right: json!(1),
+            }),
+            evaluate("sub > 1", json!({ "sub": "a" }))
+        );
+        assert_eq!(
+            Err(EvaluationError::NotBoolean(json!("a"))),
+            evaluate("sub", json!({ "sub": "a" }))
+        );
+    }
+
+    #[test]
+    fn compile_errors() {
+        let cases = [
+            ("iss = \"a\"", 5, "unexpected `=`, use `==` for comparisons"),
+            ("iss == ", 8, "the expression ended early"),
+            ("(iss == \"a\"", 12, "expected `)` but the expression ended"),
+            ("\"a", 1, "unterminated string"),
+            (
+                "a == b == c",
+                8,
+                "unexpected `==`, comparisons cannot be chained",
+            ),
+            ("exp - \"a\" > 1", 5, "`-` requires numeric operands"),
+            ("1 && true", 3, "`&&` requires boolean operands"),
+            ("exp + 1", 1, "the expression must evaluate to a boolean"),
+            ("  exp + 1", 3, "the expression must evaluate to a boolean"),
+            (
+                "sub == \"a\" && !(exp + 1)",
+                16,
+                "the expression must evaluate to a boolean",
+            ),
+            ("iss == \"a\" iss", 12, "unexpected identifier `iss`"),
+            ("a.1 == 2", 3, "expected a claim name after `.`"),
+            ("a # b", 3, "unexpected `#`"),
+        ];
+
+        for (source, column, message) in cases {
+            assert_eq!(
+                Err(ExpressionError {
+                    column,
+                    message: message.to_owned()
+                }),
+                Expression::compile(source).map(|_| ()),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn reject_deep_nesting() {
+        let nested = [
+            "!".repeat(100_000) + "admin",
+            "(".repeat(100_000) + "admin" + &")".repeat(100_000),
+            "[".repeat(100_000) + &"]".repeat(100_000) + " == null",
+            vec!["admin"; 100_000].join(" || "),
+        ];
+
+        for source in nested {
+            let error = Expression::compile(&source).unwrap_err();
+
+            assert_eq!("the expression is nested too deeply", error.message);
+        }
+
+        let shallow = "!".repeat(MAX_DEPTH) + "admin";
+        assert_eq!(
+            Ok(true),
+            Expression::compile(&shallow)
+                .unwrap()
+                .evaluate(&json!({ "admin": true }))
+        );
+    }
+
+    #[test]
+    fn deserialize_from_configuration() {
+        #[derive(Deserialize)]
+        struct Rules {
+            admin: Expression,
+        }
+
+        let rules: Rules = serde_json::from_value(json!({
+            "admin": "\"admin\" in roles",
+        }))
+        .unwrap();
+        assert_eq!(
+            Ok(true),
+            rules.admin.evaluate(&json!({ "roles": ["admin"] }))
+        );
+
+        let error = serde_json::from_value::<Rules>(json!({ "admin": "roles ==" }))
+            .err()
+            .unwrap();
+        assert_eq!(
+            "invalid expression \"roles ==\": the expression ended early at column 9",
+            error.to_string()
+        );
+    }
+}
@@ -0,0 +1,449 @@
+use std::fmt;
+
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::debug;
+
+use crate::{
+    claims::{accept_token, verified_token},
+    Jwks, JwksError, ParseTokenClaims, TokenError,
+};
+
+/// The issuer of GitHub Actions OIDC tokens.
+pub const GITHUB_ACTIONS_ISSUER: &str = "https://token.actions.githubusercontent.com";
+
+impl Jwks {
+    /// Pull the signing keys for GitHub Actions OIDC tokens.
+    ///
+    /// # Arguments
+    /// * `audience` - The audience requested by the workflow, e.g. with
+    ///   `core.getIDToken(audience)`. This will be matched against the `aud`
+    ///   claim from the token.
+    pub async fn from_github_actions(audience: &str) -> Result<Self, JwksError> {
+        Self::from_github_actions_with_client(&reqwest::Client::default(), audience).await
+    }
+
+    /// A version of [`from_github_actions`][Self::from_github_actions] that
+    /// allows for passing in a custom [`Client`][reqwest::Client].
+    pub async fn from_github_actions_with_client(
+        client: &reqwest::Client,
+        audience: &str,
+    ) -> Result<Self, JwksError> {
+        let oidc_url = format!("{GITHUB_ACTIONS_ISSUER}/.well-known/openid-configuration");
+
+        let mut jwks = Self::from_oidc_url_with_client(client, &oidc_url, Some(audience)).await?;
+        jwks.set_issuer(&[GITHUB_ACTIONS_ISSUER]);
+
+        Ok(jwks)
+    }
+}
+
+/// The claims of a GitHub Actions OIDC token.
+///
+/// See GitHub's documentation on [OpenID Connect][docs] for the meaning of
+/// each claim.
+///
+/// [docs]: https://docs.github.com/en/actions/security-for-github-actions/security-hardening-your-deployments/about-security-hardening-with-openid-connect
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GitHubActionsClaims {
+    pub iss: String,
+    pub sub: String,
+    pub exp: u64,
+    pub iat: u64,
+
+    /// The repository the workflow ran in, e.g. `octo-org/octo-repo`.
+    pub repository: String,
+    pub repository_id: String,
+    pub repository_owner: String,
+    pub repository_owner_id: String,
+
+    #[serde(default)]
+    pub repository_visibility: Option<String>,
+
+    /// The git ref that triggered the workflow, e.g. `refs/heads/main`.
+    #[serde(rename = "ref")]
+    pub git_ref: String,
+
+    #[serde(default)]
+    pub ref_type: Option<String>,
+
+    pub sha: String,
+
+    /// The deployment environment of the job, if it has one.
+    #[serde(default)]
+    pub environment: Option<String>,
+
+    /// The reusable workflow the job ran, e.g.
+    /// `octo-org/workflows/.github/workflows/deploy.yml@refs/heads/main`.
+    pub job_workflow_ref: String,
+
+    pub workflow: String,
+
+    #[serde(default)]
+    pub workflow_ref: Option<String>,
+
+    pub event_name: String,
+    pub actor: String,
+    pub actor_id: String,
+    pub run_id: String,
+    pub run_number: String,
+    pub run_attempt: String,
+
+    #[serde(default)]
+    pub head_ref: Option<String>,
+
+    #[serde(default)]
+    pub base_ref: Option<String>,
+
+    #[serde(default)]
+    pub runner_environment: Option<String>,
+}
+
+impl<S: Send + Sync> ParseTokenClaims<S> for GitHubActionsClaims {
+    type Rejection = GitHubActionsRejection;
+}
+
+/// A set of workflows allowed to call an application.
+///
+/// A token is accepted if it matches any of the policy's rules. A policy
+/// without rules accepts nothing.
+///
+/// # Example
+/// ```
+/// use axum_jwks::{GitHubActionsPolicy, GitHubActionsRule};
+///
+/// let policy = GitHubActionsPolicy::new().allow(
+///     GitHubActionsRule::repository("octo-org/octo-repo")
+///         .git_ref("refs/heads/main")
+///         .environment("prod"),
+/// );
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct GitHubActionsPolicy {
+    rules: Vec<GitHubActionsRule>,
+}
+
+impl GitHubActionsPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept tokens matching the given rule.
+    pub fn allow(mut self, rule: GitHubActionsRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Check the claims of an already validated token against the policy.
+    ///
+    /// If no rule matches, the error explains why each rule did not match.
+    pub fn check(&self, claims: &GitHubActionsClaims) -> Result<(), GitHubActionsPolicyError> {
+        let mut mismatches = Vec::with_capacity(self.rules.len());
+
+        for rule in &self.rules {
+            match rule.check(claims) {
+                Ok(()) => return Ok(()),
+                Err(mismatch) => mismatches.push(mismatch),
+            }
+        }
+
+        Err(GitHubActionsPolicyError { mismatches })
+    }
+}
+
+/// Requirements a single workflow run must meet.
+///
+/// Every requirement that is set must match exactly.
+#[derive(Clone, Debug, Default)]
+pub struct GitHubActionsRule {
+    repository: Option<String>,
+    repository_owner: Option<String>,
+    git_ref: Option<String>,
+    environment: Option<String>,
+    job_workflow_ref: Option<String>,
+}
+
+impl GitHubActionsRule {
+    /// Match workflow runs in the given repository, e.g. `octo-org/octo-repo`.
+    pub fn repository(repository: impl Into<String>) -> Self {
+        Self {
+            repository: Some(repository.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Match workflow runs in any repository of the given owner.
+    pub fn repository_owner(owner: impl Into<String>) -> Self {
+        Self {
+            repository_owner: Some(owner.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Require the run to be for the given ref, e.g. `refs/heads/main`.
+    pub fn git_ref(mut self, git_ref: impl Into<String>) -> Self {
+        self.git_ref = Some(git_ref.into());
+        self
+    }
+
+    /// Require the job to target the given deployment environment.
+    pub fn environment(mut self, environment: impl Into<String>) -> Self {
+        self.environment = Some(environment.into());
+        self
+    }
+
+    /// Require the job to run the given reusable workflow.
+    pub fn job_workflow_ref(mut self, job_workflow_ref: impl Into<String>) -> Self {
+        self.job_workflow_ref = Some(job_workflow_ref.into());
+        self
+    }
+
+    fn check(&self, claims: &GitHubActionsClaims) -> Result<(), GitHubActionsMismatch> {
+        let checks = [
+            ("repository", &self.repository, Some(&claims.repository)),
+            (
+                "repository_owner",
+                &self.repository_owner,
+                Some(&claims.repository_owner),
+            ),
+            ("ref", &self.git_ref, Some(&claims.git_ref)),
+            (
+                "environment",
+                &self.environment,
+                claims.environment.as_ref(),
+            ),
+            (
+                "job_workflow_ref",
+                &self.job_workflow_ref,
+                Some(&claims.job_workflow_ref),
+            ),
+        ];
+
+        for (claim, expected, actual) in checks {
+            if let Some(expected) = expected {
+                if actual != Some(expected) {
+                    return Err(GitHubActionsMismatch {
+                        claim,
+                        expected: expected.clone(),
+                        actual: actual.cloned(),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The first claim that prevented a [`GitHubActionsRule`] from matching.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GitHubActionsMismatch {
+    pub claim: &'static str,
+    pub expected: String,
+    pub actual: Option<String>,
+}
+
+impl fmt::Display for GitHubActionsMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.actual {
+            Some(actual) => write!(
+                f,
+                "`{}` is {:?}, expected {:?}",
+                self.claim, actual, self.expected
+            ),
+            None => write!(
+                f,
+                "`{}` is missing, expected {:?}",
+                self.claim, self.expected
+            ),
+        }
+    }
+}
+
+/// A token did not match any rule of a [`GitHubActionsPolicy`].
+#[derive(Clone, Debug, Error, PartialEq)]
+pub struct GitHubActionsPolicyError {
+    /// Why each rule did not match, in the order the rules were added.
+    pub mismatches: Vec<GitHubActionsMismatch>,
+}
+
+impl fmt::Display for GitHubActionsPolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.mismatches.is_empty() {
+            return write!(f, "no workflows are allowed");
+        }
+
+        write!(f, "the workflow run is not allowed: ")?;
+        for (index, mismatch) in self.mismatches.iter().enumerate() {
+            if index > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "rule {}: {}", index + 1, mismatch)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A workflow run whose token was validated and accepted by the
+/// [`GitHubActionsPolicy`] in the application state.
+pub struct GitHubActions(pub GitHubActionsClaims);
+
+/// The rejection for [`GitHubActionsClaims`] and [`GitHubActions`].
+#[derive(Debug, Error, PartialEq)]
+pub enum GitHubActionsRejection {
+    #[error(transparent)]
+    Token(#[from] TokenError),
+
+    #[error(transparent)]
+    Forbidden(#[from] GitHubActionsPolicyError),
+}
+
+impl IntoResponse for GitHubActionsRejection {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Token(error) => error.into_response(),
+            // The error names the expected repository, ref or environment,
+            // which is only logged.
+            Self::Forbidden(_) => StatusCode::FORBIDDEN.into_response(),
+        }
+    }
+}
+
+impl<S> FromRequestParts<S> for GitHubActions
+where
+    Jwks: FromRef<S>,
+    GitHubActionsPolicy: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = GitHubActionsRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let verified = verified_token(parts, state).await?;
+        let claims: GitHubActionsClaims = verified.raw().deserialize()?;
+
+        GitHubActionsPolicy::from_ref(state)
+            .check(&claims)
+            .inspect_err(|error| debug!(%error, "GitHub Actions token was refused."))?;
+
+        accept_token(parts, &verified)?;
+
+        Ok(Self(claims))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn claims(repository: &str, git_ref: &str, environment: Option<&str>) -> GitHubActionsClaims {
+        let owner = repository.split('/').next().unwrap();
+
+        serde_json::from_value(json!({
+            "iss": GITHUB_ACTIONS_ISSUER,
+            "sub": format!("repo:{repository}:ref:{git_ref}"),
+            "aud": "https://deploy.example.com",
+            "exp": 2,
+            "iat": 1,
+            "repository": repository,
+            "repository_id": "1",
+            "repository_owner": owner,
+            "repository_owner_id": "2",
+            "ref": git_ref,
+            "sha": "abc",
+            "environment": environment,
+            "job_workflow_ref": format!("{repository}/.github/workflows/deploy.yml@{git_ref}"),
+            "workflow": "deploy",
+            "event_name": "push",
+            "actor": "octocat",
+            "actor_id": "3",
+            "run_id": "4",
+            "run_number": "5",
+            "run_attempt": "1",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn policy_accepts_matching_rule() {
+        let policy = GitHubActionsPolicy::new()
+            .allow(GitHubActionsRule::repository("org/other"))
+            .allow(
+                GitHubActionsRule::repository("org/x")
+                    .git_ref("refs/heads/main")
+                    .environment("prod"),
+            );
+
+        assert_eq!(
+            Ok(()),
+            policy.check(&claims("org/x", "refs/heads/main", Some("prod")))
+        );
+    }
+
+    #[test]
+    fn policy_explains_mismatches() {
+        let policy = GitHubActionsPolicy::new()
+            .allow(GitHubActionsRule::repository_owner("other-org"))
+            .allow(
+                GitHubActionsRule::repository("org/x")
+                    .git_ref("refs/heads/main")
+                    .environment("prod"),
+            );
+
+        let error = policy
+            .check(&claims("org/x", "refs/heads/main", None))
+            .unwrap_err();
+
+        assert_eq!(
+            vec![
+                GitHubActionsMismatch {
+                    claim: "repository_owner",
+                    expected: "other-org".to_owned(),
+                    actual: Some("org".to_owned()),
+                },
+                GitHubActionsMismatch {
+                    claim: "environment",
+                    expected: "prod".to_owned(),
+                    actual: None,
+                },
+            ],
+            error.mismatches
+        );
+        assert_eq!(
+            "the workflow run is not allowed: rule 1: `repository_owner` is \"org\", expected \"other-org\"; rule 2: `environment` is missing, expected \"prod\"",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn empty_policy_rejects() {
+        let error = GitHubActionsPolicy::new()
+            .check(&claims("org/x", "refs/heads/main", None))
+            .unwrap_err();
+
+        assert_eq!("no workflows are allowed", error.to_string());
+    }
+
+    #[tokio::test]
+    async fn rejection_does_not_reveal_policy() {
+        let policy = GitHubActionsPolicy::new().allow(GitHubActionsRule::repository("org/secret"));
+        let error = policy
+            .check(&claims("org/x", "refs/heads/main", None))
+            .unwrap_err();
+
+        let response = GitHubActionsRejection::from(error).into_response();
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.is_empty());
+    }
+}
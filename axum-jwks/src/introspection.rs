@@ -0,0 +1,488 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime},
+};
+
+use jsonwebtoken::errors::ErrorKind;
+use ring::digest::{digest, SHA256};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::{debug, warn};
+
+use crate::{
+    checks::{check_audience, check_issuer},
+    JwksError, TokenError, ValidationOptions,
+};
+
+/// When a token is introspected instead of validated locally.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IntrospectionMode {
+    /// Only introspect tokens that are not JWTs, such as opaque reference
+    /// tokens.
+    #[default]
+    OpaqueOnly,
+
+    /// Introspect every token, even JWTs that could be validated locally.
+    ///
+    /// This is useful for high-risk routes that must notice revoked tokens
+    /// immediately, so responses are never cached in this mode.
+    Always,
+}
+
+/// Validates tokens with an OAuth 2.0 [token introspection][rfc] endpoint.
+///
+/// The endpoint is called with the configured client credentials, and the
+/// introspection response is used as the token's claims. Responses for
+/// active tokens are cached until the token's `exp`, unless every token is
+/// introspected with [`IntrospectionMode::Always`], while inactive tokens are
+/// rejected with [`TokenError::Inactive`]. Tokens are only cached by their
+/// SHA-256 digest.
+///
+/// Like a [`Jwks`][crate::Jwks], the introspection checks the `aud` and `iss`
+/// of the response when an audience or issuer is configured, either on the
+/// introspection itself or with [`ValidationOptions`] in the request's
+/// extensions. Without an audience, tokens issued for any resource server are
+/// accepted.
+///
+/// Insert the introspection into the request's extensions with
+/// [`Extension`][axum::Extension] to fall back to it when a token is not a
+/// JWT, or to use it for every token with [`IntrospectionMode::Always`]. This
+/// applies to the extractors in this crate that honor [`ValidationOptions`].
+/// The introspection is cheap to clone, and clones share their cache.
+///
+/// # Example
+/// ```no_run
+/// use axum::{routing::get, Extension, Router};
+/// use axum_jwks::{Claims, Introspection, Jwks};
+/// # use axum_jwks::{ParseTokenClaims, TokenError};
+/// # #[derive(serde::Deserialize)]
+/// # struct TokenClaims {}
+/// # impl<S: Send + Sync> ParseTokenClaims<S> for TokenClaims { type Rejection = TokenError; }
+///
+/// async fn handler(Claims(claims): Claims<TokenClaims>) {}
+///
+/// # async fn app(jwks: Jwks) -> Router {
+/// let introspection = Introspection::from_oidc_url(
+///     "https://auth.example.com/.well-known/openid-configuration",
+///     "my-api",
+///     "client-secret",
+/// )
+/// .await
+/// .unwrap()
+/// .audience(["my-api"]);
+///
+/// Router::new()
+///     .route("/", get(handler))
+///     .layer(Extension(introspection))
+///     .with_state(jwks)
+/// # }
+/// ```
+///
+/// [rfc]: https://datatracker.ietf.org/doc/html/rfc7662
+#[derive(Clone)]
+pub struct Introspection {
+    client: reqwest::Client,
+    endpoint: String,
+    client_id: String,
+    client_secret: String,
+    mode: IntrospectionMode,
+    audience: Option<Vec<String>>,
+    issuer: Option<Vec<String>>,
+    cache: Arc<Mutex<HashMap<Vec<u8>, CachedResponse>>>,
+}
+
+#[derive(Deserialize)]
+struct Oid {
+    introspection_endpoint: Option<String>,
+}
+
+impl Introspection {
+    /// Use the given introspection endpoint.
+    pub fn new(
+        endpoint: impl Into<String>,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+    ) -> Self {
+        Self {
+            client: reqwest::Client::default(),
+            endpoint: endpoint.into(),
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            mode: IntrospectionMode::default(),
+            audience: None,
+            issuer: None,
+            cache: Arc::default(),
+        }
+    }
+
+    /// Use the introspection endpoint from an authority's
+    /// openid-configuration.
+    pub async fn from_oidc_url(
+        oidc_url: &str,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+    ) -> Result<Self, JwksError> {
+        Self::from_oidc_url_with_client(
+            &reqwest::Client::default(),
+            oidc_url,
+            client_id,
+            client_secret,
+        )
+        .await
+    }
+
+    /// A version of [`from_oidc_url`][Self::from_oidc_url] that allows for
+    /// passing in a custom [`Client`][reqwest::Client].
+    pub async fn from_oidc_url_with_client(
+        client: &reqwest::Client,
+        oidc_url: &str,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+    ) -> Result<Self, JwksError> {
+        debug!(%oidc_url, "Fetching openid-configuration.");
+        let oidc = client.get(oidc_url).send().await?.json::<Oid>().await?;
+        let endpoint = oidc
+            .introspection_endpoint
+            .ok_or(JwksError::MissingEndpoint("introspection_endpoint"))?;
+
+        Ok(Self::new(endpoint, client_id, client_secret).client(client.clone()))
+    }
+
+    /// Call the endpoint with a custom [`Client`][reqwest::Client].
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Choose which tokens are introspected. Defaults to
+    /// [`IntrospectionMode::OpaqueOnly`].
+    pub fn mode(mut self, mode: IntrospectionMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Require the `aud` of introspection responses to contain one of the
+    /// given audiences.
+    pub fn audience<I, A>(mut self, audience: I) -> Self
+    where
+        I: IntoIterator<Item = A>,
+        A: Into<String>,
+    {
+        self.audience = Some(audience.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Require the `iss` of introspection responses to be one of the given
+    /// issuers.
+    pub fn issuer<I, A>(mut self, issuer: I) -> Self
+    where
+        I: IntoIterator<Item = A>,
+        A: Into<String>,
+    {
+        self.issuer = Some(issuer.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub(crate) fn introspects_all(&self) -> bool {
+        self.mode == IntrospectionMode::Always
+    }
+
+    /// Introspect a token, returning the introspection response of an active
+    /// token.
+    pub async fn introspect(&self, token: &str) -> Result<Value, TokenError> {
+        self.introspect_with(token, &ValidationOptions::default())
+            .await
+    }
+
+    /// Introspect a token, overriding the audience and issuer the response
+    /// is checked against.
+    pub(crate) async fn introspect_with(
+        &self,
+        token: &str,
+        options: &ValidationOptions,
+    ) -> Result<Value, TokenError> {
+        let response = self.active_response(token).await?;
+
+        let audience = options.audiences().or(self.audience.as_deref());
+        if let Some(audience) = audience {
+            let audience: Vec<&str> = audience.iter().map(String::as_str).collect();
+            check_audience(&response, &audience)
+                .map_err(|_| TokenError::Invalid(ErrorKind::InvalidAudience.into()))?;
+        }
+        let issuer = options.issuers().or(self.issuer.as_deref());
+        if let Some(issuer) = issuer {
+            let issuer: Vec<&str> = issuer.iter().map(String::as_str).collect();
+            check_issuer(&response, &issuer)
+                .map_err(|_| TokenError::Invalid(ErrorKind::InvalidIssuer.into()))?;
+        }
+
+        Ok(response)
+    }
+
+    /// The introspection response of an active token, from the cache where
+    /// allowed.
+    async fn active_response(&self, token: &str) -> Result<Value, TokenError> {
+        let use_cache = !self.introspects_all();
+        let key = digest(&SHA256, token.as_bytes()).as_ref().to_vec();
+        if let Some(cached) = self.cache().get(&key).filter(|_| use_cache) {
+            if cached.expires_at > SystemTime::now() {
+                return Ok(cached.response.clone());
+            }
+        }
+
+        let response = self
+            .client
+            .post(&self.endpoint)
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(&[("token", token), ("token_type_hint", "access_token")])
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|error| {
+                warn!(%error, "Token introspection failed.");
+
+                TokenError::IntrospectionFailed
+            })?
+            .json::<Value>()
+            .await
+            .map_err(|error| {
+                warn!(%error, "Introspection response is malformed.");
+
+                TokenError::IntrospectionFailed
+            })?;
+
+        if response.get("active").and_then(Value::as_bool) != Some(true) {
+            debug!("Introspected token is not active.");
+
+            return Err(TokenError::Inactive);
+        }
+
+        let expires_at = response
+            .get("exp")
+            .and_then(Value::as_u64)
+            .and_then(|exp| SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(exp)))
+            .filter(|_| use_cache);
+        if let Some(expires_at) = expires_at {
+            let now = SystemTime::now();
+            let mut cache = self.cache();
+            cache.retain(|_, cached| cached.expires_at > now);
+            cache.insert(
+                key,
+                CachedResponse {
+                    response: response.clone(),
+                    expires_at,
+                },
+            );
+        }
+
+        Ok(response)
+    }
+
+    fn cache(&self) -> MutexGuard<'_, HashMap<Vec<u8>, CachedResponse>> {
+        self.cache.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+struct CachedResponse {
+    response: Value,
+    expires_at: SystemTime,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, HeaderMap, Request, StatusCode},
+        routing::{get, post},
+        Extension, Form, Json, Router,
+    };
+    use serde_json::json;
+    use tokio::net::TcpListener;
+    use tower_service::Service;
+
+    use super::*;
+    use crate::{test_util, Claims, ParseTokenClaims};
+
+    #[derive(serde::Deserialize)]
+    struct TestClaims {
+        sub: String,
+    }
+
+    impl<S: Send + Sync> ParseTokenClaims<S> for TestClaims {
+        type Rejection = TokenError;
+    }
+
+    /// Serve a stand-in for an authorization server whose introspection
+    /// endpoint knows the active token `opaque-active`, counting how often it
+    /// is called.
+    async fn fake_authorization_server(calls: Arc<AtomicUsize>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+
+        let discovery = {
+            let base = base.clone();
+            move || async move {
+                Json(json!({ "introspection_endpoint": format!("{base}/introspect") }))
+            }
+        };
+        let introspect = move |headers: HeaderMap, Form(form): Form<HashMap<String, String>>| {
+            calls.fetch_add(1, Ordering::SeqCst);
+
+            async move {
+                // "my-api:secret" in base64.
+                if headers.get(AUTHORIZATION).unwrap() != "Basic bXktYXBpOnNlY3JldA==" {
+                    return Err(StatusCode::UNAUTHORIZED);
+                }
+
+                Ok(Json(match form["token"].as_str() {
+                    "opaque-active" => json!({
+                        "active": true,
+                        "sub": "user",
+                        "aud": "my-api",
+                        "iss": "https://auth.example.com",
+                        "exp": test_util::timestamp(60),
+                    }),
+                    _ => json!({ "active": false }),
+                }))
+            }
+        };
+
+        let router = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/introspect", post(introspect));
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        format!("{base}/.well-known/openid-configuration")
+    }
+
+    async fn status(router: &mut Router, token: &str) -> StatusCode {
+        let request = Request::builder()
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+
+        router.call(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn introspect_and_cache_active_tokens() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let oidc_url = fake_authorization_server(calls.clone()).await;
+        let introspection = Introspection::from_oidc_url(&oidc_url, "my-api", "secret")
+            .await
+            .unwrap();
+
+        let claims = introspection.introspect("opaque-active").await.unwrap();
+        assert_eq!("user", claims["sub"]);
+        assert!(introspection.introspect("opaque-active").await.is_ok());
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+
+        assert_eq!(
+            Err(TokenError::Inactive),
+            introspection.introspect("opaque-revoked").await
+        );
+        assert_eq!(
+            Err(TokenError::IntrospectionFailed),
+            Introspection::new(format!("{oidc_url}/missing"), "my-api", "secret")
+                .introspect("opaque-active")
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn always_mode_skips_cache() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let oidc_url = fake_authorization_server(calls.clone()).await;
+        let introspection = Introspection::from_oidc_url(&oidc_url, "my-api", "secret")
+            .await
+            .unwrap()
+            .mode(IntrospectionMode::Always);
+
+        for _ in 0..2 {
+            assert!(introspection.introspect("opaque-active").await.is_ok());
+        }
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn check_audience_and_issuer() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let oidc_url = fake_authorization_server(calls.clone()).await;
+        let introspection = Introspection::from_oidc_url(&oidc_url, "my-api", "secret")
+            .await
+            .unwrap();
+
+        assert!(introspection
+            .clone()
+            .audience(["my-api"])
+            .issuer(["https://auth.example.com"])
+            .introspect("opaque-active")
+            .await
+            .is_ok());
+        assert!(matches!(
+            introspection
+                .clone()
+                .audience(["other-api"])
+                .introspect("opaque-active")
+                .await,
+            Err(TokenError::Invalid(_))
+        ));
+        assert!(matches!(
+            introspection
+                .clone()
+                .issuer(["https://evil.example.com"])
+                .introspect("opaque-active")
+                .await,
+            Err(TokenError::Invalid(_))
+        ));
+
+        // Options in the request's extensions override the introspection's.
+        let mut router = Router::new()
+            .route("/", get(|_: Claims<TestClaims>| async {}))
+            .layer(Extension(
+                ValidationOptions::default().audience(["other-api"]),
+            ))
+            .layer(Extension(introspection.audience(["my-api"])))
+            .with_state(test_util::jwks(None));
+
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            status(&mut router, "opaque-active").await
+        );
+    }
+
+    #[tokio::test]
+    async fn extractors_fall_back_to_introspection() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let oidc_url = fake_authorization_server(calls.clone()).await;
+        let introspection = Introspection::from_oidc_url(&oidc_url, "my-api", "secret")
+            .await
+            .unwrap();
+        let handler = |Claims(claims): Claims<TestClaims>| async move { claims.sub };
+        let jwt = test_util::sign(&json!({ "sub": "user", "exp": test_util::timestamp(60) }));
+
+        let mut router = Router::new()
+            .route("/", get(handler))
+            .layer(Extension(introspection.clone()))
+            .with_state(test_util::jwks(None));
+
+        assert_eq!(StatusCode::OK, status(&mut router, "opaque-active").await);
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            status(&mut router, "opaque-revoked").await
+        );
+        assert_eq!(StatusCode::OK, status(&mut router, &jwt).await);
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+
+        // The introspection endpoint doesn't know about the JWT.
+        let mut router = Router::new()
+            .route("/", get(handler))
+            .layer(Extension(introspection.mode(IntrospectionMode::Always)))
+            .with_state(test_util::jwks(None));
+
+        assert_eq!(StatusCode::UNAUTHORIZED, status(&mut router, &jwt).await);
+    }
+}
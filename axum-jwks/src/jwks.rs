@@ -1,15 +1,17 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::HashMap, str::FromStr, sync::Arc, time::SystemTime};
 
+use axum::body::Bytes;
 use jsonwebtoken::{
     decode, decode_header,
     jwk::{self, AlgorithmParameters, KeyAlgorithm},
-    DecodingKey, TokenData, Validation,
+    DecodingKey, Header, TokenData, Validation,
 };
 use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::value::RawValue;
 use thiserror::Error;
 use tracing::{debug, info, warn};
 
-use crate::TokenError;
+use crate::{RawClaims, TokenError};
 
 /// A container for a set of JWT decoding keys.
 ///
@@ -17,11 +19,12 @@ use crate::TokenError;
 /// through the `kid` attribute in the token's header.
 #[derive(Clone)]
 pub struct Jwks {
-    keys: HashMap<String, Jwk>,
+    keys: Arc<HashMap<String, Jwk>>,
 }
 
 #[derive(Deserialize)]
 struct Oid {
+    issuer: Option<String>,
     jwks_uri: String,
     id_token_signing_alg_values_supported: Option<Vec<String>>,
 }
@@ -48,8 +51,26 @@ impl Jwks {
         oidc_url: &str,
         audience: Option<&str>,
     ) -> Result<Self, JwksError> {
+        Self::discover(client, oidc_url, audience, |_, request| request).await
+    }
+
+    /// Discover and fetch a key set, letting `prepare` amend each request
+    /// for the URL it is sent to, for example to add credentials.
+    pub(crate) async fn discover<F>(
+        client: &reqwest::Client,
+        oidc_url: &str,
+        audience: Option<&str>,
+        prepare: F,
+    ) -> Result<Self, JwksError>
+    where
+        F: Fn(&str, reqwest::RequestBuilder) -> reqwest::RequestBuilder,
+    {
         debug!(%oidc_url, "Fetching openid-configuration.");
-        let oidc = client.get(oidc_url).send().await?.json::<Oid>().await?;
+        let oidc = prepare(oidc_url, client.get(oidc_url))
+            .send()
+            .await?
+            .json::<Oid>()
+            .await?;
         let jwks_uri = oidc.jwks_uri;
         let alg = match &oidc.id_token_signing_alg_values_supported {
             Some(algs) => match algs.first() {
@@ -59,7 +80,12 @@ impl Jwks {
             _ => None,
         };
 
-        Self::from_jwks_url_with_client(&reqwest::Client::default(), &jwks_uri, audience, alg).await
+        let mut jwks = Self::fetch(client, &jwks_uri, audience, alg, prepare).await?;
+        for key in Arc::make_mut(&mut jwks.keys).values_mut() {
+            key.info.issuer.clone_from(&oidc.issuer);
+        }
+
+        Ok(jwks)
     }
 
     ///
@@ -88,15 +114,37 @@ impl Jwks {
         audience: Option<&str>,
         alg: Option<jsonwebtoken::Algorithm>,
     ) -> Result<Self, JwksError> {
+        Self::fetch(client, jwks_url, audience, alg, |_, request| request).await
+    }
+
+    async fn fetch<F>(
+        client: &reqwest::Client,
+        jwks_url: &str,
+        audience: Option<&str>,
+        alg: Option<jsonwebtoken::Algorithm>,
+        prepare: F,
+    ) -> Result<Self, JwksError>
+    where
+        F: Fn(&str, reqwest::RequestBuilder) -> reqwest::RequestBuilder,
+    {
         debug!(%jwks_url, "Fetching JSON Web Key Set.");
-        let jwks: jwk::JwkSet = client.get(jwks_url).send().await?.json().await?;
+        let jwks: jwk::JwkSet = prepare(jwks_url, client.get(jwks_url))
+            .send()
+            .await?
+            .json()
+            .await?;
         info!(
             %jwks_url,
             count = jwks.keys.len(),
             "Successfully pulled JSON Web Key Set."
         );
 
-        Self::from_jwk_set(jwks, audience, alg)
+        let mut jwks = Self::from_jwk_set(jwks, audience, alg)?;
+        for key in Arc::make_mut(&mut jwks.keys).values_mut() {
+            key.info.jwks_url = Some(jwks_url.to_owned());
+        }
+
+        Ok(jwks)
     }
 
     ///
@@ -115,6 +163,7 @@ impl Jwks {
         alg: Option<jsonwebtoken::Algorithm>,
     ) -> Result<Self, JwksError> {
         let mut keys = HashMap::new();
+        let fetched_at = SystemTime::now();
         let to_supported_alg = |key_algorithm: Option<KeyAlgorithm>| match key_algorithm {
             Some(key_alg) => jsonwebtoken::Algorithm::from_str(key_alg.to_string().as_str()).ok(),
             _ => None,
@@ -124,27 +173,12 @@ impl Jwks {
             if let Some(key_alg) = to_supported_alg(jwk.common.key_algorithm).or(alg) {
                 let kid = jwk.common.key_id.ok_or(JwkError::MissingKeyId)?;
 
-                match &jwk.algorithm {
+                let decoding_key = match &jwk.algorithm {
                     AlgorithmParameters::RSA(rsa) => {
-                        let decoding_key = DecodingKey::from_rsa_components(&rsa.n, &rsa.e)
-                            .map_err(|err| JwkError::DecodingError {
-                                key_id: kid.clone(),
-                                error: err,
-                            })?;
-                        let mut validation = Validation::new(key_alg);
-                        if let Some(audience) = audience {
-                            validation.set_audience(&[audience.to_string()]);
-                        } else {
-                            validation.validate_aud = false;
-                        }
-
-                        keys.insert(
-                            kid,
-                            Jwk {
-                                decoding: decoding_key,
-                                validation,
-                            },
-                        );
+                        DecodingKey::from_rsa_components(&rsa.n, &rsa.e)
+                    }
+                    AlgorithmParameters::EllipticCurve(ec) => {
+                        DecodingKey::from_ec_components(&ec.x, &ec.y)
                     }
                     other => {
                         return Err(JwkError::UnexpectedAlgorithm {
@@ -154,6 +188,33 @@ impl Jwks {
                         .into())
                     }
                 }
+                .map_err(|err| JwkError::DecodingError {
+                    key_id: kid.clone(),
+                    error: err,
+                })?;
+
+                let mut validation = Validation::new(key_alg);
+                if let Some(audience) = audience {
+                    validation.set_audience(&[audience.to_string()]);
+                } else {
+                    validation.validate_aud = false;
+                }
+
+                let info = KeyInfo {
+                    key_id: kid.clone(),
+                    algorithm: key_alg,
+                    issuer: None,
+                    jwks_url: None,
+                    fetched_at,
+                };
+                keys.insert(
+                    kid,
+                    Jwk {
+                        decoding: decoding_key,
+                        validation,
+                        info,
+                    },
+                );
             } else {
                 warn!(
                     "JWK key algorithm {:?} is not supported. Tokens signed by that key will not be accepted.",
@@ -162,10 +223,76 @@ impl Jwks {
             }
         }
 
-        Ok(Self { keys })
+        Ok(Self {
+            keys: Arc::new(keys),
+        })
+    }
+
+    /// Require tokens to be issued by one of the given issuers.
+    ///
+    /// The `iss` claim of every token validated by this key set will be
+    /// matched against the provided values.
+    pub fn set_issuer<T: ToString>(&mut self, issuers: &[T]) {
+        for key in Arc::make_mut(&mut self.keys).values_mut() {
+            key.validation.set_issuer(issuers);
+        }
+    }
+
+    /// Whether both key sets are clones of the same keys, without any
+    /// changes made to either since.
+    pub(crate) fn same_keys(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.keys, &other.keys)
+    }
+
+    /// Describe the key with the given `kid`.
+    ///
+    /// Returns `None` if the key set does not contain the key.
+    pub fn key_info(&self, kid: &str) -> Option<&KeyInfo> {
+        self.keys.get(kid).map(|key| &key.info)
     }
 
     pub fn validate_claims<T>(&self, token: &str) -> Result<TokenData<T>, TokenError>
+    where
+        T: DeserializeOwned,
+    {
+        self.validate_claims_with(token, &ValidationOptions::default())
+    }
+
+    /// Validate a token, overriding parts of the validation configured when
+    /// the key set was built.
+    ///
+    /// This allows validating tokens for several audiences or issuers with a
+    /// single key set.
+    ///
+    /// # Example
+    /// ```
+    /// use axum_jwks::{Jwks, TokenError, ValidationOptions};
+    /// use serde_json::Value;
+    ///
+    /// fn validate(jwks: &Jwks, token: &str) -> Result<Value, TokenError> {
+    ///     let options = ValidationOptions::default().audience(["https://api.a.com"]);
+    ///
+    ///     Ok(jwks.validate_claims_with(token, &options)?.claims)
+    /// }
+    /// ```
+    pub fn validate_claims_with<T>(
+        &self,
+        token: &str,
+        options: &ValidationOptions,
+    ) -> Result<TokenData<T>, TokenError>
+    where
+        T: DeserializeOwned,
+    {
+        self.decode(token, options).map(|(data, _)| data)
+    }
+
+    /// Validate a token, returning its data and the leeway it was validated
+    /// with.
+    fn decode<T>(
+        &self,
+        token: &str,
+        options: &ValidationOptions,
+    ) -> Result<(TokenData<T>, u64), TokenError>
     where
         T: DeserializeOwned,
     {
@@ -186,21 +313,210 @@ impl Jwks {
             TokenError::UnknownKeyId(kid.to_owned())
         })?;
 
+        let validation = options.apply(&key.validation);
         let decoded_token: TokenData<T> =
-            decode(token, &key.decoding, &key.validation).map_err(|error| {
+            decode(token, &key.decoding, &validation).map_err(|error| {
                 debug!(?error, "Token is malformed or does not pass validation.");
 
                 TokenError::Invalid(error)
             })?;
 
-        Ok(decoded_token)
+        Ok((decoded_token, validation.leeway))
+    }
+
+    /// Validate a token without deserializing its claims.
+    ///
+    /// The returned [`RawClaims`] own the decoded payload, so claims can be
+    /// deserialized into types that borrow from it instead of copying every
+    /// string.
+    ///
+    /// # Example
+    /// ```
+    /// use std::borrow::Cow;
+    ///
+    /// use axum_jwks::{Jwks, TokenError};
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Peek<'a> {
+    ///     #[serde(borrow)]
+    ///     sub: Cow<'a, str>,
+    /// }
+    ///
+    /// fn subject(jwks: &Jwks, token: &str) -> Result<String, TokenError> {
+    ///     let raw = jwks.validate_raw_claims(token)?;
+    ///     let claims: Peek = raw.deserialize()?;
+    ///
+    ///     Ok(claims.sub.into_owned())
+    /// }
+    /// ```
+    pub fn validate_raw_claims(&self, token: &str) -> Result<RawClaims, TokenError> {
+        self.validate_raw_claims_with(token, &ValidationOptions::default())
+    }
+
+    /// A version of [`validate_raw_claims`][Self::validate_raw_claims] that
+    /// overrides parts of the validation like
+    /// [`validate_claims_with`][Self::validate_claims_with].
+    pub fn validate_raw_claims_with(
+        &self,
+        token: &str,
+        options: &ValidationOptions,
+    ) -> Result<RawClaims, TokenError> {
+        self.validate_raw(token, options).map(|(_, raw, _)| raw)
+    }
+
+    /// Validate a token, keeping its header, its payload and the leeway it
+    /// was validated with.
+    pub(crate) fn validate_raw(
+        &self,
+        token: &str,
+        options: &ValidationOptions,
+    ) -> Result<(Header, RawClaims, u64), TokenError> {
+        let (data, leeway) = self.decode::<Box<RawValue>>(token, options)?;
+        let payload: Box<str> = data.claims.into();
+
+        Ok((
+            data.header,
+            RawClaims::new(Bytes::from(payload.into_string())),
+            leeway,
+        ))
+    }
+}
+
+/// Overrides for the validation a [`Jwks`] performs on a single token.
+///
+/// Options that are not set keep the value configured when the key set was
+/// built.
+///
+/// When inserted into a request's extensions, for example with
+/// [`Extension`][axum::Extension] or
+/// [`AudienceLayer`][crate::AudienceLayer], the options are used by every
+/// extractor in this crate that validates the request's token.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ValidationOptions {
+    audience: Option<Vec<String>>,
+    issuer: Option<Vec<String>>,
+    leeway: Option<u64>,
+    relaxed: bool,
+}
+
+impl ValidationOptions {
+    /// Require the `aud` claim to contain one of the given audiences. Tokens
+    /// without an `aud` claim are rejected.
+    pub fn audience<I, A>(mut self, audience: I) -> Self
+    where
+        I: IntoIterator<Item = A>,
+        A: Into<String>,
+    {
+        self.audience = Some(audience.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Require the `iss` claim to be one of the given issuers.
+    pub fn issuer<I, A>(mut self, issuer: I) -> Self
+    where
+        I: IntoIterator<Item = A>,
+        A: Into<String>,
+    {
+        self.issuer = Some(issuer.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Allow the given number of seconds of clock skew when checking `exp`
+    /// and `nbf`.
+    pub fn leeway(mut self, seconds: u64) -> Self {
+        self.leeway = Some(seconds);
+        self
+    }
+
+    pub(crate) fn audiences(&self) -> Option<&[String]> {
+        self.audience.as_deref()
+    }
+
+    pub(crate) fn issuers(&self) -> Option<&[String]> {
+        self.issuer.as_deref()
+    }
+
+    pub(crate) fn configured_leeway(&self) -> Option<u64> {
+        self.leeway
+    }
+
+    /// Don't require the `aud` and `exp` claims.
+    ///
+    /// This is for tokens that are not access tokens, such as status lists.
+    pub(crate) fn relaxed(mut self) -> Self {
+        self.relaxed = true;
+        self
+    }
+
+    fn apply(&self, validation: &Validation) -> Validation {
+        let mut validation = validation.clone();
+
+        if let Some(audience) = &self.audience {
+            validation.set_audience(audience);
+            validation.validate_aud = true;
+            validation.required_spec_claims.insert("aud".to_owned());
+        }
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(issuer);
+        }
+        if let Some(leeway) = self.leeway {
+            validation.leeway = leeway;
+        }
+        if self.relaxed {
+            validation.validate_aud = false;
+            validation.required_spec_claims.remove("exp");
+        }
+
+        validation
     }
 }
 
+/// Decode a token's claims without verifying its signature or any claims.
+///
+/// This must only be used to decide how a token should be verified, never to
+/// trust its contents.
+pub(crate) fn decode_unverified<T>(token: &str) -> Result<T, TokenError>
+where
+    T: DeserializeOwned,
+{
+    let header = decode_header(token).map_err(TokenError::InvalidHeader)?;
+    let mut validation = Validation::new(header.alg);
+    validation.insecure_disable_signature_validation();
+    validation.validate_aud = false;
+    validation.validate_exp = false;
+    validation.required_spec_claims.clear();
+
+    decode(token, &DecodingKey::from_secret(&[]), &validation)
+        .map(|data| data.claims)
+        .map_err(TokenError::Invalid)
+}
+
 #[derive(Clone)]
 struct Jwk {
     decoding: DecodingKey,
     validation: Validation,
+    info: KeyInfo,
+}
+
+/// Metadata about a key in a [`Jwks`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyInfo {
+    /// The key's `kid` attribute.
+    pub key_id: String,
+
+    /// The algorithm tokens signed by the key must use.
+    pub algorithm: jsonwebtoken::Algorithm,
+
+    /// The issuer advertised by the openid-configuration the key set was
+    /// discovered from.
+    pub issuer: Option<String>,
+
+    /// The URL the key set was fetched from.
+    pub jwks_url: Option<String>,
+
+    /// When the key set was fetched or built.
+    pub fetched_at: SystemTime,
 }
 
 /// An error with the overall set of JSON Web Keys.
@@ -216,8 +532,30 @@ pub enum JwksError {
     #[error("there was an error with an individual key: {0}")]
     KeyError(#[from] JwkError),
 
+    /// The openid-configuration does not advertise a required endpoint.
+    #[error("the openid-configuration does not contain {0:?}")]
+    MissingEndpoint(&'static str),
+
     #[error("the provided algorithm from oidc is invalid or empty: {0}")]
     InvalidAlgorithm(#[from] jsonwebtoken::errors::Error),
+
+    /// A local file needed to reach the authority, such as a CA bundle or
+    /// credential, could not be read.
+    #[error("could not read local configuration: {0}")]
+    ReadError(#[from] std::io::Error),
+
+    /// An environment variable needed to reach the authority is not set.
+    #[error("the environment variable {0} is not set")]
+    MissingEnvironmentVariable(&'static str),
+
+    /// The credential for the authority cannot be sent in a header.
+    #[error("the credential is not a valid header value")]
+    InvalidCredential,
+
+    /// A key set document provided by the caller, such as a trust bundle, is
+    /// not valid JSON or does not have the expected structure.
+    #[error("the key set document is malformed: {0}")]
+    InvalidDocument(#[from] serde_json::Error),
 }
 
 /// An error with a specific key from a JWKS.
@@ -240,7 +578,7 @@ pub enum JwkError {
     MissingKeyId,
 
     /// The key uses an unexpected algorithm type.
-    #[error("the key {key_id:?} uses a non-RSA algorithm {algorithm:?}")]
+    #[error("the key {key_id:?} uses an unsupported algorithm {algorithm:?}")]
     UnexpectedAlgorithm {
         algorithm: AlgorithmParameters,
         key_id: String,
@@ -0,0 +1,602 @@
+use std::{collections::HashSet, env, fmt, fs, path::PathBuf};
+
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::debug;
+
+use crate::{
+    claims::{accept_token, verified_token},
+    Jwks, JwksError, ParseTokenClaims, TokenError,
+};
+
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+const DEFAULT_ISSUER: &str = "https://kubernetes.default.svc.cluster.local";
+
+/// The location of a cluster's service account signing keys.
+///
+/// Kubernetes publishes the keys used to sign service account tokens through
+/// the API server's OIDC discovery endpoint. Reaching that endpoint usually
+/// requires trusting the cluster CA and authenticating with a service account
+/// token of our own.
+#[derive(Clone, Debug)]
+pub struct KubernetesKeySource {
+    api_server: String,
+    ca_certificate: Option<Vec<u8>>,
+    bearer_token: Option<BearerToken>,
+    issuer: String,
+}
+
+#[derive(Clone)]
+enum BearerToken {
+    Static(String),
+    File(PathBuf),
+}
+
+impl fmt::Debug for BearerToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // The token is a credential, so it is never printed.
+            Self::Static(_) => f.write_str("Static(..)"),
+            Self::File(path) => f.debug_tuple("File").field(path).finish(),
+        }
+    }
+}
+
+impl KubernetesKeySource {
+    /// Use the API server at the given base URL without any credentials.
+    pub fn new(api_server: impl Into<String>) -> Self {
+        Self {
+            api_server: api_server.into().trim_end_matches('/').to_owned(),
+            ca_certificate: None,
+            bearer_token: None,
+            issuer: DEFAULT_ISSUER.to_owned(),
+        }
+    }
+
+    /// Use the API server and credentials available to a pod.
+    ///
+    /// The API server address is read from the `KUBERNETES_SERVICE_HOST` and
+    /// `KUBERNETES_SERVICE_PORT` environment variables. The CA bundle and
+    /// token are read from the mounted service account directory. The token
+    /// is re-read by every call to [`Jwks::from_kubernetes`] so that rotated
+    /// tokens are picked up.
+    pub fn in_cluster() -> Result<Self, JwksError> {
+        let dir = PathBuf::from(SERVICE_ACCOUNT_DIR);
+        let host = env::var("KUBERNETES_SERVICE_HOST")
+            .map_err(|_| JwksError::MissingEnvironmentVariable("KUBERNETES_SERVICE_HOST"))?;
+        let port = env::var("KUBERNETES_SERVICE_PORT").unwrap_or_else(|_| "443".to_owned());
+        let host = if host.contains(':') {
+            format!("[{host}]")
+        } else {
+            host
+        };
+
+        Ok(Self::new(format!("https://{host}:{port}"))
+            .with_ca_certificate(fs::read(dir.join("ca.crt"))?)
+            .with_bearer_token_file(dir.join("token")))
+    }
+
+    /// Trust the given PEM encoded CA certificate when connecting to the API
+    /// server.
+    pub fn with_ca_certificate(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.ca_certificate = Some(pem.into());
+        self
+    }
+
+    /// Authenticate to the API server with a fixed token.
+    pub fn with_bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.bearer_token = Some(BearerToken::Static(token.into()));
+        self
+    }
+
+    /// Authenticate to the API server with the token stored in a file.
+    ///
+    /// The file is read by every call to [`Jwks::from_kubernetes`].
+    pub fn with_bearer_token_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.bearer_token = Some(BearerToken::File(path.into()));
+        self
+    }
+
+    /// Expect tokens to be issued by the given issuer.
+    ///
+    /// This must match the API server's `--service-account-issuer` flag. It
+    /// defaults to `https://kubernetes.default.svc.cluster.local`.
+    pub fn with_issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuer = issuer.into();
+        self
+    }
+
+    fn client(&self) -> Result<reqwest::Client, JwksError> {
+        let mut builder = reqwest::Client::builder();
+
+        if let Some(pem) = &self.ca_certificate {
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(pem)?);
+        }
+
+        Ok(builder.build()?)
+    }
+
+    /// The `Authorization` header for requests to the API server.
+    fn authorization(&self) -> Result<Option<reqwest::header::HeaderValue>, JwksError> {
+        let Some(token) = &self.bearer_token else {
+            return Ok(None);
+        };
+
+        let token = match token {
+            BearerToken::Static(token) => token.clone(),
+            BearerToken::File(path) => fs::read_to_string(path)?,
+        };
+        let mut value = reqwest::header::HeaderValue::from_str(&format!("Bearer {}", token.trim()))
+            .map_err(|_| JwksError::InvalidCredential)?;
+        value.set_sensitive(true);
+
+        Ok(Some(value))
+    }
+
+    /// Whether a URL points at the API server.
+    fn is_api_server(&self, url: &str) -> bool {
+        url.strip_prefix(&self.api_server)
+            .is_some_and(|path| path.is_empty() || path.starts_with(['/', '?']))
+    }
+}
+
+impl Jwks {
+    /// Pull the service account signing keys from a Kubernetes API server.
+    ///
+    /// The bearer token of the source is only sent to the API server itself,
+    /// never to a `jwks_uri` on another host, as used by managed clusters.
+    ///
+    /// # Arguments
+    /// * `source` - The API server and credentials used to reach it.
+    /// * `audience` - The audience the projected tokens were requested for.
+    ///   This will be matched against the `aud` claim from the token.
+    pub async fn from_kubernetes(
+        source: &KubernetesKeySource,
+        audience: Option<&str>,
+    ) -> Result<Self, JwksError> {
+        let client = source.client()?;
+        let authorization = source.authorization()?;
+        let oidc_url = format!("{}/.well-known/openid-configuration", source.api_server);
+
+        let mut jwks =
+            Self::discover(
+                &client,
+                &oidc_url,
+                audience,
+                |url, request| match &authorization {
+                    Some(value) if source.is_api_server(url) => {
+                        request.header(reqwest::header::AUTHORIZATION, value.clone())
+                    }
+                    _ => request,
+                },
+            )
+            .await?;
+        jwks.set_issuer(&[&source.issuer]);
+
+        Ok(jwks)
+    }
+}
+
+/// The claims of a projected service account token.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct KubernetesClaims {
+    pub iss: String,
+
+    /// The service account in the form
+    /// `system:serviceaccount:NAMESPACE:NAME`.
+    pub sub: String,
+
+    pub aud: Vec<String>,
+    pub exp: u64,
+    pub iat: u64,
+
+    #[serde(default)]
+    pub nbf: Option<u64>,
+
+    #[serde(rename = "kubernetes.io")]
+    pub kubernetes: KubernetesIdentity,
+}
+
+/// The Kubernetes specific part of a service account token.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct KubernetesIdentity {
+    pub namespace: String,
+    pub serviceaccount: ObjectReference,
+
+    /// The pod the token is bound to, if any.
+    #[serde(default)]
+    pub pod: Option<ObjectReference>,
+
+    /// The secret the token is bound to, if any.
+    #[serde(default)]
+    pub secret: Option<ObjectReference>,
+
+    /// The node the token's pod is scheduled on, if any.
+    #[serde(default)]
+    pub node: Option<ObjectReference>,
+}
+
+/// A reference to a Kubernetes object by name and UID.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ObjectReference {
+    pub name: String,
+    pub uid: String,
+}
+
+impl<S: Send + Sync> ParseTokenClaims<S> for KubernetesClaims {
+    type Rejection = KubernetesRejection;
+}
+
+/// Restrictions on which service accounts may call an application.
+///
+/// An empty policy accepts any service account that presents a valid token.
+#[derive(Clone, Debug, Default)]
+pub struct ServiceAccountPolicy {
+    namespaces: HashSet<String>,
+    service_accounts: HashSet<(String, String)>,
+    require_pod_binding: bool,
+}
+
+impl ServiceAccountPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow every service account in the given namespace.
+    pub fn allow_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespaces.insert(namespace.into());
+        self
+    }
+
+    /// Allow a single service account.
+    pub fn allow_service_account(
+        mut self,
+        namespace: impl Into<String>,
+        name: impl Into<String>,
+    ) -> Self {
+        self.service_accounts
+            .insert((namespace.into(), name.into()));
+        self
+    }
+
+    /// Only accept tokens that are bound to a pod.
+    ///
+    /// Bound tokens are invalidated by the API server when the pod is
+    /// deleted, which long-lived secret based tokens are not.
+    pub fn require_pod_binding(mut self) -> Self {
+        self.require_pod_binding = true;
+        self
+    }
+
+    /// Check the claims of an already validated token against the policy.
+    pub fn check(&self, claims: &KubernetesClaims) -> Result<(), ServiceAccountError> {
+        let identity = &claims.kubernetes;
+        let namespace = &identity.namespace;
+        let name = &identity.serviceaccount.name;
+
+        if claims.sub != format!("system:serviceaccount:{namespace}:{name}") {
+            return Err(ServiceAccountError::SubjectMismatch(claims.sub.clone()));
+        }
+
+        if self.require_pod_binding && identity.pod.is_none() {
+            return Err(ServiceAccountError::NotPodBound);
+        }
+
+        let restricted = !self.namespaces.is_empty() || !self.service_accounts.is_empty();
+        let allowed = self.namespaces.contains(namespace)
+            || self
+                .service_accounts
+                .contains(&(namespace.clone(), name.clone()));
+
+        if restricted && !allowed {
+            return Err(ServiceAccountError::NotAllowed {
+                namespace: namespace.clone(),
+                name: name.clone(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// A reason a valid service account token was refused by a
+/// [`ServiceAccountPolicy`].
+#[derive(Debug, Error, PartialEq)]
+pub enum ServiceAccountError {
+    /// The `sub` claim does not name the service account in the
+    /// `kubernetes.io` claims.
+    #[error("the subject {0:?} does not match the service account claims")]
+    SubjectMismatch(String),
+
+    /// The token is not bound to a pod.
+    #[error("the token is not bound to a pod")]
+    NotPodBound,
+
+    /// The service account is not in the allowlist.
+    #[error("the service account {namespace}/{name} is not allowed")]
+    NotAllowed { namespace: String, name: String },
+}
+
+/// A service account whose token was validated and accepted by the
+/// [`ServiceAccountPolicy`] in the application state.
+pub struct ServiceAccount(pub KubernetesClaims);
+
+/// The rejection for [`KubernetesClaims`] and [`ServiceAccount`].
+#[derive(Debug, Error, PartialEq)]
+pub enum KubernetesRejection {
+    #[error(transparent)]
+    Token(#[from] TokenError),
+
+    #[error(transparent)]
+    Forbidden(#[from] ServiceAccountError),
+}
+
+impl IntoResponse for KubernetesRejection {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Token(error) => error.into_response(),
+            Self::Forbidden(_) => StatusCode::FORBIDDEN.into_response(),
+        }
+    }
+}
+
+impl<S> FromRequestParts<S> for ServiceAccount
+where
+    Jwks: FromRef<S>,
+    ServiceAccountPolicy: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = KubernetesRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let verified = verified_token(parts, state).await?;
+        let claims: KubernetesClaims = verified.raw().deserialize()?;
+
+        ServiceAccountPolicy::from_ref(state)
+            .check(&claims)
+            .inspect_err(|error| debug!(%error, "Service account was refused."))?;
+
+        accept_token(parts, &verified)?;
+
+        Ok(Self(claims))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::{header, HeaderMap, Request},
+        routing::get,
+        Json, Router,
+    };
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::test_util;
+
+    const ISSUER: &str = "https://kubernetes.default.svc.cluster.local";
+
+    fn claims(namespace: &str, name: &str, pod: bool) -> serde_json::Value {
+        let mut identity = json!({
+            "namespace": namespace,
+            "serviceaccount": { "name": name, "uid": "sa-uid" },
+        });
+        if pod {
+            identity["pod"] = json!({ "name": "pod-1", "uid": "pod-uid" });
+        }
+
+        json!({
+            "iss": ISSUER,
+            "sub": format!("system:serviceaccount:{namespace}:{name}"),
+            "aud": ["my-service"],
+            "exp": test_util::timestamp(60),
+            "iat": test_util::timestamp(0),
+            "kubernetes.io": identity,
+        })
+    }
+
+    fn parse(value: serde_json::Value) -> KubernetesClaims {
+        serde_json::from_value(value).unwrap()
+    }
+
+    /// Serve a stand-in for the API server's discovery endpoints that
+    /// requires the given bearer token, advertising `jwks_uri` or its own key
+    /// set.
+    async fn fake_api_server(token: &'static str, jwks_uri: Option<String>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+
+        let authorized = move |headers: HeaderMap| {
+            headers
+                .get(header::AUTHORIZATION)
+                .is_some_and(|value| value == format!("Bearer {token}").as_str())
+        };
+        let discovery = {
+            let base = base.clone();
+            move |headers: HeaderMap| async move {
+                if !authorized(headers) {
+                    return Err(StatusCode::UNAUTHORIZED);
+                }
+
+                Ok(Json(json!({
+                    "issuer": ISSUER,
+                    "jwks_uri": jwks_uri.unwrap_or_else(|| format!("{base}/openid/v1/jwks")),
+                    "id_token_signing_alg_values_supported": ["RS256"],
+                })))
+            }
+        };
+        let jwks = move |headers: HeaderMap| async move {
+            if !authorized(headers) {
+                return Err(StatusCode::UNAUTHORIZED);
+            }
+
+            Ok(Json(test_util::jwk_set()))
+        };
+
+        let router = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/openid/v1/jwks", get(jwks));
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        base
+    }
+
+    #[tokio::test]
+    async fn fetch_keys_from_api_server() {
+        let base = fake_api_server("reader-token", None).await;
+        let source = KubernetesKeySource::new(base).with_bearer_token("reader-token");
+
+        let jwks = Jwks::from_kubernetes(&source, Some("my-service"))
+            .await
+            .unwrap();
+        let token = test_util::sign(&claims("default", "client", true));
+
+        let claims = jwks.validate_claims::<KubernetesClaims>(&token).unwrap();
+
+        assert_eq!("default", claims.claims.kubernetes.namespace);
+    }
+
+    #[tokio::test]
+    async fn token_is_not_sent_to_external_jwks_uri() {
+        // A public key set on another host, like the ones of managed clusters.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let jwks_uri = format!("http://{}/openid/v1/jwks", listener.local_addr().unwrap());
+        let public_jwks = |headers: HeaderMap| async move {
+            if headers.contains_key(header::AUTHORIZATION) {
+                return Err(StatusCode::BAD_REQUEST);
+            }
+
+            Ok(Json(test_util::jwk_set()))
+        };
+        let router = Router::new().route("/openid/v1/jwks", get(public_jwks));
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        let base = fake_api_server("reader-token", Some(jwks_uri)).await;
+        let source = KubernetesKeySource::new(base).with_bearer_token("reader-token");
+
+        assert!(Jwks::from_kubernetes(&source, Some("my-service"))
+            .await
+            .is_ok());
+    }
+
+    #[test]
+    fn only_api_server_urls_are_authenticated() {
+        let source = KubernetesKeySource::new("https://10.0.0.1:443/");
+
+        assert!(source.is_api_server("https://10.0.0.1:443/openid/v1/jwks"));
+        assert!(!source.is_api_server("https://10.0.0.1:4430/openid/v1/jwks"));
+        assert!(!source.is_api_server("https://oidc.eks.amazonaws.com/keys"));
+    }
+
+    #[tokio::test]
+    async fn credentials_are_not_printed_or_sent_malformed() {
+        let source = KubernetesKeySource::new("https://10.0.0.1").with_bearer_token("secret");
+
+        assert!(!format!("{source:?}").contains("secret"));
+
+        let source = KubernetesKeySource::new("https://10.0.0.1").with_bearer_token("a\nb");
+
+        assert!(matches!(
+            Jwks::from_kubernetes(&source, None).await,
+            Err(JwksError::InvalidCredential)
+        ));
+    }
+
+    #[tokio::test]
+    async fn fetch_keys_requires_credentials() {
+        let base = fake_api_server("reader-token", None).await;
+        let source = KubernetesKeySource::new(base);
+
+        let result = Jwks::from_kubernetes(&source, Some("my-service")).await;
+
+        assert!(matches!(result, Err(JwksError::FetchError(_))));
+    }
+
+    #[test]
+    fn policy_allowlists() {
+        let policy = ServiceAccountPolicy::new()
+            .allow_namespace("trusted")
+            .allow_service_account("default", "client");
+
+        assert_eq!(
+            Ok(()),
+            policy.check(&parse(claims("trusted", "any", false)))
+        );
+        assert_eq!(
+            Ok(()),
+            policy.check(&parse(claims("default", "client", false)))
+        );
+        assert_eq!(
+            Err(ServiceAccountError::NotAllowed {
+                namespace: "default".to_owned(),
+                name: "other".to_owned(),
+            }),
+            policy.check(&parse(claims("default", "other", false)))
+        );
+    }
+
+    #[test]
+    fn policy_pod_binding_and_subject() {
+        let policy = ServiceAccountPolicy::new().require_pod_binding();
+
+        assert_eq!(Ok(()), policy.check(&parse(claims("default", "a", true))));
+        assert_eq!(
+            Err(ServiceAccountError::NotPodBound),
+            policy.check(&parse(claims("default", "a", false)))
+        );
+
+        let mut mismatched = parse(claims("default", "a", true));
+        mismatched.sub = "system:serviceaccount:default:b".to_owned();
+        assert!(matches!(
+            policy.check(&mismatched),
+            Err(ServiceAccountError::SubjectMismatch(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn service_account_extractor() {
+        #[derive(Clone)]
+        struct State {
+            jwks: Jwks,
+            policy: ServiceAccountPolicy,
+        }
+
+        impl FromRef<State> for Jwks {
+            fn from_ref(state: &State) -> Self {
+                state.jwks.clone()
+            }
+        }
+
+        impl FromRef<State> for ServiceAccountPolicy {
+            fn from_ref(state: &State) -> Self {
+                state.policy.clone()
+            }
+        }
+
+        let mut jwks = test_util::jwks(Some("my-service"));
+        jwks.set_issuer(&[ISSUER]);
+        let state = State {
+            jwks,
+            policy: ServiceAccountPolicy::new().allow_namespace("trusted"),
+        };
+
+        for (namespace, allowed) in [("trusted", true), ("other", false)] {
+            let token = test_util::sign(&claims(namespace, "client", true));
+            let request = Request::builder()
+                .header(header::AUTHORIZATION, format!("Bearer {token}"))
+                .body(())
+                .unwrap();
+            let (mut parts, _) = request.into_parts();
+
+            let result = ServiceAccount::from_request_parts(&mut parts, &state).await;
+
+            assert_eq!(allowed, result.is_ok(), "namespace {namespace}");
+        }
+    }
+}
@@ -36,7 +36,7 @@
 //!     pub sub: String
 //! }
 //!
-//! impl ParseTokenClaims for TokenClaims {
+//! impl ParseTokenClaims<AppState> for TokenClaims {
 //!     type Rejection = TokenClaimsError;
 //! }
 //!
@@ -91,10 +91,95 @@
 //! In case a JWK uses an unsupported key algorithm this is logged as warning but otherwise ignored.
 //! Tokens signed by that key will *not* be valid.
 
+// Lets code generated by the derive macro refer to `::axum_jwks` in this
+// crate's own tests.
+extern crate self as axum_jwks;
+
+mod audience;
+mod checks;
 mod claims;
+mod csrf;
+mod dpop;
+mod expression;
+mod github;
+mod introspection;
 mod jwks;
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+mod kubernetes;
+mod locator;
+mod mtls;
+mod policy;
+mod principal;
+mod proxy;
+mod raw;
+mod registered;
+mod replay;
+mod roles;
+mod scope;
+mod spiffe;
+mod status;
+mod tenant;
+#[cfg(test)]
+mod test_util;
 mod token;
+mod userinfo;
+mod verified;
+#[cfg(feature = "ws")]
+mod ws;
 
+pub use audience::{AudienceLayer, AudienceService};
+#[cfg(feature = "derive")]
+pub use axum_jwks_derive::ParseTokenClaims;
+pub use checks::ClaimsRejection;
 pub use claims::{Claims, ParseTokenClaims};
-pub use jwks::{JwkError, Jwks, JwksError};
+pub use csrf::CsrfProtection;
+pub use dpop::{jwk_thumbprint, DPoP, DPoPRejection, DPoPVerifier};
+pub use expression::{EvaluationError, Expression, ExpressionError};
+pub use github::{
+    GitHubActions, GitHubActionsClaims, GitHubActionsMismatch, GitHubActionsPolicy,
+    GitHubActionsPolicyError, GitHubActionsRejection, GitHubActionsRule, GITHUB_ACTIONS_ISSUER,
+};
+pub use introspection::{Introspection, IntrospectionMode};
+pub use jwks::{JwkError, Jwks, JwksError, KeyInfo, ValidationOptions};
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+pub use kubernetes::{
+    KubernetesClaims, KubernetesIdentity, KubernetesKeySource, KubernetesRejection,
+    ObjectReference, ServiceAccount, ServiceAccountError, ServiceAccountPolicy,
+};
+pub use locator::{Ambiguity, TokenLocator, TokenSource};
+pub use mtls::{
+    certificate_thumbprint, CertificateBinding, CertificateBindingRejection, CertificateBound,
+    ClientCertificate, ForwardedCertificate,
+};
+pub use policy::{
+    all_of, any_of, claim_contains, claim_equals, claim_matches_path_param, has_scope, not,
+    predicate, BoxPolicy, Denial, Policy, PolicyLayer, PolicyRejection, PolicyRequest,
+    PolicyService,
+};
+pub use principal::{
+    Auth0, Azure, ClaimsAdapter, Cognito, Keycloak, OAuthScopes, Okta, Principal, PrincipalAdapter,
+};
+pub use proxy::{
+    CfAccessClaims, IapAudience, IapClaims, CF_ACCESS_JWT_ASSERTION, GOOGLE_IAP_JWT_ASSERTION,
+};
+pub use raw::RawClaims;
+pub use registered::{Actor, Confirmation, RegisteredClaims};
+pub use replay::{InMemoryReplayStore, ReplayGuard, ReplayStore};
+pub use roles::{Permissions, RoleDefinition, RoleGraph, RoleGraphBuilder, RoleGraphError};
+pub use scope::{RequireScopes, Scope, ScopeRejection, ScopeSet};
+pub use spiffe::{JwtSvid, SpiffeBundles, SpiffeId, SpiffeIdError, SpiffeRejection};
+pub use status::{FailureMode, StatusListChecker};
+pub use tenant::{TenantBinding, TenantRejection, TenantScoped, TenantSource};
 pub use token::{Token, TokenError};
+pub use userinfo::{Enriched, UserInfo, UserInfoRejection};
+pub use verified::Verified;
+#[cfg(feature = "ws")]
+pub use ws::{AuthenticatedUpgrade, TokenExpiry, WebSocketRejection, WEBSOCKET_BEARER_PROTOCOL};
+
+/// Items used by code generated by the derive macro. Not public API.
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::checks::{
+        check_audience, check_issuer, check_one_of, check_scopes, checked_claims, OneOf,
+    };
+}
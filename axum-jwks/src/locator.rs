@@ -0,0 +1,338 @@
+use axum::{
+    extract::Query,
+    http::{
+        header::{AUTHORIZATION, COOKIE},
+        request::Parts,
+        HeaderName,
+    },
+};
+use tracing::debug;
+
+use serde_json::Value;
+
+use crate::{CsrfProtection, Token, TokenError};
+
+/// A place in a request a token can be read from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TokenSource {
+    /// A bearer token in the `Authorization` header.
+    Bearer,
+
+    /// The raw value of a custom header, e.g. `X-Api-Token`.
+    Header(HeaderName),
+
+    /// The value of a cookie.
+    Cookie(String),
+
+    /// The value of a query parameter.
+    Query(String),
+}
+
+impl TokenSource {
+    fn values(&self, parts: &Parts) -> Vec<String> {
+        let values: Vec<String> = match self {
+            Self::Bearer => parts
+                .headers
+                .get_all(AUTHORIZATION)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .filter_map(|value| value.split_once(' '))
+                .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+                .map(|(_, token)| token.to_owned())
+                .collect(),
+            Self::Header(name) => parts
+                .headers
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .map(str::to_owned)
+                .collect(),
+            Self::Cookie(name) => cookie_values(parts, name),
+            Self::Query(name) => Query::<Vec<(String, String)>>::try_from_uri(&parts.uri)
+                .map(|Query(params)| {
+                    params
+                        .into_iter()
+                        .filter(|(key, _)| key == name)
+                        .map(|(_, value)| value)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        };
+
+        values
+            .into_iter()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty())
+            .collect()
+    }
+}
+
+/// The values of all cookies with the given name.
+pub(crate) fn cookie_values(parts: &Parts, name: &str) -> Vec<String> {
+    parts
+        .headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.split_once('='))
+        .filter(|(key, _)| key.trim() == name)
+        .map(|(_, value)| value.trim().trim_matches('"').to_owned())
+        .collect()
+}
+
+/// How a [`TokenLocator`] handles requests that carry more than one token.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Ambiguity {
+    /// Use the token from the first source that provides one.
+    FirstMatch,
+
+    /// Reject the request if the tokens found are not all identical.
+    #[default]
+    RejectConflicting,
+
+    /// Reject the request if more than one token is found, even if they are
+    /// identical.
+    RejectMultiple,
+}
+
+/// Finds the token in a request by checking an ordered list of sources.
+///
+/// By default, only bearer tokens in the `Authorization` header are accepted.
+/// To use a different configuration, insert a locator into the request's
+/// extensions with [`Extension`][axum::Extension]. It is then used by the
+/// [`Token`] extractor and every extractor built on top of it, such as
+/// [`Claims`][crate::Claims].
+///
+/// # Example
+/// ```
+/// use axum::{http::HeaderName, routing::get, Extension, Router};
+/// use axum_jwks::{Ambiguity, TokenLocator};
+///
+/// let locator = TokenLocator::new()
+///     .bearer()
+///     .header(HeaderName::from_static("x-api-token"))
+///     .cookie("access_token")
+///     .query("access_token")
+///     .on_ambiguity(Ambiguity::RejectMultiple);
+///
+/// let router: Router = Router::new()
+///     .route("/events", get(|| async { "ok" }))
+///     .layer(Extension(locator));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenLocator {
+    sources: Vec<TokenSource>,
+    ambiguity: Ambiguity,
+    csrf: Option<CsrfProtection>,
+}
+
+impl TokenLocator {
+    /// A locator without any sources.
+    pub fn new() -> Self {
+        Self {
+            sources: Vec::new(),
+            ambiguity: Ambiguity::default(),
+            csrf: None,
+        }
+    }
+
+    /// The locator in the request's extensions, or the default locator.
+    pub(crate) fn from_extensions(parts: &Parts) -> Self {
+        parts.extensions.get::<Self>().cloned().unwrap_or_default()
+    }
+
+    /// Look for a token in the given source after all previously added
+    /// sources.
+    pub fn source(mut self, source: TokenSource) -> Self {
+        self.sources.push(source);
+        self
+    }
+
+    /// Look for a bearer token in the `Authorization` header.
+    pub fn bearer(self) -> Self {
+        self.source(TokenSource::Bearer)
+    }
+
+    /// Look for a token in a custom header.
+    pub fn header(self, name: HeaderName) -> Self {
+        self.source(TokenSource::Header(name))
+    }
+
+    /// Look for a token in a cookie.
+    pub fn cookie(self, name: impl Into<String>) -> Self {
+        self.source(TokenSource::Cookie(name.into()))
+    }
+
+    /// Look for a token in a query parameter.
+    pub fn query(self, name: impl Into<String>) -> Self {
+        self.source(TokenSource::Query(name.into()))
+    }
+
+    /// Set how requests with more than one token are handled.
+    pub fn on_ambiguity(mut self, ambiguity: Ambiguity) -> Self {
+        self.ambiguity = ambiguity;
+        self
+    }
+
+    /// Protect tokens read from cookies against cross-site request forgery.
+    pub fn csrf(mut self, csrf: CsrfProtection) -> Self {
+        self.csrf = Some(csrf);
+        self
+    }
+
+    /// Find the token in a request.
+    pub fn locate(&self, parts: &Parts) -> Result<Token, TokenError> {
+        self.locate_with_source(parts).map(|(_, token)| token)
+    }
+
+    /// Find the token in a request along with the source it was read from.
+    pub(crate) fn locate_with_source(
+        &self,
+        parts: &Parts,
+    ) -> Result<(&TokenSource, Token), TokenError> {
+        let mut found = self.sources.iter().flat_map(|source| {
+            source
+                .values(parts)
+                .into_iter()
+                .map(move |value| (source, value))
+        });
+
+        let (source, token) = found.next().ok_or(TokenError::Missing)?;
+
+        let ambiguous = match self.ambiguity {
+            Ambiguity::FirstMatch => false,
+            Ambiguity::RejectConflicting => found.any(|(_, other)| other != token),
+            Ambiguity::RejectMultiple => found.next().is_some(),
+        };
+
+        if ambiguous {
+            debug!("Request contains more than one token.");
+
+            return Err(TokenError::Ambiguous);
+        }
+
+        if let (TokenSource::Cookie(_), Some(csrf)) = (source, &self.csrf) {
+            csrf.check_request(parts)?;
+        }
+
+        Ok((source, Token::new(token)))
+    }
+
+    /// Whether tokens read from cookies are protected against cross-site
+    /// request forgery.
+    pub(crate) fn checks_csrf(&self) -> bool {
+        self.csrf.is_some()
+    }
+
+    /// Finish CSRF checks that depend on the validated claims of a token.
+    pub(crate) fn check_csrf_claims(
+        &self,
+        source: &TokenSource,
+        parts: &Parts,
+        claims: &Value,
+    ) -> Result<(), TokenError> {
+        match (source, &self.csrf) {
+            (TokenSource::Cookie(_), Some(csrf)) => csrf.check_claims(parts, claims),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Default for TokenLocator {
+    /// A locator that only accepts bearer tokens.
+    fn default() -> Self {
+        Self::new().bearer()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        extract::FromRequestParts,
+        http::{header::AUTHORIZATION, Request},
+    };
+
+    use super::*;
+
+    fn parts(uri: &str, headers: &[(&str, &str)]) -> Parts {
+        let mut request = Request::builder().uri(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+
+        request.body(()).unwrap().into_parts().0
+    }
+
+    fn locator() -> TokenLocator {
+        TokenLocator::new()
+            .bearer()
+            .header(HeaderName::from_static("x-api-token"))
+            .cookie("access_token")
+            .query("access_token")
+    }
+
+    #[test]
+    fn locate_from_each_source() {
+        let cases = [
+            parts("/", &[("authorization", "bearer token")]),
+            parts("/", &[("x-api-token", "token")]),
+            parts("/", &[("cookie", "theme=dark; access_token=token")]),
+            parts("/?other=1&access_token=token", &[]),
+        ];
+
+        for parts in cases {
+            assert_eq!(Ok(Token::new("token".to_owned())), locator().locate(&parts));
+        }
+
+        assert_eq!(
+            Err(TokenError::Missing),
+            locator().locate(&parts("/", &[("authorization", "Basic abc")]))
+        );
+    }
+
+    #[test]
+    fn ambiguous_requests() {
+        let same = parts(
+            "/?access_token=a",
+            &[("authorization", "Bearer a"), ("x-api-token", "a")],
+        );
+        let conflicting = parts("/?access_token=b", &[("authorization", "Bearer a")]);
+
+        assert!(locator().locate(&same).is_ok());
+        assert_eq!(Err(TokenError::Ambiguous), locator().locate(&conflicting));
+
+        let first_match = locator().on_ambiguity(Ambiguity::FirstMatch);
+        assert_eq!(
+            Ok(Token::new("a".to_owned())),
+            first_match.locate(&conflicting)
+        );
+
+        let reject_multiple = locator().on_ambiguity(Ambiguity::RejectMultiple);
+        assert_eq!(Err(TokenError::Ambiguous), reject_multiple.locate(&same));
+    }
+
+    #[tokio::test]
+    async fn token_extractor_uses_locator_extension() {
+        let mut parts = parts("/?access_token=token", &[]);
+
+        assert_eq!(
+            Err(TokenError::Missing),
+            Token::from_request_parts(&mut parts, &()).await
+        );
+
+        parts
+            .extensions
+            .insert(TokenLocator::new().query("access_token"));
+        assert_eq!(
+            Ok(Token::new("token".to_owned())),
+            Token::from_request_parts(&mut parts, &()).await
+        );
+
+        parts
+            .headers
+            .insert(AUTHORIZATION, "Bearer token".parse().unwrap());
+        parts.extensions.insert(TokenLocator::default());
+        assert!(Token::from_request_parts(&mut parts, &()).await.is_ok());
+    }
+}
@@ -0,0 +1,563 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::{header::WWW_AUTHENTICATE, request::Parts, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use ring::digest::{digest, SHA256};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use thiserror::Error;
+use tracing::debug;
+
+use crate::{
+    claims::{accept_token, verified_token},
+    Jwks, ParseTokenClaims, TokenError,
+};
+
+/// The DER encoded certificate a client presented during the TLS handshake.
+///
+/// When axum terminates TLS itself, insert this into the request's extensions
+/// so that [`CertificateBound`] can use it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientCertificate(pub Vec<u8>);
+
+/// Where a proxy that terminates TLS forwards the client certificate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ForwardedCertificate {
+    /// A header containing the certificate, either URL encoded PEM (e.g.
+    /// `X-Client-Cert` from nginx's `$ssl_client_escaped_cert`) or a byte
+    /// sequence of the DER encoding as defined in RFC 9440 (`Client-Cert`).
+    Header(HeaderName),
+
+    /// Envoy's `X-Forwarded-Client-Cert` header. The element added by the
+    /// nearest proxy is used, from its `Cert` or `Hash` field.
+    Xfcc,
+}
+
+/// How certificate-bound access tokens as defined in [RFC 8705] are checked.
+///
+/// Make this available from the application state with
+/// [`FromRef`][axum::extract::FromRef] to use the [`CertificateBound`]
+/// extractor.
+///
+/// By default, only a [`ClientCertificate`] in the request's extensions is
+/// used. Certificates forwarded by a proxy are only trusted if the request
+/// comes directly from a trusted proxy, which requires the server to provide
+/// [`ConnectInfo<SocketAddr>`][axum::extract::ConnectInfo].
+///
+/// [RFC 8705]: https://www.rfc-editor.org/rfc/rfc8705
+///
+/// # Example
+/// ```
+/// use axum_jwks::{CertificateBinding, ForwardedCertificate};
+///
+/// let binding = CertificateBinding::new()
+///     .forwarded(ForwardedCertificate::Xfcc)
+///     .trusted_proxy("10.0.0.0/8")
+///     .trusted_proxy("::1");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CertificateBinding {
+    forwarded: Option<ForwardedCertificate>,
+    trusted_proxies: Vec<(IpAddr, u8)>,
+    trust_any_proxy: bool,
+}
+
+impl CertificateBinding {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read certificates forwarded by a trusted proxy.
+    pub fn forwarded(mut self, forwarded: ForwardedCertificate) -> Self {
+        self.forwarded = Some(forwarded);
+        self
+    }
+
+    /// Trust forwarded certificates from the given address or network, e.g.
+    /// `10.0.0.1` or `10.0.0.0/8`.
+    ///
+    /// # Panics
+    /// If `proxy` is not a valid address or network.
+    pub fn trusted_proxy(mut self, proxy: &str) -> Self {
+        let (address, prefix) = match proxy.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (proxy, None),
+        };
+        let address: IpAddr = address.parse().expect("invalid proxy address");
+        let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix
+            .map(|prefix| prefix.parse().expect("invalid proxy network prefix"))
+            .unwrap_or(max_prefix);
+        assert!(prefix <= max_prefix, "invalid proxy network prefix");
+
+        self.trusted_proxies.push((address, prefix));
+        self
+    }
+
+    /// Trust forwarded certificates from any peer.
+    ///
+    /// Only use this if the server cannot be reached without going through
+    /// the proxy, which must remove the certificate header from incoming
+    /// requests.
+    pub fn trust_any_proxy(mut self) -> Self {
+        self.trust_any_proxy = true;
+        self
+    }
+
+    /// The SHA-256 thumbprint of the request's client certificate.
+    pub fn client_thumbprint(&self, parts: &Parts) -> Option<String> {
+        if let Some(ClientCertificate(der)) = parts.extensions.get() {
+            return Some(certificate_thumbprint(der));
+        }
+
+        let forwarded = self.forwarded.as_ref()?;
+
+        if !self.is_trusted_proxy(parts) {
+            debug!("Ignoring forwarded certificate from an untrusted peer.");
+
+            return None;
+        }
+
+        match forwarded {
+            ForwardedCertificate::Header(name) => {
+                let value = parts.headers.get(name)?.to_str().ok()?;
+
+                decode_certificate(value).map(|der| certificate_thumbprint(&der))
+            }
+            ForwardedCertificate::Xfcc => {
+                let value = parts
+                    .headers
+                    .get(HeaderName::from_static("x-forwarded-client-cert"))?
+                    .to_str()
+                    .ok()?;
+
+                xfcc_thumbprint(value)
+            }
+        }
+    }
+
+    fn is_trusted_proxy(&self, parts: &Parts) -> bool {
+        if self.trust_any_proxy {
+            return true;
+        }
+
+        let Some(ConnectInfo(peer)) = parts.extensions.get::<ConnectInfo<SocketAddr>>() else {
+            return false;
+        };
+
+        self.trusted_proxies
+            .iter()
+            .any(|(network, prefix)| in_network(peer.ip(), *network, *prefix))
+    }
+}
+
+fn in_network(address: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    let (address, network, bits) = match (address.to_canonical(), network) {
+        (IpAddr::V4(address), IpAddr::V4(network)) => {
+            (u32::from(address) as u128, u32::from(network) as u128, 32)
+        }
+        (IpAddr::V6(address), IpAddr::V6(network)) => {
+            (u128::from(address), u128::from(network), 128)
+        }
+        _ => return false,
+    };
+
+    let shift = bits - u32::from(prefix);
+    shift >= bits || address >> shift == network >> shift
+}
+
+/// The base64url encoded SHA-256 thumbprint of a DER encoded certificate, as
+/// used in the `cnf.x5t#S256` claim.
+pub fn certificate_thumbprint(der: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(digest(&SHA256, der))
+}
+
+/// Decode a forwarded certificate in any of the supported encodings.
+fn decode_certificate(value: &str) -> Option<Vec<u8>> {
+    let value = value.trim();
+
+    if let Some(der) = value
+        .strip_prefix(':')
+        .and_then(|value| value.strip_suffix(':'))
+    {
+        return STANDARD.decode(der).ok();
+    }
+
+    let pem = percent_decode(value)?;
+    let body: String = pem
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("-----"))
+        .collect();
+
+    STANDARD.decode(body).ok()
+}
+
+/// The thumbprint from the last element of an `X-Forwarded-Client-Cert`
+/// header.
+fn xfcc_thumbprint(value: &str) -> Option<String> {
+    let element = split_unquoted(value, ',').pop()?;
+    let mut hash = None;
+
+    for pair in split_unquoted(&element, ';') {
+        let Some((key, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"');
+
+        match key.trim().to_ascii_lowercase().as_str() {
+            "cert" => return decode_certificate(value).map(|der| certificate_thumbprint(&der)),
+            "hash" => hash = hex_decode(value),
+            _ => {}
+        }
+    }
+
+    hash.map(|hash| URL_SAFE_NO_PAD.encode(hash))
+}
+
+/// Split a value on a separator that is not inside double quotes.
+fn split_unquoted(value: &str, separator: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for c in value.chars() {
+        let current = parts.last_mut().expect("parts are never empty");
+
+        match c {
+            _ if escaped => {
+                escaped = false;
+                current.push(c);
+            }
+            '\\' if quoted => {
+                escaped = true;
+                current.push(c);
+            }
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            _ if c == separator && !quoted => parts.push(String::new()),
+            _ => current.push(c),
+        }
+    }
+
+    parts
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' => {
+                let hex = std::str::from_utf8(bytes.get(index + 1..index + 3)?).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                index += 3;
+            }
+            byte => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+fn hex_decode(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..value.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(value.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+/// Claims of an access token that is bound to the request's client
+/// certificate.
+///
+/// The token is validated with the [`Jwks`] from the application state, and
+/// its `cnf.x5t#S256` claim must match the thumbprint of the client
+/// certificate found with the [`CertificateBinding`] from the application
+/// state. The claims are then checked with [`ParseTokenClaims`] like
+/// [`Claims`][crate::Claims] does.
+#[derive(Debug)]
+pub struct CertificateBound<C = Value> {
+    pub claims: C,
+
+    /// The thumbprint of the client certificate.
+    pub thumbprint: String,
+}
+
+impl<S, C> FromRequestParts<S> for CertificateBound<C>
+where
+    C: DeserializeOwned + ParseTokenClaims<S> + Send,
+    Jwks: FromRef<S>,
+    CertificateBinding: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = CertificateBindingRejection<C::Rejection>;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let verified = verified_token(parts, state).await?;
+        let claims = verified.claims()?;
+        let thumbprint = CertificateBinding::from_ref(state)
+            .client_thumbprint(parts)
+            .ok_or(CertificateBindingRejection::MissingCertificate)?;
+
+        let bound_to = claims.pointer("/cnf/x5t#S256").and_then(Value::as_str);
+        if bound_to != Some(thumbprint.as_str()) {
+            debug!(?bound_to, %thumbprint, "Access token is not bound to the client certificate.");
+
+            return Err(CertificateBindingRejection::Mismatch);
+        }
+
+        C::check_claims(verified.raw()).map_err(CertificateBindingRejection::Claims)?;
+        let claims: C = verified.raw().deserialize()?;
+        claims
+            .validate(state)
+            .await
+            .map_err(CertificateBindingRejection::Claims)?;
+        accept_token(parts, &verified)?;
+
+        Ok(Self { claims, thumbprint })
+    }
+}
+
+/// The rejection for [`CertificateBound`].
+///
+/// `R` is the rejection of the claims type's [`ParseTokenClaims`]
+/// implementation.
+#[derive(Debug, Error, PartialEq)]
+pub enum CertificateBindingRejection<R = TokenError> {
+    #[error(transparent)]
+    Token(#[from] TokenError),
+
+    /// The claims were rejected by their [`ParseTokenClaims`] implementation.
+    #[error("the token's claims were rejected")]
+    Claims(R),
+
+    /// The request has no client certificate from a trusted source.
+    #[error("no client certificate was presented")]
+    MissingCertificate,
+
+    /// The access token is not bound to the client certificate.
+    #[error("the access token is not bound to the client certificate")]
+    Mismatch,
+}
+
+impl<R: IntoResponse> IntoResponse for CertificateBindingRejection<R> {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Token(error) => error.into_response(),
+            Self::Claims(rejection) => rejection.into_response(),
+            Self::MissingCertificate | Self::Mismatch => (
+                StatusCode::UNAUTHORIZED,
+                [(
+                    WWW_AUTHENTICATE,
+                    HeaderValue::from_static("Bearer error=\"invalid_token\""),
+                )],
+            )
+                .into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header::AUTHORIZATION, Request};
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_util;
+
+    const DER: &[u8] = b"not really a certificate";
+
+    fn pem() -> String {
+        format!(
+            "-----BEGIN CERTIFICATE-----\n{}\n-----END CERTIFICATE-----\n",
+            STANDARD.encode(DER)
+        )
+    }
+
+    fn url_encode(value: &str) -> String {
+        value
+            .bytes()
+            .map(|byte| match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' => (byte as char).to_string(),
+                _ => format!("%{byte:02X}"),
+            })
+            .collect()
+    }
+
+    fn parts(peer: &str, headers: &[(&str, String)]) -> Parts {
+        let token = test_util::sign(&json!({
+            "cnf": { "x5t#S256": certificate_thumbprint(DER) },
+            "exp": test_util::timestamp(60),
+        }));
+        let mut request = Request::builder()
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .extension(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+        for (name, value) in headers {
+            request = request.header(*name, value);
+        }
+
+        request.body(()).unwrap().into_parts().0
+    }
+
+    #[derive(Clone)]
+    struct State {
+        jwks: Jwks,
+        binding: CertificateBinding,
+    }
+
+    impl FromRef<State> for Jwks {
+        fn from_ref(state: &State) -> Self {
+            state.jwks.clone()
+        }
+    }
+
+    impl FromRef<State> for CertificateBinding {
+        fn from_ref(state: &State) -> Self {
+            state.binding.clone()
+        }
+    }
+
+    #[test]
+    fn forwarded_certificate_encodings() {
+        let thumbprint = Some(certificate_thumbprint(DER));
+        let header = CertificateBinding::new()
+            .forwarded(ForwardedCertificate::Header(HeaderName::from_static(
+                "x-client-cert",
+            )))
+            .trust_any_proxy();
+        let xfcc = CertificateBinding::new()
+            .forwarded(ForwardedCertificate::Xfcc)
+            .trust_any_proxy();
+        let hash: String = digest(&SHA256, DER)
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+
+        let cases = [
+            (&header, "x-client-cert", url_encode(&pem())),
+            (
+                &header,
+                "x-client-cert",
+                format!(":{}:", STANDARD.encode(DER)),
+            ),
+            (
+                &xfcc,
+                "x-forwarded-client-cert",
+                format!(
+                    "By=spiffe://a;Hash=00,By=spiffe://b;Subject=\"CN=c;O=d,e\";Cert=\"{}\"",
+                    url_encode(&pem())
+                ),
+            ),
+            (
+                &xfcc,
+                "x-forwarded-client-cert",
+                format!("By=spiffe://b;Hash={hash}"),
+            ),
+        ];
+
+        for (binding, name, value) in cases {
+            let parts = parts("127.0.0.1:1234", &[(name, value.clone())]);
+
+            assert_eq!(thumbprint, binding.client_thumbprint(&parts), "{value}");
+        }
+    }
+
+    #[test]
+    fn only_trust_configured_proxies() {
+        let binding = CertificateBinding::new()
+            .forwarded(ForwardedCertificate::Header(HeaderName::from_static(
+                "x-client-cert",
+            )))
+            .trusted_proxy("10.0.0.0/8")
+            .trusted_proxy("::1");
+        let header = [("x-client-cert", url_encode(&pem()))];
+
+        for (peer, trusted) in [
+            ("10.1.2.3:1234", true),
+            ("[::ffff:10.1.2.3]:1234", true),
+            ("[::1]:1234", true),
+            ("11.0.0.1:1234", false),
+            ("[::2]:1234", false),
+        ] {
+            assert_eq!(
+                trusted,
+                binding.client_thumbprint(&parts(peer, &header)).is_some(),
+                "{peer}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn certificate_bound_extractor() {
+        let state = State {
+            jwks: test_util::jwks(None),
+            binding: CertificateBinding::new(),
+        };
+
+        let mut bound = parts("127.0.0.1:1234", &[]);
+        bound.extensions.insert(ClientCertificate(DER.to_vec()));
+        let result = CertificateBound::<Value>::from_request_parts(&mut bound, &state).await;
+        assert_eq!(certificate_thumbprint(DER), result.unwrap().thumbprint);
+
+        let mut other = parts("127.0.0.1:1234", &[]);
+        other
+            .extensions
+            .insert(ClientCertificate(b"other".to_vec()));
+        let result = CertificateBound::<Value>::from_request_parts(&mut other, &state).await;
+        assert_eq!(CertificateBindingRejection::Mismatch, result.unwrap_err());
+
+        let mut missing = parts("127.0.0.1:1234", &[]);
+        let result = CertificateBound::<Value>::from_request_parts(&mut missing, &state).await;
+        assert_eq!(
+            CertificateBindingRejection::MissingCertificate,
+            result.unwrap_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn certificate_bound_claims_are_validated() {
+        #[derive(Debug, serde::Deserialize)]
+        struct Revoked {}
+
+        impl ParseTokenClaims<State> for Revoked {
+            type Rejection = TokenError;
+
+            async fn validate(&self, _state: &State) -> Result<(), Self::Rejection> {
+                Err(TokenError::Revoked)
+            }
+        }
+
+        let state = State {
+            jwks: test_util::jwks(None),
+            binding: CertificateBinding::new(),
+        };
+        let mut bound = parts("127.0.0.1:1234", &[]);
+        bound.extensions.insert(ClientCertificate(DER.to_vec()));
+
+        let result = CertificateBound::<Revoked>::from_request_parts(&mut bound, &state).await;
+
+        assert_eq!(
+            CertificateBindingRejection::Claims(TokenError::Revoked),
+            result.unwrap_err()
+        );
+    }
+}
@@ -0,0 +1,627 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    extract::{FromRequestParts, RawPathParams, Request},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde_json::Value;
+use thiserror::Error;
+use tower_layer::Layer;
+use tower_service::Service;
+use tracing::debug;
+
+use crate::{
+    claims::{accept_token, verified_token},
+    principal::string_list,
+    Jwks, TokenError,
+};
+
+/// A rule deciding whether a request with validated claims may proceed.
+///
+/// Most policies are built from the provided predicates such as
+/// [`claim_contains`] and combined with [`all_of`], [`any_of`] and [`not`].
+/// Implement this trait for checks that need more than the claims and
+/// request parts.
+pub trait Policy: Send + Sync + 'static {
+    /// Allow the request, or explain why it was denied.
+    fn evaluate(&self, request: &PolicyRequest<'_>) -> Result<(), Denial>;
+
+    /// A short human readable description used in denial reasons.
+    fn describe(&self) -> String;
+}
+
+/// What a [`Policy`] can inspect about a request.
+pub struct PolicyRequest<'a> {
+    /// The claims of the validated token.
+    pub claims: &'a Value,
+
+    /// The request's method, URI, headers and extensions.
+    pub parts: &'a Parts,
+
+    path_params: &'a [(String, String)],
+}
+
+impl<'a> PolicyRequest<'a> {
+    pub fn new(claims: &'a Value, parts: &'a Parts, path_params: &'a [(String, String)]) -> Self {
+        Self {
+            claims,
+            parts,
+            path_params,
+        }
+    }
+
+    /// A path parameter from the matched route.
+    pub fn path_param(&self, name: &str) -> Option<&str> {
+        self.path_params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// A claim, either by top-level name or by JSON pointer such as
+    /// `/realm_access/roles`.
+    pub fn claim(&self, claim: &str) -> Option<&Value> {
+        if claim.starts_with('/') {
+            self.claims.pointer(claim)
+        } else {
+            self.claims.get(claim)
+        }
+    }
+}
+
+/// Why a [`Policy`] denied a request.
+///
+/// Combinators keep the denials of the policies they are made of as
+/// `causes`, so the full reasoning can be logged.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Denial {
+    /// The description of the policy that denied the request.
+    pub policy: String,
+    pub reason: String,
+    pub causes: Vec<Denial>,
+}
+
+impl Denial {
+    pub fn new(policy: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            policy: policy.into(),
+            reason: reason.into(),
+            causes: Vec::new(),
+        }
+    }
+
+    fn with_causes(mut self, causes: Vec<Denial>) -> Self {
+        self.causes = causes;
+        self
+    }
+}
+
+impl fmt::Display for Denial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.policy, self.reason)?;
+
+        if !self.causes.is_empty() {
+            write!(f, " [")?;
+            for (index, cause) in self.causes.iter().enumerate() {
+                if index > 0 {
+                    write!(f, "; ")?;
+                }
+                write!(f, "{cause}")?;
+            }
+            write!(f, "]")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A type-erased [`Policy`] that can be cloned and combined.
+#[derive(Clone)]
+pub struct BoxPolicy(Arc<dyn Policy>);
+
+impl BoxPolicy {
+    pub fn new(policy: impl Policy) -> Self {
+        Self(Arc::new(policy))
+    }
+}
+
+impl Policy for BoxPolicy {
+    fn evaluate(&self, request: &PolicyRequest<'_>) -> Result<(), Denial> {
+        self.0.evaluate(request)
+    }
+
+    fn describe(&self) -> String {
+        self.0.describe()
+    }
+}
+
+impl fmt::Debug for BoxPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("BoxPolicy").field(&self.describe()).finish()
+    }
+}
+
+struct Predicate<F> {
+    description: String,
+    check: F,
+}
+
+impl<F> Policy for Predicate<F>
+where
+    F: Fn(&PolicyRequest<'_>) -> Result<(), String> + Send + Sync + 'static,
+{
+    fn evaluate(&self, request: &PolicyRequest<'_>) -> Result<(), Denial> {
+        (self.check)(request).map_err(|reason| Denial::new(self.describe(), reason))
+    }
+
+    fn describe(&self) -> String {
+        self.description.clone()
+    }
+}
+
+/// A policy from a closure.
+pub fn predicate<F>(description: impl Into<String>, check: F) -> BoxPolicy
+where
+    F: Fn(&PolicyRequest<'_>) -> bool + Send + Sync + 'static,
+{
+    BoxPolicy::new(Predicate {
+        description: description.into(),
+        check: move |request: &PolicyRequest<'_>| {
+            if check(request) {
+                Ok(())
+            } else {
+                Err("the predicate was not satisfied".to_owned())
+            }
+        },
+    })
+}
+
+/// Require a claim to equal a value.
+pub fn claim_equals(claim: impl Into<String>, value: impl Into<Value>) -> BoxPolicy {
+    let claim = claim.into();
+    let value = value.into();
+
+    BoxPolicy::new(Predicate {
+        description: format!("{claim} == {value}"),
+        check: move |request: &PolicyRequest<'_>| match request.claim(&claim) {
+            Some(actual) if *actual == value => Ok(()),
+            Some(actual) => Err(format!("`{claim}` is {actual}")),
+            None => Err(format!("`{claim}` is missing")),
+        },
+    })
+}
+
+/// Require a claim that is an array, or a space delimited string, to
+/// contain a value.
+pub fn claim_contains(claim: impl Into<String>, value: impl Into<String>) -> BoxPolicy {
+    let claim = claim.into();
+    let value = value.into();
+
+    BoxPolicy::new(Predicate {
+        description: format!("{claim} contains {value:?}"),
+        check: move |request: &PolicyRequest<'_>| {
+            if string_list(request.claim(&claim)).any(|item| item == value) {
+                Ok(())
+            } else {
+                Err(format!("`{claim}` does not contain {value:?}"))
+            }
+        },
+    })
+}
+
+/// Require the token to grant a scope through the `scope` or `scp` claim.
+pub fn has_scope(scope: impl Into<String>) -> BoxPolicy {
+    let scope = scope.into();
+
+    BoxPolicy::new(Predicate {
+        description: format!("has scope {scope:?}"),
+        check: move |request: &PolicyRequest<'_>| {
+            if string_list(request.claim("scope"))
+                .chain(string_list(request.claim("scp")))
+                .any(|granted| granted == scope)
+            {
+                Ok(())
+            } else {
+                Err(format!("the scope {scope:?} was not granted"))
+            }
+        },
+    })
+}
+
+/// Require a string claim to equal a path parameter of the matched route.
+pub fn claim_matches_path_param(claim: impl Into<String>, param: impl Into<String>) -> BoxPolicy {
+    let claim = claim.into();
+    let param = param.into();
+
+    BoxPolicy::new(Predicate {
+        description: format!("{claim} == path parameter {param:?}"),
+        check: move |request: &PolicyRequest<'_>| {
+            let expected = request
+                .path_param(&param)
+                .ok_or_else(|| format!("the route has no parameter {param:?}"))?;
+
+            match request.claim(&claim).and_then(Value::as_str) {
+                Some(actual) if actual == expected => Ok(()),
+                Some(actual) => Err(format!("`{claim}` is {actual:?}, expected {expected:?}")),
+                None => Err(format!("`{claim}` is missing")),
+            }
+        },
+    })
+}
+
+struct AllOf(Vec<BoxPolicy>);
+
+impl Policy for AllOf {
+    fn evaluate(&self, request: &PolicyRequest<'_>) -> Result<(), Denial> {
+        for policy in &self.0 {
+            policy.evaluate(request).map_err(|denial| {
+                Denial::new(self.describe(), "a required policy was not satisfied")
+                    .with_causes(vec![denial])
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        join_descriptions(&self.0, " AND ")
+    }
+}
+
+/// Require every policy to allow the request.
+///
+/// An empty list allows every request.
+pub fn all_of(policies: impl IntoIterator<Item = BoxPolicy>) -> BoxPolicy {
+    BoxPolicy::new(AllOf(policies.into_iter().collect()))
+}
+
+struct AnyOf(Vec<BoxPolicy>);
+
+impl Policy for AnyOf {
+    fn evaluate(&self, request: &PolicyRequest<'_>) -> Result<(), Denial> {
+        let mut causes = Vec::with_capacity(self.0.len());
+
+        for policy in &self.0 {
+            match policy.evaluate(request) {
+                Ok(()) => return Ok(()),
+                Err(denial) => causes.push(denial),
+            }
+        }
+
+        Err(Denial::new(self.describe(), "no alternative was satisfied").with_causes(causes))
+    }
+
+    fn describe(&self) -> String {
+        join_descriptions(&self.0, " OR ")
+    }
+}
+
+/// Require at least one policy to allow the request.
+///
+/// An empty list denies every request.
+pub fn any_of(policies: impl IntoIterator<Item = BoxPolicy>) -> BoxPolicy {
+    BoxPolicy::new(AnyOf(policies.into_iter().collect()))
+}
+
+struct Not(BoxPolicy);
+
+impl Policy for Not {
+    fn evaluate(&self, request: &PolicyRequest<'_>) -> Result<(), Denial> {
+        match self.0.evaluate(request) {
+            Ok(()) => Err(Denial::new(
+                self.describe(),
+                "the negated policy was satisfied",
+            )),
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("NOT ({})", self.0.describe())
+    }
+}
+
+/// Allow the request only if the policy denies it.
+pub fn not(policy: BoxPolicy) -> BoxPolicy {
+    BoxPolicy::new(Not(policy))
+}
+
+fn join_descriptions(policies: &[BoxPolicy], separator: &str) -> String {
+    let descriptions: Vec<_> = policies
+        .iter()
+        .map(|policy| format!("({})", policy.describe()))
+        .collect();
+
+    descriptions.join(separator)
+}
+
+/// A [`Layer`] that enforces a [`Policy`] on every request.
+///
+/// The bearer token is validated with the given [`Jwks`]. The validated
+/// claims are shared with [`Claims`][crate::Claims] extractors in the wrapped
+/// handlers, so the token is only validated once.
+///
+/// Apply the layer with
+/// [`Router::route_layer`][axum::Router::route_layer] so that path
+/// parameters of the matched route are available to the policy.
+///
+/// # Example
+/// ```no_run
+/// use axum::{routing::get, Router};
+/// use axum_jwks::{
+///     all_of, any_of, claim_contains, claim_matches_path_param, has_scope, Jwks, PolicyLayer,
+/// };
+///
+/// # async fn app(jwks: Jwks) -> Router {
+/// let policy = any_of([
+///     claim_contains("roles", "admin"),
+///     all_of([
+///         has_scope("owner"),
+///         claim_matches_path_param("tenant_id", "tenant"),
+///     ]),
+/// ]);
+///
+/// Router::new()
+///     .route("/tenants/{tenant}/settings", get(|| async { "ok" }))
+///     .route_layer(PolicyLayer::new(jwks, policy))
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct PolicyLayer {
+    jwks: Jwks,
+    policy: BoxPolicy,
+}
+
+impl PolicyLayer {
+    pub fn new(jwks: Jwks, policy: impl Policy) -> Self {
+        Self {
+            jwks,
+            policy: BoxPolicy::new(policy),
+        }
+    }
+}
+
+impl<I> Layer<I> for PolicyLayer {
+    type Service = PolicyService<I>;
+
+    fn layer(&self, inner: I) -> Self::Service {
+        PolicyService {
+            inner,
+            jwks: self.jwks.clone(),
+            policy: self.policy.clone(),
+        }
+    }
+}
+
+/// The [`Service`] created by [`PolicyLayer`].
+#[derive(Clone)]
+pub struct PolicyService<I> {
+    inner: I,
+    jwks: Jwks,
+    policy: BoxPolicy,
+}
+
+impl<I> Service<Request> for PolicyService<I>
+where
+    I: Service<Request, Response = Response> + Clone + Send + 'static,
+    I::Future: Send,
+{
+    type Response = Response;
+    type Error = I::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, I::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        // Take the service that was driven to readiness and leave a clone in
+        // its place.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let jwks = self.jwks.clone();
+        let policy = self.policy.clone();
+
+        Box::pin(async move {
+            let (mut parts, body) = request.into_parts();
+
+            if let Err(rejection) = authorize(&mut parts, &jwks, &policy).await {
+                return Ok(rejection.into_response());
+            }
+
+            inner.call(Request::from_parts(parts, body)).await
+        })
+    }
+}
+
+async fn authorize(
+    parts: &mut Parts,
+    jwks: &Jwks,
+    policy: &BoxPolicy,
+) -> Result<(), PolicyRejection> {
+    let verified = verified_token(parts, jwks).await?;
+    let claims = verified.claims()?;
+    let path_params: Vec<_> = match RawPathParams::from_request_parts(parts, &()).await {
+        Ok(params) => params
+            .iter()
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    policy
+        .evaluate(&PolicyRequest::new(&claims, parts, &path_params))
+        .inspect_err(|denial| debug!(%denial, "Request was denied by policy."))?;
+    accept_token(parts, &verified)?;
+
+    Ok(())
+}
+
+/// The response when [`PolicyLayer`] refuses a request.
+#[derive(Debug, Error, PartialEq)]
+pub enum PolicyRejection {
+    #[error(transparent)]
+    Token(#[from] TokenError),
+
+    /// The token is valid but the policy denied the request.
+    #[error("the request was denied: {0}")]
+    Denied(Denial),
+}
+
+impl From<Denial> for PolicyRejection {
+    fn from(denial: Denial) -> Self {
+        Self::Denied(denial)
+    }
+}
+
+impl IntoResponse for PolicyRejection {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Token(error) => error.into_response(),
+            Self::Denied(_) => StatusCode::FORBIDDEN.into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request},
+        routing::get,
+        Router,
+    };
+    use serde_json::json;
+    use tower_service::Service;
+
+    use super::*;
+    use crate::test_util;
+
+    fn evaluate(policy: &BoxPolicy, claims: Value, path: &[(&str, &str)]) -> Result<(), Denial> {
+        let parts = Request::new(()).into_parts().0;
+        let params: Vec<_> = path
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        policy.evaluate(&PolicyRequest::new(&claims, &parts, &params))
+    }
+
+    fn admin_or_tenant_owner() -> BoxPolicy {
+        any_of([
+            claim_contains("roles", "admin"),
+            all_of([
+                has_scope("owner"),
+                claim_matches_path_param("tenant_id", "tenant"),
+            ]),
+        ])
+    }
+
+    #[test]
+    fn combinators() {
+        let policy = admin_or_tenant_owner();
+        let tenant = [("tenant", "t1")];
+
+        assert_eq!(
+            Ok(()),
+            evaluate(&policy, json!({ "roles": ["admin"] }), &tenant)
+        );
+        assert_eq!(
+            Ok(()),
+            evaluate(
+                &policy,
+                json!({ "scope": "owner", "tenant_id": "t1" }),
+                &tenant
+            )
+        );
+        assert!(evaluate(
+            &policy,
+            json!({ "scope": "owner", "tenant_id": "t2" }),
+            &tenant
+        )
+        .is_err());
+
+        let policy = not(claim_equals("sub", "blocked"));
+        assert_eq!(Ok(()), evaluate(&policy, json!({ "sub": "user" }), &[]));
+        assert!(evaluate(&policy, json!({ "sub": "blocked" }), &[]).is_err());
+    }
+
+    #[test]
+    fn denial_reasons() {
+        let denial = evaluate(
+            &admin_or_tenant_owner(),
+            json!({ "scope": "owner", "tenant_id": "t2" }),
+            &[("tenant", "t1")],
+        )
+        .unwrap_err();
+
+        assert_eq!("no alternative was satisfied", denial.reason);
+        assert_eq!(2, denial.causes.len());
+        assert_eq!(
+            "`roles` does not contain \"admin\"",
+            denial.causes[0].reason
+        );
+        assert_eq!(
+            "`tenant_id` is \"t2\", expected \"t1\"",
+            denial.causes[1].causes[0].reason
+        );
+    }
+
+    #[test]
+    fn claim_by_pointer() {
+        let policy = claim_contains("/realm_access/roles", "admin");
+
+        assert_eq!(
+            Ok(()),
+            evaluate(
+                &policy,
+                json!({ "realm_access": { "roles": ["admin"] } }),
+                &[]
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn layer_enforces_policy() {
+        let mut router = Router::new()
+            .route("/tenants/{tenant}", get(|| async { "ok" }))
+            .route_layer(PolicyLayer::new(
+                test_util::jwks(None),
+                claim_matches_path_param("tenant_id", "tenant"),
+            ));
+        let token = test_util::sign(&json!({
+            "tenant_id": "t1",
+            "exp": test_util::timestamp(60),
+        }));
+
+        for (path, status) in [
+            ("/tenants/t1", StatusCode::OK),
+            ("/tenants/t2", StatusCode::FORBIDDEN),
+        ] {
+            let request = Request::builder()
+                .uri(path)
+                .header(AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap();
+
+            let response = router.call(request).await.unwrap();
+
+            assert_eq!(status, response.status(), "{path}");
+        }
+
+        let request = Request::builder()
+            .uri("/tenants/t1")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.call(request).await.unwrap();
+
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+    }
+}
@@ -0,0 +1,390 @@
+use std::{collections::BTreeSet, fmt, sync::Arc};
+
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use serde_json::{Map, Value};
+
+use crate::{
+    claims::{accept_token, verified_token},
+    Jwks, TokenError,
+};
+
+/// The authenticated caller, independent of the identity provider that issued
+/// their token.
+///
+/// The issuer and subject are always read from the standard `iss` and `sub`
+/// claims. Scopes, roles and the tenant are filled in by a
+/// [`ClaimsAdapter`] that knows where a specific provider puts them.
+///
+/// When used as an extractor, the token is validated with the [`Jwks`] from
+/// the application state and mapped with the [`PrincipalAdapter`] from the
+/// application state.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Principal {
+    pub issuer: Option<String>,
+    pub subject: Option<String>,
+    pub scopes: BTreeSet<String>,
+    pub roles: BTreeSet<String>,
+    pub tenant: Option<String>,
+
+    /// Every claim of the validated token.
+    pub claims: Map<String, Value>,
+}
+
+impl Principal {
+    /// Build a principal from the claims of an already validated token.
+    pub fn from_claims(claims: Map<String, Value>, adapter: &dyn ClaimsAdapter) -> Self {
+        let mut principal = Self {
+            issuer: string_claim(&claims, "iss"),
+            subject: string_claim(&claims, "sub"),
+            ..Default::default()
+        };
+        adapter.adapt(&claims, &mut principal);
+        principal.claims = claims;
+
+        principal
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.contains(scope)
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.contains(role)
+    }
+}
+
+/// Fills a [`Principal`] from the claims of a specific identity provider.
+///
+/// Adapters can be combined as a tuple, in which case each one is applied in
+/// order. Closures taking the claims and the principal are adapters too.
+pub trait ClaimsAdapter: Send + Sync {
+    fn adapt(&self, claims: &Map<String, Value>, principal: &mut Principal);
+}
+
+impl<F> ClaimsAdapter for F
+where
+    F: Fn(&Map<String, Value>, &mut Principal) + Send + Sync,
+{
+    fn adapt(&self, claims: &Map<String, Value>, principal: &mut Principal) {
+        self(claims, principal)
+    }
+}
+
+impl<A, B> ClaimsAdapter for (A, B)
+where
+    A: ClaimsAdapter,
+    B: ClaimsAdapter,
+{
+    fn adapt(&self, claims: &Map<String, Value>, principal: &mut Principal) {
+        self.0.adapt(claims, principal);
+        self.1.adapt(claims, principal);
+    }
+}
+
+/// Scopes from the `scope` and `scp` claims, as a space delimited string or
+/// an array.
+///
+/// This covers any provider following RFC 8693 or RFC 9068.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OAuthScopes;
+
+impl ClaimsAdapter for OAuthScopes {
+    fn adapt(&self, claims: &Map<String, Value>, principal: &mut Principal) {
+        principal.scopes.extend(string_list(claims.get("scope")));
+        principal.scopes.extend(string_list(claims.get("scp")));
+    }
+}
+
+/// Keycloak roles from `realm_access.roles` and `resource_access`.
+///
+/// Client roles are included as-is when restricted to a single client, and
+/// prefixed with the client ID (`client:role`) otherwise.
+#[derive(Clone, Debug, Default)]
+pub struct Keycloak {
+    client_id: Option<String>,
+}
+
+impl Keycloak {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only include client roles for the given client.
+    pub fn client(client_id: impl Into<String>) -> Self {
+        Self {
+            client_id: Some(client_id.into()),
+        }
+    }
+}
+
+impl ClaimsAdapter for Keycloak {
+    fn adapt(&self, claims: &Map<String, Value>, principal: &mut Principal) {
+        OAuthScopes.adapt(claims, principal);
+
+        principal.roles.extend(string_list(
+            claims
+                .get("realm_access")
+                .and_then(|realm| realm.get("roles")),
+        ));
+
+        let Some(Value::Object(resources)) = claims.get("resource_access") else {
+            return;
+        };
+        match &self.client_id {
+            Some(client_id) => principal.roles.extend(string_list(
+                resources.get(client_id).and_then(|r| r.get("roles")),
+            )),
+            None => {
+                for (client_id, resource) in resources {
+                    principal.roles.extend(
+                        string_list(resource.get("roles"))
+                            .map(|role| format!("{client_id}:{role}")),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Auth0 permissions, roles and organizations.
+///
+/// Permissions from RBAC are treated as scopes. Auth0 only includes roles in
+/// a namespaced custom claim added by an action, so the claim name must be
+/// configured to read them. The organization ID is used as the tenant.
+#[derive(Clone, Debug, Default)]
+pub struct Auth0 {
+    roles_claim: Option<String>,
+}
+
+impl Auth0 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read roles from the given custom claim, e.g.
+    /// `https://example.com/roles`.
+    pub fn roles_claim(mut self, claim: impl Into<String>) -> Self {
+        self.roles_claim = Some(claim.into());
+        self
+    }
+}
+
+impl ClaimsAdapter for Auth0 {
+    fn adapt(&self, claims: &Map<String, Value>, principal: &mut Principal) {
+        OAuthScopes.adapt(claims, principal);
+        principal
+            .scopes
+            .extend(string_list(claims.get("permissions")));
+
+        if let Some(claim) = &self.roles_claim {
+            principal.roles.extend(string_list(claims.get(claim)));
+        }
+        if let Some(org_id) = string_claim(claims, "org_id") {
+            principal.tenant = Some(org_id);
+        }
+    }
+}
+
+/// Okta scopes from `scp` and roles from the `groups` claim.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Okta;
+
+impl ClaimsAdapter for Okta {
+    fn adapt(&self, claims: &Map<String, Value>, principal: &mut Principal) {
+        OAuthScopes.adapt(claims, principal);
+        principal.roles.extend(string_list(claims.get("groups")));
+    }
+}
+
+/// Amazon Cognito scopes from `scope` and roles from `cognito:groups`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cognito;
+
+impl ClaimsAdapter for Cognito {
+    fn adapt(&self, claims: &Map<String, Value>, principal: &mut Principal) {
+        OAuthScopes.adapt(claims, principal);
+        principal
+            .roles
+            .extend(string_list(claims.get("cognito:groups")));
+    }
+}
+
+/// Microsoft Entra ID (Azure AD) app roles from `roles`, delegated scopes from
+/// `scp` and the directory ID from `tid` as the tenant.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Azure;
+
+impl ClaimsAdapter for Azure {
+    fn adapt(&self, claims: &Map<String, Value>, principal: &mut Principal) {
+        OAuthScopes.adapt(claims, principal);
+        principal.roles.extend(string_list(claims.get("roles")));
+
+        if let Some(tid) = string_claim(claims, "tid") {
+            principal.tenant = Some(tid);
+        }
+    }
+}
+
+/// The [`ClaimsAdapter`] used by the [`Principal`] extractor.
+///
+/// Make this available from the application state with
+/// [`FromRef`][axum::extract::FromRef]. The default adapter only reads
+/// [`OAuthScopes`].
+#[derive(Clone)]
+pub struct PrincipalAdapter(Arc<dyn ClaimsAdapter>);
+
+impl PrincipalAdapter {
+    pub fn new(adapter: impl ClaimsAdapter + 'static) -> Self {
+        Self(Arc::new(adapter))
+    }
+}
+
+impl Default for PrincipalAdapter {
+    fn default() -> Self {
+        Self::new(OAuthScopes)
+    }
+}
+
+impl fmt::Debug for PrincipalAdapter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PrincipalAdapter").finish_non_exhaustive()
+    }
+}
+
+impl<S> FromRequestParts<S> for Principal
+where
+    Jwks: FromRef<S>,
+    PrincipalAdapter: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = TokenError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let verified = verified_token(parts, state).await?;
+        let claims = verified.raw().deserialize()?;
+        accept_token(parts, &verified)?;
+
+        Ok(Self::from_claims(
+            claims,
+            PrincipalAdapter::from_ref(state).0.as_ref(),
+        ))
+    }
+}
+
+fn string_claim(claims: &Map<String, Value>, name: &str) -> Option<String> {
+    claims.get(name).and_then(Value::as_str).map(str::to_owned)
+}
+
+/// The strings in a claim that is either a space delimited string or an array
+/// of strings.
+pub(crate) fn string_list(value: Option<&Value>) -> impl Iterator<Item = String> + '_ {
+    let (words, items) = match value {
+        Some(Value::String(words)) => (Some(words.split_whitespace()), None),
+        Some(Value::Array(items)) => (None, Some(items.iter().filter_map(Value::as_str))),
+        _ => (None, None),
+    };
+
+    words
+        .into_iter()
+        .flatten()
+        .chain(items.into_iter().flatten())
+        .map(str::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn principal(claims: Value, adapter: &dyn ClaimsAdapter) -> Principal {
+        let Value::Object(claims) = claims else {
+            unreachable!()
+        };
+
+        Principal::from_claims(claims, adapter)
+    }
+
+    fn set(items: &[&str]) -> BTreeSet<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn oauth_scopes_string_and_array() {
+        let p = principal(
+            json!({ "iss": "i", "sub": "s", "scope": "a b", "scp": ["c"] }),
+            &OAuthScopes,
+        );
+
+        assert_eq!(Some("i".to_owned()), p.issuer);
+        assert_eq!(Some("s".to_owned()), p.subject);
+        assert_eq!(set(&["a", "b", "c"]), p.scopes);
+    }
+
+    #[test]
+    fn keycloak_roles() {
+        let claims = json!({
+            "realm_access": { "roles": ["admin"] },
+            "resource_access": {
+                "api": { "roles": ["write"] },
+                "web": { "roles": ["view"] },
+            },
+        });
+
+        assert_eq!(
+            set(&["admin", "api:write", "web:view"]),
+            principal(claims.clone(), &Keycloak::new()).roles
+        );
+        assert_eq!(
+            set(&["admin", "write"]),
+            principal(claims, &Keycloak::client("api")).roles
+        );
+    }
+
+    #[test]
+    fn auth0_permissions_roles_and_org() {
+        let p = principal(
+            json!({
+                "scope": "openid",
+                "permissions": ["read:users"],
+                "https://example.com/roles": ["editor"],
+                "org_id": "org_1",
+            }),
+            &Auth0::new().roles_claim("https://example.com/roles"),
+        );
+
+        assert_eq!(set(&["openid", "read:users"]), p.scopes);
+        assert_eq!(set(&["editor"]), p.roles);
+        assert_eq!(Some("org_1".to_owned()), p.tenant);
+    }
+
+    #[test]
+    fn okta_cognito_and_azure() {
+        let okta = principal(json!({ "scp": ["a"], "groups": ["g"] }), &Okta);
+        assert_eq!((set(&["a"]), set(&["g"])), (okta.scopes, okta.roles));
+
+        let cognito = principal(json!({ "scope": "a", "cognito:groups": ["g"] }), &Cognito);
+        assert_eq!((set(&["a"]), set(&["g"])), (cognito.scopes, cognito.roles));
+
+        let azure = principal(json!({ "scp": "a b", "roles": ["g"], "tid": "t" }), &Azure);
+        assert_eq!((set(&["a", "b"]), set(&["g"])), (azure.scopes, azure.roles));
+        assert_eq!(Some("t".to_owned()), azure.tenant);
+    }
+
+    #[test]
+    fn combined_and_custom_adapters() {
+        let tenant = |claims: &Map<String, Value>, principal: &mut Principal| {
+            principal.tenant = string_claim(claims, "tenant_id");
+        };
+        let p = principal(
+            json!({ "groups": ["g"], "tenant_id": "t" }),
+            &(Okta, tenant),
+        );
+
+        assert_eq!(set(&["g"]), p.roles);
+        assert_eq!(Some("t".to_owned()), p.tenant);
+    }
+}
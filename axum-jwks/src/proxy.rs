@@ -0,0 +1,329 @@
+use std::fmt;
+
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    claims::{accept_token, cache_token, validate_token},
+    Jwks, JwksError, ParseTokenClaims, Token,
+};
+
+/// The header Cloudflare Access uses to forward its signed assertion.
+pub const CF_ACCESS_JWT_ASSERTION: &str = "cf-access-jwt-assertion";
+
+/// The header Google Cloud Identity-Aware Proxy uses to forward its signed
+/// assertion.
+pub const GOOGLE_IAP_JWT_ASSERTION: &str = "x-goog-iap-jwt-assertion";
+
+const GOOGLE_IAP_JWKS_URL: &str = "https://www.gstatic.com/iap/verify/public_key-jwk";
+const GOOGLE_IAP_ISSUER: &str = "https://cloud.google.com/iap";
+
+/// The audience of an assertion issued by Google IAP.
+///
+/// IAP derives the audience from the resource it protects, so it can be
+/// looked up in the Google Cloud console rather than configured.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IapAudience {
+    /// A backend service behind a load balancer, formatted as
+    /// `/projects/PROJECT_NUMBER/global/backendServices/SERVICE_ID`.
+    BackendService {
+        project_number: u64,
+        backend_service_id: u64,
+    },
+
+    /// An App Engine application, formatted as
+    /// `/projects/PROJECT_NUMBER/apps/PROJECT_ID`.
+    AppEngine {
+        project_number: u64,
+        project_id: String,
+    },
+}
+
+impl fmt::Display for IapAudience {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BackendService {
+                project_number,
+                backend_service_id,
+            } => write!(
+                f,
+                "/projects/{project_number}/global/backendServices/{backend_service_id}"
+            ),
+            Self::AppEngine {
+                project_number,
+                project_id,
+            } => write!(f, "/projects/{project_number}/apps/{project_id}"),
+        }
+    }
+}
+
+impl Jwks {
+    /// Pull the signing keys for a Cloudflare Access team.
+    ///
+    /// # Arguments
+    /// * `team_domain` - The team domain, e.g. `my-team.cloudflareaccess.com`.
+    ///   A bare team name is expanded to the default `cloudflareaccess.com`
+    ///   domain.
+    /// * `audience` - The application audience (AUD) tag. This will be matched
+    ///   against the `aud` claim from the token.
+    ///
+    /// # Return Value
+    /// A key set that only accepts tokens issued by the team domain for the
+    /// given application.
+    pub async fn from_cloudflare_access(
+        team_domain: &str,
+        audience: &str,
+    ) -> Result<Self, JwksError> {
+        Self::from_cloudflare_access_with_client(&reqwest::Client::default(), team_domain, audience)
+            .await
+    }
+
+    /// A version of [`from_cloudflare_access`][Self::from_cloudflare_access]
+    /// that allows for passing in a custom [`Client`][reqwest::Client].
+    pub async fn from_cloudflare_access_with_client(
+        client: &reqwest::Client,
+        team_domain: &str,
+        audience: &str,
+    ) -> Result<Self, JwksError> {
+        let issuer = cloudflare_team_url(team_domain);
+        let jwks_url = format!("{issuer}/cdn-cgi/access/certs");
+
+        let mut jwks = Self::from_jwks_url_with_client(
+            client,
+            &jwks_url,
+            Some(audience),
+            Some(jsonwebtoken::Algorithm::RS256),
+        )
+        .await?;
+        jwks.set_issuer(&[issuer]);
+
+        Ok(jwks)
+    }
+
+    /// Pull Google's public keys for Identity-Aware Proxy assertions.
+    ///
+    /// # Arguments
+    /// * `audience` - The resource protected by IAP. This will be matched
+    ///   against the `aud` claim from the token.
+    pub async fn from_google_iap(audience: &IapAudience) -> Result<Self, JwksError> {
+        Self::from_google_iap_with_client(&reqwest::Client::default(), audience).await
+    }
+
+    /// A version of [`from_google_iap`][Self::from_google_iap] that allows for
+    /// passing in a custom [`Client`][reqwest::Client].
+    pub async fn from_google_iap_with_client(
+        client: &reqwest::Client,
+        audience: &IapAudience,
+    ) -> Result<Self, JwksError> {
+        let mut jwks = Self::from_jwks_url_with_client(
+            client,
+            GOOGLE_IAP_JWKS_URL,
+            Some(&audience.to_string()),
+            Some(jsonwebtoken::Algorithm::ES256),
+        )
+        .await?;
+        jwks.set_issuer(&[GOOGLE_IAP_ISSUER]);
+
+        Ok(jwks)
+    }
+}
+
+fn cloudflare_team_url(team_domain: &str) -> String {
+    let domain = team_domain
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .trim_end_matches('/');
+
+    if domain.contains('.') {
+        format!("https://{domain}")
+    } else {
+        format!("https://{domain}.cloudflareaccess.com")
+    }
+}
+
+/// Claims from the assertion Cloudflare Access attaches to proxied requests.
+///
+/// This works like [`Claims`][crate::Claims], but reads the token from the
+/// `Cf-Access-Jwt-Assertion` header. The [`Jwks`] in the application state
+/// should be created with
+/// [`Jwks::from_cloudflare_access`][Jwks::from_cloudflare_access].
+pub struct CfAccessClaims<C>(pub C);
+
+/// Claims from the assertion Google IAP attaches to proxied requests.
+///
+/// This works like [`Claims`][crate::Claims], but reads the token from the
+/// `x-goog-iap-jwt-assertion` header. The [`Jwks`] in the application state
+/// should be created with [`Jwks::from_google_iap`][Jwks::from_google_iap].
+pub struct IapClaims<C>(pub C);
+
+async fn claims_from_header<S, C>(
+    parts: &mut Parts,
+    state: &S,
+    header: &str,
+) -> Result<C, C::Rejection>
+where
+    C: DeserializeOwned + ParseTokenClaims<S> + Send,
+    Jwks: FromRef<S>,
+    S: Sync,
+{
+    let jwks = Jwks::from_ref(state);
+    let token = Token::from_header(parts, header)?;
+
+    let verified = validate_token(parts, &jwks, &token).await?;
+    let verified = cache_token(parts, &jwks, verified).await?;
+    C::check_claims(verified.raw())?;
+    let claims: C = verified.raw().deserialize()?;
+    claims.validate(state).await?;
+    accept_token(parts, &verified)?;
+
+    Ok(claims)
+}
+
+impl<S, C> FromRequestParts<S> for CfAccessClaims<C>
+where
+    C: DeserializeOwned + ParseTokenClaims<S> + Send,
+    Jwks: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = C::Rejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        claims_from_header(parts, state, CF_ACCESS_JWT_ASSERTION)
+            .await
+            .map(Self)
+    }
+}
+
+impl<S, C> FromRequestParts<S> for IapClaims<C>
+where
+    C: DeserializeOwned + ParseTokenClaims<S> + Send,
+    Jwks: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = C::Rejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        claims_from_header(parts, state, GOOGLE_IAP_JWT_ASSERTION)
+            .await
+            .map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{test_util, TokenError, ValidationOptions};
+
+    #[derive(Deserialize, Serialize)]
+    struct TestClaims {
+        sub: String,
+        aud: String,
+        iss: String,
+        exp: i64,
+    }
+
+    impl<S: Send + Sync> ParseTokenClaims<S> for TestClaims {
+        type Rejection = TokenError;
+    }
+
+    fn claims(iss: &str) -> TestClaims {
+        TestClaims {
+            sub: "user".to_owned(),
+            aud: "my-aud".to_owned(),
+            iss: iss.to_owned(),
+            exp: test_util::timestamp(60),
+        }
+    }
+
+    #[test]
+    fn team_url_normalization() {
+        let expected = "https://team.cloudflareaccess.com";
+
+        assert_eq!(expected, cloudflare_team_url("team"));
+        assert_eq!(expected, cloudflare_team_url("team.cloudflareaccess.com"));
+        assert_eq!(
+            expected,
+            cloudflare_team_url("https://team.cloudflareaccess.com/")
+        );
+    }
+
+    #[test]
+    fn iap_audience_format() {
+        let backend = IapAudience::BackendService {
+            project_number: 123,
+            backend_service_id: 456,
+        };
+        let app = IapAudience::AppEngine {
+            project_number: 123,
+            project_id: "my-project".to_owned(),
+        };
+
+        assert_eq!(
+            "/projects/123/global/backendServices/456",
+            backend.to_string()
+        );
+        assert_eq!("/projects/123/apps/my-project", app.to_string());
+    }
+
+    #[tokio::test]
+    async fn iap_claims_from_assertion_header() {
+        let mut jwks = test_util::jwks(Some("my-aud"));
+        jwks.set_issuer(&[GOOGLE_IAP_ISSUER]);
+        let token = test_util::sign_ec(&claims(GOOGLE_IAP_ISSUER));
+
+        let request = Request::builder()
+            .header(GOOGLE_IAP_JWT_ASSERTION, token)
+            .body(())
+            .unwrap();
+        let (mut parts, _) = request.into_parts();
+
+        let IapClaims(claims) = IapClaims::<TestClaims>::from_request_parts(&mut parts, &jwks)
+            .await
+            .unwrap();
+
+        assert_eq!("user", claims.sub);
+    }
+
+    #[tokio::test]
+    async fn iap_claims_use_validation_options() {
+        let mut jwks = test_util::jwks(Some("my-aud"));
+        jwks.set_issuer(&[GOOGLE_IAP_ISSUER]);
+        let token = test_util::sign_ec(&claims(GOOGLE_IAP_ISSUER));
+
+        let request = Request::builder()
+            .header(GOOGLE_IAP_JWT_ASSERTION, token)
+            .body(())
+            .unwrap();
+        let (mut parts, _) = request.into_parts();
+        parts
+            .extensions
+            .insert(ValidationOptions::default().audience(["other-aud"]));
+
+        let result = IapClaims::<TestClaims>::from_request_parts(&mut parts, &jwks).await;
+
+        assert!(matches!(result, Err(TokenError::Invalid(_))));
+    }
+
+    #[tokio::test]
+    async fn cf_access_claims_reject_wrong_issuer() {
+        let mut jwks = test_util::jwks(Some("my-aud"));
+        jwks.set_issuer(&["https://team.cloudflareaccess.com"]);
+        let token = test_util::sign(&claims("https://other.cloudflareaccess.com"));
+
+        let request = Request::builder()
+            .header(CF_ACCESS_JWT_ASSERTION, token)
+            .body(())
+            .unwrap();
+        let (mut parts, _) = request.into_parts();
+
+        let result = CfAccessClaims::<TestClaims>::from_request_parts(&mut parts, &jwks).await;
+
+        assert!(matches!(result, Err(TokenError::Invalid(_))));
+    }
+}
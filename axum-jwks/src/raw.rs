@@ -0,0 +1,182 @@
+use axum::{
+    body::Bytes,
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use serde::Deserialize;
+
+use crate::{
+    claims::{accept_token, verified_token},
+    Jwks, TokenError,
+};
+
+/// The decoded payload of a validated token.
+///
+/// Claims are only deserialized on demand with
+/// [`deserialize`][Self::deserialize], into types that may borrow from the
+/// payload. Borrow strings as `Cow<'a, str>` with `#[serde(borrow)]`, since
+/// strings containing JSON escapes cannot be borrowed as `&'a str`.
+///
+/// As an extractor, the token is validated with the [`Jwks`] from the
+/// application state, and the payload is kept in the request's extensions
+/// for every later extractor. Checks that need the parsed claims, such as a
+/// [`ReplayGuard`][crate::ReplayGuard], a
+/// [`StatusListChecker`][crate::StatusListChecker],
+/// [`Introspection`][crate::Introspection] or CSRF protection for cookies,
+/// parse the claims as usual when they are configured.
+///
+/// # Example
+/// ```
+/// use std::borrow::Cow;
+///
+/// use axum_jwks::{RawClaims, TokenError};
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// struct Peek<'a> {
+///     #[serde(borrow)]
+///     sub: Cow<'a, str>,
+///     #[serde(borrow, default)]
+///     scope: Cow<'a, str>,
+/// }
+///
+/// async fn handler(raw: RawClaims) -> Result<String, TokenError> {
+///     let claims: Peek = raw.deserialize()?;
+///
+///     Ok(format!("{} may {}", claims.sub, claims.scope))
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct RawClaims(Bytes);
+
+impl RawClaims {
+    pub(crate) fn new(payload: Bytes) -> Self {
+        Self(payload)
+    }
+
+    /// The JSON payload of the token.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Deserialize the claims, borrowing from the payload where possible.
+    pub fn deserialize<'a, T>(&'a self) -> Result<T, TokenError>
+    where
+        T: Deserialize<'a>,
+    {
+        serde_json::from_slice(&self.0).map_err(|error| TokenError::Invalid(error.into()))
+    }
+}
+
+impl<S> FromRequestParts<S> for RawClaims
+where
+    Jwks: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = TokenError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let verified = verified_token(parts, state).await?;
+        accept_token(parts, &verified)?;
+
+        Ok(verified.raw().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request, StatusCode},
+        routing::get,
+        Extension, Router,
+    };
+    use serde_json::json;
+    use tower_service::Service;
+
+    use super::*;
+    use crate::{test_util, InMemoryReplayStore, ReplayGuard};
+
+    #[derive(Deserialize)]
+    struct Peek<'a> {
+        #[serde(borrow)]
+        sub: Cow<'a, str>,
+        scope: &'a str,
+    }
+
+    #[test]
+    fn borrow_from_payload() {
+        let jwks = test_util::jwks(None);
+        let token = test_util::sign(&json!({
+            "sub": "alice \"the admin\"",
+            "scope": "read write",
+            "exp": test_util::timestamp(60),
+        }));
+
+        let raw = jwks.validate_raw_claims(&token).unwrap();
+        let claims: Peek = raw.deserialize().unwrap();
+
+        assert_eq!("alice \"the admin\"", claims.sub);
+        // The scope was borrowed instead of copied.
+        assert!(raw
+            .as_bytes()
+            .as_ptr_range()
+            .contains(&claims.scope.as_ptr()));
+
+        let expired =
+            test_util::sign(&json!({ "sub": "alice", "exp": test_util::timestamp(-600) }));
+        assert!(matches!(
+            jwks.validate_raw_claims(&expired),
+            Err(TokenError::Invalid(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn extract_raw_claims() {
+        let handler = |raw: RawClaims| async move {
+            let claims: Peek = raw.deserialize()?;
+
+            Ok::<_, TokenError>(claims.sub.into_owned())
+        };
+        let token = test_util::sign(&json!({
+            "sub": "alice",
+            "scope": "read",
+            "jti": "1",
+            "exp": test_util::timestamp(60),
+        }));
+        let request = || {
+            Request::builder()
+                .header(AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let mut router = Router::new()
+            .route("/", get(handler))
+            .with_state(test_util::jwks(None));
+        for _ in 0..2 {
+            let response = router.call(request()).await.unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+
+            assert_eq!(&b"alice"[..], &body[..]);
+        }
+
+        let mut router = Router::new()
+            .route("/", get(handler))
+            .layer(Extension(ReplayGuard::new(InMemoryReplayStore::new())))
+            .with_state(test_util::jwks(None));
+
+        assert_eq!(
+            StatusCode::OK,
+            router.call(request()).await.unwrap().status()
+        );
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            router.call(request()).await.unwrap().status()
+        );
+    }
+}
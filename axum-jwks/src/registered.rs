@@ -0,0 +1,454 @@
+use std::{
+    collections::BTreeSet,
+    time::{Duration, SystemTime},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{principal::string_list, ParseTokenClaims, TokenError};
+
+/// The registered claims of a JWT, with any other claims in `Extra`.
+///
+/// Claims that are either a string or an array, such as `aud` and `scope`,
+/// are always parsed into sets. The `aud` claim must be a single audience or
+/// an array of audiences, while `scope` may also be space delimited.
+/// Timestamps are parsed into [`SystemTime`]s. Every claim that is not
+/// registered is flattened into `Extra`, which is a map of the remaining
+/// claims by default, or a struct of your own.
+///
+/// The claims can be used directly with [`Claims`][crate::Claims] and
+/// [`Jwks::validate_claims`][crate::Jwks::validate_claims].
+///
+/// # Example
+/// ```
+/// use axum_jwks::{Claims, RegisteredClaims};
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// struct Profile {
+///     email: String,
+/// }
+///
+/// async fn handler(Claims(claims): Claims<RegisteredClaims<Profile>>) -> String {
+///     if claims.has_scope("read:email") {
+///         claims.extra.email
+///     } else {
+///         claims.subject.unwrap_or_default()
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct RegisteredClaims<Extra = Map<String, Value>> {
+    /// The party that issued the token.
+    #[serde(rename = "iss", default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+
+    /// The principal the token is about, usually a user or client ID.
+    #[serde(rename = "sub", default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+
+    /// The recipients the token is intended for.
+    #[serde(
+        rename = "aud",
+        default,
+        skip_serializing_if = "BTreeSet::is_empty",
+        with = "audience_set"
+    )]
+    pub audience: BTreeSet<String>,
+
+    /// When the token expires.
+    #[serde(
+        rename = "exp",
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "timestamp"
+    )]
+    pub expires_at: Option<SystemTime>,
+
+    /// When the token becomes valid.
+    #[serde(
+        rename = "nbf",
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "timestamp"
+    )]
+    pub not_before: Option<SystemTime>,
+
+    /// When the token was issued.
+    #[serde(
+        rename = "iat",
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "timestamp"
+    )]
+    pub issued_at: Option<SystemTime>,
+
+    /// The unique identifier of the token.
+    #[serde(rename = "jti", default, skip_serializing_if = "Option::is_none")]
+    pub token_id: Option<String>,
+
+    /// The scopes granted by the `scope` and `scp` claims.
+    ///
+    /// The scopes are serialized as `scope`.
+    #[serde(flatten, with = "scope_claims")]
+    pub scopes: BTreeSet<String>,
+
+    /// The client the token was issued to.
+    #[serde(rename = "azp", default, skip_serializing_if = "Option::is_none")]
+    pub authorized_party: Option<String>,
+
+    /// The key the token is bound to.
+    #[serde(rename = "cnf", default, skip_serializing_if = "Option::is_none")]
+    pub confirmation: Option<Confirmation>,
+
+    /// The party acting on behalf of the subject, for delegated tokens.
+    #[serde(rename = "act", default, skip_serializing_if = "Option::is_none")]
+    pub actor: Option<Actor>,
+
+    /// Every claim that is not registered.
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+impl<Extra> RegisteredClaims<Extra> {
+    /// Whether the token is intended for the given audience.
+    pub fn has_audience(&self, audience: &str) -> bool {
+        self.audience.contains(audience)
+    }
+
+    /// Whether the token grants the given scope through its `scope` or `scp`
+    /// claim.
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.contains(scope)
+    }
+
+    /// Whether the token expired at the given time.
+    ///
+    /// Tokens without an `exp` claim never expire.
+    pub fn is_expired_at(&self, time: SystemTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= time)
+    }
+}
+
+impl<S, Extra> ParseTokenClaims<S> for RegisteredClaims<Extra>
+where
+    Extra: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = TokenError;
+}
+
+/// The `cnf` claim of a sender-constrained token (RFC 7800).
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Confirmation {
+    /// The thumbprint of a DPoP key (RFC 9449).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jkt: Option<String>,
+
+    /// The thumbprint of a client certificate (RFC 8705).
+    #[serde(rename = "x5t#S256", default, skip_serializing_if = "Option::is_none")]
+    pub x5t_s256: Option<String>,
+
+    /// An embedded public key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwk: Option<Value>,
+
+    /// Every other confirmation method.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The `act` claim of a delegated token (RFC 8693).
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Actor {
+    #[serde(rename = "iss", default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+
+    #[serde(rename = "sub", default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+
+    /// The party acting on behalf of this actor, if the delegation is
+    /// nested.
+    #[serde(rename = "act", default, skip_serializing_if = "Option::is_none")]
+    pub actor: Option<Box<Actor>>,
+
+    /// Every other claim identifying the actor.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The `aud` claim, which is a single string or an array of strings.
+mod audience_set {
+    use std::collections::BTreeSet;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Audience {
+        One(String),
+        Many(BTreeSet<String>),
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<BTreeSet<String>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(match Option::<Audience>::deserialize(deserializer)? {
+            Some(Audience::One(audience)) => BTreeSet::from([audience]),
+            Some(Audience::Many(audiences)) => audiences,
+            None => BTreeSet::new(),
+        })
+    }
+
+    pub fn serialize<S>(audiences: &BTreeSet<String>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(audiences)
+    }
+}
+
+/// The `scope` and `scp` claims, merged into a single set.
+///
+/// Providers such as Azure AD and Okta use `scp` instead of `scope`, so both
+/// are read like [`RequireScopes`][crate::RequireScopes] does.
+mod scope_claims {
+    use std::collections::BTreeSet;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde_json::Value;
+
+    use super::string_list;
+
+    #[derive(Deserialize)]
+    struct Scopes {
+        #[serde(default)]
+        scope: Option<Value>,
+        #[serde(default)]
+        scp: Option<Value>,
+    }
+
+    #[derive(Serialize)]
+    struct Scope<'a> {
+        #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+        scope: &'a BTreeSet<String>,
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<BTreeSet<String>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let scopes = Scopes::deserialize(deserializer)?;
+
+        Ok(string_list(scopes.scope.as_ref())
+            .chain(string_list(scopes.scp.as_ref()))
+            .collect())
+    }
+
+    pub fn serialize<S>(scopes: &BTreeSet<String>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Scope { scope: scopes }.serialize(serializer)
+    }
+}
+
+/// Timestamps in seconds since the UNIX epoch.
+mod timestamp {
+    use std::time::{Duration, SystemTime};
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    use super::from_seconds;
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<SystemTime>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match Option::<f64>::deserialize(deserializer)? {
+            Some(seconds) => from_seconds(seconds)
+                .map(Some)
+                .ok_or_else(|| D::Error::custom("timestamp is out of range")),
+            None => Ok(None),
+        }
+    }
+
+    pub fn serialize<S>(time: &Option<SystemTime>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let seconds = time
+            .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .unwrap_or(Duration::ZERO)
+            .as_secs();
+
+        serializer.serialize_u64(seconds)
+    }
+}
+
+fn from_seconds(seconds: f64) -> Option<SystemTime> {
+    let offset = Duration::try_from_secs_f64(seconds.abs()).ok()?;
+
+    if seconds >= 0.0 {
+        SystemTime::UNIX_EPOCH.checked_add(offset)
+    } else {
+        SystemTime::UNIX_EPOCH.checked_sub(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use serde_json::json;
+    use tower_service::Service;
+
+    use super::*;
+    use crate::{test_util, Claims};
+
+    #[test]
+    fn parse_registered_claims() {
+        let claims: RegisteredClaims = serde_json::from_value(json!({
+            "iss": "https://auth.example.com",
+            "sub": "alice",
+            "aud": "api",
+            "exp": 1_700_000_060,
+            "iat": 1_700_000_000.5,
+            "scope": "read write",
+            "azp": "web-client",
+            "cnf": { "jkt": "thumbprint" },
+            "act": { "sub": "service", "act": { "sub": "gateway" } },
+            "email": "alice@example.com",
+        }))
+        .unwrap();
+
+        assert_eq!(Some("alice"), claims.subject.as_deref());
+        assert!(claims.has_audience("api"));
+        assert!(claims.has_scope("write"));
+        assert_eq!(
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_060)),
+            claims.expires_at
+        );
+        assert_eq!(
+            Some(SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_500)),
+            claims.issued_at
+        );
+        assert!(claims.is_expired_at(SystemTime::now()));
+        assert_eq!(
+            Some("thumbprint"),
+            claims.confirmation.as_ref().unwrap().jkt.as_deref()
+        );
+        assert_eq!(
+            Some("gateway"),
+            claims
+                .actor
+                .as_ref()
+                .and_then(|actor| actor.actor.as_ref())
+                .and_then(|actor| actor.subject.as_deref())
+        );
+        assert_eq!(
+            Map::from_iter([("email".to_owned(), json!("alice@example.com"))]),
+            claims.extra
+        );
+    }
+
+    #[test]
+    fn audience_and_scope_lists() {
+        let claims: RegisteredClaims = serde_json::from_value(json!({
+            "aud": ["api", "other"],
+            "scope": ["read"],
+        }))
+        .unwrap();
+
+        assert_eq!(
+            BTreeSet::from(["api".to_owned(), "other".to_owned()]),
+            claims.audience
+        );
+        assert!(claims.has_scope("read"));
+        assert_eq!(
+            json!({ "aud": ["api", "other"], "scope": ["read"] }),
+            serde_json::to_value(&claims).unwrap()
+        );
+    }
+
+    #[test]
+    fn scopes_include_scp() {
+        let claims: RegisteredClaims = serde_json::from_value(json!({
+            "scope": "read",
+            "scp": ["write"],
+        }))
+        .unwrap();
+
+        assert!(claims.has_scope("read"));
+        assert!(claims.has_scope("write"));
+        assert!(claims.extra.is_empty());
+        assert_eq!(
+            json!({ "scope": ["read", "write"] }),
+            serde_json::to_value(&claims).unwrap()
+        );
+        assert_eq!(
+            json!({}),
+            serde_json::to_value(RegisteredClaims::<Map<String, Value>>::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn audience_is_not_split() {
+        let claims: RegisteredClaims = serde_json::from_value(json!({ "aud": "my api" })).unwrap();
+
+        assert!(claims.has_audience("my api"));
+        assert!(!claims.has_audience("api"));
+
+        for aud in [json!(1), json!({ "api": true }), json!(["api", 1])] {
+            assert!(
+                serde_json::from_value::<RegisteredClaims>(json!({ "aud": aud })).is_err(),
+                "{aud}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn extract_with_typed_extra_claims() {
+        #[derive(Deserialize)]
+        struct Profile {
+            email: String,
+        }
+
+        let handler = |Claims(claims): Claims<RegisteredClaims<Profile>>| async move {
+            format!("{} {}", claims.subject.unwrap(), claims.extra.email)
+        };
+        let mut router = Router::new()
+            .route("/", get(handler))
+            .with_state(test_util::jwks(Some("api")));
+
+        let token = test_util::sign(&json!({
+            "sub": "alice",
+            "aud": ["api"],
+            "exp": test_util::timestamp(60),
+            "email": "alice@example.com",
+        }));
+        let request = Request::builder()
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+        let response = router.call(request).await.unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&b"alice alice@example.com"[..], &body[..]);
+
+        let claims = test_util::jwks(Some("api"))
+            .validate_claims::<RegisteredClaims>(&token)
+            .unwrap()
+            .claims;
+        assert!(claims.has_audience("api"));
+    }
+}
@@ -0,0 +1,326 @@
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, RandomState},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use serde_json::Value;
+use tracing::debug;
+
+use crate::TokenError;
+
+/// The number of independently locked shards of an [`InMemoryReplayStore`].
+const SHARDS: usize = 16;
+
+/// How long tokens without an `exp` claim are remembered.
+const TTL_WITHOUT_EXPIRY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The seconds of clock skew `jsonwebtoken` allows by default.
+pub(crate) const DEFAULT_LEEWAY: u64 = 60;
+
+/// Remembers values such as token IDs so that they can only be used once.
+///
+/// Implement this to share state between multiple instances of a service, for
+/// example in a database. The store is called while a request is being
+/// extracted, so implementations should answer quickly.
+pub trait ReplayStore: Send + Sync {
+    /// Record a value until `expires_at`.
+    ///
+    /// Returns `false` if the value was already recorded and has not expired
+    /// yet, meaning it is being replayed.
+    fn insert(&self, value: &str, expires_at: SystemTime) -> bool;
+}
+
+/// A [`ReplayStore`] that keeps values in memory.
+///
+/// Values are spread over several independently locked shards so concurrent
+/// requests rarely contend. Expired values are removed as new values are
+/// inserted. The store is cheap to clone, and clones share their values.
+#[derive(Clone)]
+pub struct InMemoryReplayStore {
+    shards: Arc<[Mutex<Shard>]>,
+    hasher: RandomState,
+}
+
+#[derive(Default)]
+struct Shard {
+    values: HashMap<String, SystemTime>,
+    /// The number of values at which expired values are removed next.
+    prune_at: usize,
+}
+
+impl InMemoryReplayStore {
+    pub fn new() -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| Mutex::default()).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+}
+
+impl Default for InMemoryReplayStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReplayStore for InMemoryReplayStore {
+    fn insert(&self, value: &str, expires_at: SystemTime) -> bool {
+        let now = SystemTime::now();
+        let shard = &self.shards[self.hasher.hash_one(value) as usize % SHARDS];
+        let mut shard = shard.lock().unwrap_or_else(|error| error.into_inner());
+
+        if shard.values.len() >= shard.prune_at {
+            shard.values.retain(|_, expires_at| *expires_at > now);
+            // Pruning is linear, so only do it once the shard has doubled in
+            // size to keep inserts constant time on average.
+            shard.prune_at = (shard.values.len() * 2).max(64);
+        }
+
+        match shard.values.get(value) {
+            Some(previous) if *previous > now => false,
+            _ => {
+                shard.values.insert(value.to_owned(), expires_at);
+                true
+            }
+        }
+    }
+}
+
+/// Rejects tokens whose `jti` claim was already used.
+///
+/// This is meant for one-time tokens such as webhook callbacks or signed
+/// download links. Token IDs are remembered until the token's `exp` plus the
+/// leeway it was validated with, or for a day if it has no `exp`. Tokens
+/// without a `jti` claim are rejected.
+///
+/// Insert the guard into the request's extensions with
+/// [`Extension`][axum::Extension] to check every token validated by the
+/// extractors in this crate. Otherwise, call [`check`][Self::check] after
+/// validating a token with
+/// [`Jwks::validate_claims`][crate::Jwks::validate_claims].
+///
+/// Extractors record the token ID once the token passed all of their checks,
+/// such as [`ParseTokenClaims::validate`][crate::ParseTokenClaims::validate]
+/// or a required scope, so a rejected token is not used up. Any number of
+/// extractors can accept the same token within a request.
+///
+/// # Example
+/// ```
+/// use axum::{routing::post, Extension, Router};
+/// use axum_jwks::{Claims, InMemoryReplayStore, Jwks, ReplayGuard};
+/// # use axum_jwks::{ParseTokenClaims, TokenError};
+/// # #[derive(serde::Deserialize)]
+/// # struct Callback {}
+/// # impl<S: Send + Sync> ParseTokenClaims<S> for Callback { type Rejection = TokenError; }
+///
+/// async fn webhook(Claims(callback): Claims<Callback>) {}
+///
+/// # fn app(jwks: Jwks) -> Router {
+/// Router::new()
+///     .route("/webhook", post(webhook))
+///     .layer(Extension(ReplayGuard::new(InMemoryReplayStore::new())))
+///     .with_state(jwks)
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ReplayGuard {
+    store: Arc<dyn ReplayStore>,
+}
+
+impl ReplayGuard {
+    pub fn new(store: impl ReplayStore + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+        }
+    }
+
+    /// Record the token ID of validated claims, failing if it was used
+    /// before.
+    ///
+    /// This assumes the token was validated with the default leeway of 60
+    /// seconds. Use [`check_with_leeway`][Self::check_with_leeway] otherwise.
+    pub fn check(&self, claims: &Value) -> Result<(), TokenError> {
+        self.check_with_leeway(claims, DEFAULT_LEEWAY)
+    }
+
+    /// Record the token ID of claims that were validated with the given
+    /// seconds of leeway, failing if it was used before.
+    ///
+    /// The token ID is remembered for as long as validation accepts the
+    /// token.
+    pub fn check_with_leeway(&self, claims: &Value, leeway: u64) -> Result<(), TokenError> {
+        self.record(&token_id(claims)?, claims, leeway)
+    }
+
+    /// Record a token ID from [`token_id`], failing if it was used before.
+    pub(crate) fn record(
+        &self,
+        token_id: &str,
+        claims: &Value,
+        leeway: u64,
+    ) -> Result<(), TokenError> {
+        // Expiries too far in the future to represent are treated like a
+        // missing `exp`.
+        let expires_at = claims
+            .get("exp")
+            .and_then(Value::as_u64)
+            .and_then(|exp| exp.checked_add(leeway))
+            .and_then(|exp| SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(exp)))
+            .unwrap_or_else(|| SystemTime::now() + TTL_WITHOUT_EXPIRY);
+
+        if !self.store.insert(token_id, expires_at) {
+            debug!(?token_id, "Token was replayed.");
+
+            return Err(TokenError::Replayed);
+        }
+
+        Ok(())
+    }
+}
+
+/// The value a [`ReplayGuard`] records for a token.
+pub(crate) fn token_id(claims: &Value) -> Result<String, TokenError> {
+    let jti = claims
+        .get("jti")
+        .and_then(Value::as_str)
+        .filter(|jti| !jti.is_empty())
+        .ok_or(TokenError::MissingTokenId)?;
+
+    // Token IDs are only unique per issuer.
+    let issuer = claims
+        .get("iss")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+
+    Ok(format!("{issuer}\n{jti}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request, StatusCode},
+        routing::post,
+        Extension, Router,
+    };
+    use serde_json::json;
+    use tower_service::Service;
+
+    use super::*;
+    use crate::{test_util, Claims, ParseTokenClaims, ValidationOptions};
+
+    #[test]
+    fn reject_values_until_they_expire() {
+        let store = InMemoryReplayStore::new();
+        let later = SystemTime::now() + Duration::from_secs(60);
+        let past = SystemTime::now() - Duration::from_secs(1);
+
+        assert!(store.insert("a", later));
+        assert!(!store.clone().insert("a", later));
+        assert!(store.insert("b", past));
+        assert!(store.insert("b", later));
+    }
+
+    #[test]
+    fn guard_checks_token_ids() {
+        let guard = ReplayGuard::new(InMemoryReplayStore::new());
+        let exp = test_util::timestamp(60);
+
+        assert_eq!(
+            Ok(()),
+            guard.check(&json!({ "iss": "a", "jti": "1", "exp": exp }))
+        );
+        assert_eq!(
+            Ok(()),
+            guard.check(&json!({ "iss": "b", "jti": "1", "exp": exp }))
+        );
+        assert_eq!(
+            Err(TokenError::Replayed),
+            guard.check(&json!({ "iss": "a", "jti": "1", "exp": exp }))
+        );
+        assert_eq!(
+            Err(TokenError::MissingTokenId),
+            guard.check(&json!({ "exp": exp }))
+        );
+    }
+
+    #[test]
+    fn guard_accepts_far_future_expiry() {
+        let guard = ReplayGuard::new(InMemoryReplayStore::new());
+        let claims = json!({ "jti": "1", "exp": u64::MAX });
+
+        assert_eq!(Ok(()), guard.check(&claims));
+        assert_eq!(Err(TokenError::Replayed), guard.check(&claims));
+    }
+
+    #[tokio::test]
+    async fn extractors_use_guard_extension() {
+        #[derive(serde::Deserialize)]
+        struct TestClaims {}
+
+        impl<S: Send + Sync> ParseTokenClaims<S> for TestClaims {
+            type Rejection = TokenError;
+        }
+
+        let mut router = Router::new()
+            .route("/", post(|_: Claims<TestClaims>| async {}))
+            .layer(Extension(ReplayGuard::new(InMemoryReplayStore::new())))
+            .with_state(test_util::jwks(None));
+        let token = test_util::sign(&json!({ "jti": "1", "exp": test_util::timestamp(60) }));
+
+        for expected in [StatusCode::OK, StatusCode::UNAUTHORIZED] {
+            let request = Request::builder()
+                .method("POST")
+                .header(AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap();
+
+            assert_eq!(expected, router.call(request).await.unwrap().status());
+        }
+    }
+    #[tokio::test]
+    async fn remember_tokens_within_leeway() {
+        #[derive(serde::Deserialize)]
+        struct TestClaims {}
+
+        impl<S: Send + Sync> ParseTokenClaims<S> for TestClaims {
+            type Rejection = TokenError;
+        }
+
+        let router = |options: ValidationOptions| {
+            Router::new()
+                .route("/", post(|_: Claims<TestClaims>| async {}))
+                .layer(Extension(options))
+                .layer(Extension(ReplayGuard::new(InMemoryReplayStore::new())))
+                .with_state(test_util::jwks(None))
+        };
+
+        // Expired tokens are still accepted within the default leeway of 60
+        // seconds, or the leeway configured for the request.
+        let cases = [
+            (ValidationOptions::default(), -5),
+            (ValidationOptions::default().leeway(300), -120),
+        ];
+
+        for (options, exp) in cases {
+            let mut router = router(options);
+            let token = test_util::sign(&json!({ "jti": "1", "exp": test_util::timestamp(exp) }));
+
+            for expected in [StatusCode::OK, StatusCode::UNAUTHORIZED] {
+                let request = Request::builder()
+                    .method("POST")
+                    .header(AUTHORIZATION, format!("Bearer {token}"))
+                    .body(Body::empty())
+                    .unwrap();
+
+                assert_eq!(
+                    expected,
+                    router.call(request).await.unwrap().status(),
+                    "{exp}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{Jwks, Principal, PrincipalAdapter, TokenError};
+
+/// The definition of a single role in a [`RoleGraph`].
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoleDefinition {
+    /// Roles whose permissions this role also has.
+    pub inherits: Vec<String>,
+
+    /// Permissions granted directly by this role.
+    pub permissions: Vec<String>,
+}
+
+/// A hierarchy of roles and the permissions they grant.
+///
+/// Identity providers usually issue coarse roles such as `admin` or `editor`.
+/// A role graph expands them into the fine grained permissions handlers
+/// check, including permissions inherited from other roles.
+///
+/// The graph can be built in code or deserialized from configuration as a map
+/// of role names to [`RoleDefinition`]s.
+///
+/// # Example
+/// ```
+/// use axum_jwks::RoleGraph;
+///
+/// let graph = RoleGraph::builder()
+///     .grant("viewer", ["posts:read"])
+///     .grant("editor", ["posts:write"])
+///     .inherit("editor", "viewer")
+///     .inherit("admin", "editor")
+///     .grant("admin", ["users:delete"])
+///     .build()
+///     .unwrap();
+///
+/// let permissions = graph.permissions_for(["admin"]);
+///
+/// assert!(permissions.contains("posts:read"));
+/// assert!(permissions.contains("users:delete"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct RoleGraph {
+    effective: Arc<HashMap<String, BTreeSet<String>>>,
+}
+
+impl RoleGraph {
+    pub fn builder() -> RoleGraphBuilder {
+        RoleGraphBuilder::default()
+    }
+
+    /// Build a graph from role definitions, e.g. loaded from configuration.
+    pub fn from_definitions(
+        definitions: HashMap<String, RoleDefinition>,
+    ) -> Result<Self, RoleGraphError> {
+        RoleGraphBuilder { definitions }.build()
+    }
+
+    /// The permissions granted by a set of roles.
+    ///
+    /// Roles that are not part of the graph grant no permissions.
+    pub fn permissions_for<I, R>(&self, roles: I) -> Permissions
+    where
+        I: IntoIterator<Item = R>,
+        R: AsRef<str>,
+    {
+        let mut permissions = BTreeSet::new();
+
+        for role in roles {
+            if let Some(granted) = self.effective.get(role.as_ref()) {
+                permissions.extend(granted.iter().cloned());
+            }
+        }
+
+        Permissions(permissions)
+    }
+}
+
+/// A builder for a [`RoleGraph`].
+#[derive(Clone, Debug, Default)]
+pub struct RoleGraphBuilder {
+    definitions: HashMap<String, RoleDefinition>,
+}
+
+impl RoleGraphBuilder {
+    /// Grant permissions to a role.
+    pub fn grant<I, P>(mut self, role: impl Into<String>, permissions: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<String>,
+    {
+        self.definitions
+            .entry(role.into())
+            .or_default()
+            .permissions
+            .extend(permissions.into_iter().map(Into::into));
+        self
+    }
+
+    /// Give a role every permission of another role.
+    pub fn inherit(mut self, role: impl Into<String>, parent: impl Into<String>) -> Self {
+        self.definitions
+            .entry(role.into())
+            .or_default()
+            .inherits
+            .push(parent.into());
+        self
+    }
+
+    /// Check the graph for unknown roles and cycles and compute the effective
+    /// permissions of every role.
+    pub fn build(self) -> Result<RoleGraph, RoleGraphError> {
+        for (role, definition) in &self.definitions {
+            for parent in &definition.inherits {
+                if !self.definitions.contains_key(parent) {
+                    return Err(RoleGraphError::UnknownRole {
+                        role: role.clone(),
+                        parent: parent.clone(),
+                    });
+                }
+            }
+        }
+
+        let mut effective = HashMap::with_capacity(self.definitions.len());
+        let mut roles: Vec<_> = self.definitions.keys().collect();
+        // Sort so that the reported cycle doesn't depend on hash order.
+        roles.sort();
+
+        for role in roles {
+            let mut path = Vec::new();
+            self.resolve(role, &mut path, &mut effective)?;
+        }
+
+        Ok(RoleGraph {
+            effective: Arc::new(effective),
+        })
+    }
+
+    fn resolve(
+        &self,
+        role: &str,
+        path: &mut Vec<String>,
+        effective: &mut HashMap<String, BTreeSet<String>>,
+    ) -> Result<(), RoleGraphError> {
+        if effective.contains_key(role) {
+            return Ok(());
+        }
+
+        if let Some(start) = path.iter().position(|visited| visited == role) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(role.to_owned());
+
+            return Err(RoleGraphError::Cycle(cycle));
+        }
+
+        let definition = &self.definitions[role];
+        let mut permissions: BTreeSet<String> = definition.permissions.iter().cloned().collect();
+
+        path.push(role.to_owned());
+        for parent in &definition.inherits {
+            self.resolve(parent, path, effective)?;
+            permissions.extend(effective[parent.as_str()].iter().cloned());
+        }
+        path.pop();
+
+        effective.insert(role.to_owned(), permissions);
+
+        Ok(())
+    }
+}
+
+/// An error building a [`RoleGraph`].
+#[derive(Debug, Error, PartialEq)]
+pub enum RoleGraphError {
+    /// A role inherits from itself, directly or through other roles. The
+    /// roles forming the cycle are listed in order, starting and ending with
+    /// the same role.
+    #[error("roles inherit from each other in a cycle: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+
+    /// A role inherits from a role that is not defined.
+    #[error("the role {role:?} inherits from the undefined role {parent:?}")]
+    UnknownRole { role: String, parent: String },
+}
+
+/// The effective permissions of the caller.
+///
+/// When used as an extractor, the caller's roles are read from their
+/// [`Principal`] and expanded with the [`RoleGraph`] from the application
+/// state.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Permissions(pub BTreeSet<String>);
+
+impl Permissions {
+    pub fn contains(&self, permission: &str) -> bool {
+        self.0.contains(permission)
+    }
+}
+
+impl<S> FromRequestParts<S> for Permissions
+where
+    Jwks: FromRef<S>,
+    PrincipalAdapter: FromRef<S>,
+    RoleGraph: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = TokenError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let principal = Principal::from_request_parts(parts, state).await?;
+
+        Ok(RoleGraph::from_ref(state).permissions_for(&principal.roles))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn set(items: &[&str]) -> BTreeSet<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn inherited_permissions() {
+        let graph = RoleGraph::builder()
+            .grant("viewer", ["read"])
+            .grant("editor", ["write"])
+            .inherit("editor", "viewer")
+            .grant("auditor", ["audit"])
+            .inherit("admin", "editor")
+            .inherit("admin", "auditor")
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            set(&["audit", "read", "write"]),
+            graph.permissions_for(["admin"]).0
+        );
+        assert_eq!(
+            set(&["audit", "read"]),
+            graph.permissions_for(["viewer", "auditor", "unknown"]).0
+        );
+    }
+
+    #[test]
+    fn detect_cycles() {
+        let result = RoleGraph::builder()
+            .inherit("a", "b")
+            .inherit("b", "c")
+            .inherit("c", "a")
+            .build();
+
+        assert_eq!(
+            RoleGraphError::Cycle(vec![
+                "a".to_owned(),
+                "b".to_owned(),
+                "c".to_owned(),
+                "a".to_owned()
+            ]),
+            result.unwrap_err()
+        );
+
+        let result = RoleGraph::builder().inherit("a", "a").build();
+        assert_eq!(
+            "roles inherit from each other in a cycle: a -> a",
+            result.unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn detect_unknown_parent() {
+        let result = RoleGraph::builder().inherit("admin", "editor").build();
+
+        assert_eq!(
+            RoleGraphError::UnknownRole {
+                role: "admin".to_owned(),
+                parent: "editor".to_owned(),
+            },
+            result.unwrap_err()
+        );
+    }
+
+    #[test]
+    fn from_configuration() {
+        let definitions = serde_json::from_value(json!({
+            "admin": { "inherits": ["editor"], "permissions": ["users:delete"] },
+            "editor": { "permissions": ["posts:write"] },
+        }))
+        .unwrap();
+        let graph = RoleGraph::from_definitions(definitions).unwrap();
+
+        assert_eq!(
+            set(&["posts:write", "users:delete"]),
+            graph.permissions_for(["admin"]).0
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use std::{collections::HashSet, marker::PhantomData};
+
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::{header::WWW_AUTHENTICATE, request::Parts, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde_json::Value;
+use thiserror::Error;
+use tracing::debug;
+
+use crate::{
+    claims::{accept_token, verified_token},
+    principal::string_list,
+    Jwks, TokenError,
+};
+
+/// A single OAuth scope, identified at the type level.
+///
+/// # Example
+/// ```
+/// use axum_jwks::Scope;
+///
+/// struct ReadUsers;
+///
+/// impl Scope for ReadUsers {
+///     const NAME: &'static str = "read:users";
+/// }
+/// ```
+pub trait Scope {
+    const NAME: &'static str;
+}
+
+/// A set of [`Scope`]s that must all be granted.
+///
+/// This is implemented for every [`Scope`] and for tuples of up to eight
+/// scopes.
+pub trait ScopeSet {
+    fn scopes() -> Vec<&'static str>;
+}
+
+impl<T: Scope> ScopeSet for T {
+    fn scopes() -> Vec<&'static str> {
+        vec![T::NAME]
+    }
+}
+
+macro_rules! impl_scope_set {
+    ($($ty:ident),+) => {
+        impl<$($ty: Scope),+> ScopeSet for ($($ty,)+) {
+            fn scopes() -> Vec<&'static str> {
+                vec![$($ty::NAME),+]
+            }
+        }
+    };
+}
+
+impl_scope_set!(A);
+impl_scope_set!(A, B);
+impl_scope_set!(A, B, C);
+impl_scope_set!(A, B, C, D);
+impl_scope_set!(A, B, C, D, E);
+impl_scope_set!(A, B, C, D, E, F);
+impl_scope_set!(A, B, C, D, E, F, G);
+impl_scope_set!(A, B, C, D, E, F, G, H);
+
+/// An extractor that only succeeds if the bearer token grants every scope in
+/// `T`.
+///
+/// Scopes are read from the `scope` and `scp` claims, either as a space
+/// delimited string or as an array. The token is validated with the [`Jwks`]
+/// from the application state, and the result is shared with any
+/// [`Claims`][crate::Claims] extractor for the same request, so the token is
+/// only validated once.
+///
+/// To protect a whole router instead of individual handlers, use the
+/// extractor as middleware with
+/// [`from_extractor_with_state`][axum::middleware::from_extractor_with_state].
+///
+/// # Example
+/// ```
+/// use axum_jwks::{Claims, RequireScopes, Scope};
+/// # use axum_jwks::{ParseTokenClaims, TokenError};
+/// # #[derive(serde::Deserialize)]
+/// # struct TokenClaims { sub: String }
+/// # impl<S: Send + Sync> ParseTokenClaims<S> for TokenClaims { type Rejection = TokenError; }
+///
+/// struct ReadUsers;
+///
+/// impl Scope for ReadUsers {
+///     const NAME: &'static str = "read:users";
+/// }
+///
+/// struct WriteUsers;
+///
+/// impl Scope for WriteUsers {
+///     const NAME: &'static str = "write:users";
+/// }
+///
+/// async fn update_user(
+///     _: RequireScopes<(ReadUsers, WriteUsers)>,
+///     Claims(claims): Claims<TokenClaims>,
+/// ) {
+///     todo!()
+/// }
+/// ```
+pub struct RequireScopes<T>(PhantomData<T>);
+
+impl<S, T> FromRequestParts<S> for RequireScopes<T>
+where
+    T: ScopeSet,
+    Jwks: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = ScopeRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let verified = verified_token(parts, state).await?;
+        let missing = missing_scopes(&*verified.claims()?, &T::scopes());
+
+        if !missing.is_empty() {
+            debug!(?missing, "Token is missing required scopes.");
+
+            return Err(ScopeRejection::InsufficientScope { missing });
+        }
+        accept_token(parts, &verified)?;
+
+        Ok(Self(PhantomData))
+    }
+}
+
+/// The scopes from `required` that are not granted by the claims.
+pub(crate) fn missing_scopes(claims: &Value, required: &[&str]) -> Vec<String> {
+    let granted: HashSet<String> = string_list(claims.get("scope"))
+        .chain(string_list(claims.get("scp")))
+        .collect();
+
+    required
+        .iter()
+        .filter(|scope| !granted.contains(**scope))
+        .map(|scope| scope.to_string())
+        .collect()
+}
+
+/// The rejection for [`RequireScopes`].
+#[derive(Debug, Error, PartialEq)]
+pub enum ScopeRejection {
+    #[error(transparent)]
+    Token(#[from] TokenError),
+
+    /// The token is valid but does not grant the required scopes.
+    #[error("the token is missing the scopes {missing:?}")]
+    InsufficientScope { missing: Vec<String> },
+}
+
+impl IntoResponse for ScopeRejection {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Token(error) => error.into_response(),
+            Self::InsufficientScope { missing } => {
+                let challenge = format!(
+                    "Bearer error=\"insufficient_scope\", scope=\"{}\"",
+                    missing.join(" ")
+                );
+
+                match HeaderValue::from_str(&challenge) {
+                    Ok(challenge) => {
+                        (StatusCode::FORBIDDEN, [(WWW_AUTHENTICATE, challenge)]).into_response()
+                    }
+                    Err(_) => StatusCode::FORBIDDEN.into_response(),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::http::{header::AUTHORIZATION, Request};
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{test_util, Claims, ParseTokenClaims};
+
+    struct Read;
+
+    impl Scope for Read {
+        const NAME: &'static str = "read";
+    }
+
+    struct Write;
+
+    impl Scope for Write {
+        const NAME: &'static str = "write";
+    }
+
+    fn parts(scope: Value) -> Parts {
+        let token = test_util::sign(&json!({
+            "sub": "user",
+            "scope": scope,
+            "exp": test_util::timestamp(60),
+        }));
+        let request = Request::builder()
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .body(())
+            .unwrap();
+
+        request.into_parts().0
+    }
+
+    #[test]
+    fn missing_scopes_from_string_and_array() {
+        let claims = json!({ "scope": "read other", "scp": ["admin"] });
+
+        assert_eq!(
+            vec!["write".to_owned()],
+            missing_scopes(&claims, &["read", "admin", "write"])
+        );
+    }
+
+    #[tokio::test]
+    async fn require_scopes_accepts_granted_scopes() {
+        let jwks = test_util::jwks(None);
+        let mut parts = parts(json!(["read", "write"]));
+
+        let result = RequireScopes::<(Read, Write)>::from_request_parts(&mut parts, &jwks).await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn require_scopes_rejects_missing_scopes() {
+        let jwks = test_util::jwks(None);
+        let mut parts = parts(json!("read"));
+
+        let rejection = RequireScopes::<(Read, Write)>::from_request_parts(&mut parts, &jwks)
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(
+            ScopeRejection::InsufficientScope {
+                missing: vec!["write".to_owned()]
+            },
+            rejection
+        );
+
+        let response = rejection.into_response();
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+        assert_eq!(
+            "Bearer error=\"insufficient_scope\", scope=\"write\"",
+            response.headers()[WWW_AUTHENTICATE]
+        );
+    }
+
+    #[tokio::test]
+    async fn claims_reuse_validated_token() {
+        #[derive(Deserialize)]
+        struct TestClaims {
+            sub: String,
+        }
+
+        impl<S: Send + Sync> ParseTokenClaims<S> for TestClaims {
+            type Rejection = TokenError;
+        }
+
+        let jwks = test_util::jwks(None);
+        let mut parts = parts(json!("read"));
+
+        RequireScopes::<Read>::from_request_parts(&mut parts, &jwks)
+            .await
+            .ok()
+            .unwrap();
+        let validated = verified_token(&mut parts, &jwks).await.ok().unwrap();
+
+        let Claims(claims) = Claims::<TestClaims>::from_request_parts(&mut parts, &jwks)
+            .await
+            .unwrap();
+
+        assert_eq!("user", claims.sub);
+        // The token was only validated once.
+        let reused = verified_token(&mut parts, &jwks).await.ok().unwrap();
+        assert!(Arc::ptr_eq(
+            &validated.claims().unwrap(),
+            &reused.claims().unwrap()
+        ));
+    }
+}
@@ -0,0 +1,452 @@
+use std::{collections::HashMap, fmt, path::Path, str::FromStr, sync::Arc};
+
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm, PublicKeyUse,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use thiserror::Error;
+use tracing::{debug, info};
+
+use crate::{
+    claims::{accept_token, cache_token, validate_token},
+    jwks::decode_unverified,
+    Jwks, JwksError, Token, TokenError,
+};
+
+const JWT_SVID_USE: &str = "jwt-svid";
+const MAX_ID_LENGTH: usize = 2048;
+
+/// A SPIFFE ID such as `spiffe://example.org/ns/default/sa/api`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SpiffeId {
+    trust_domain: String,
+    path: String,
+}
+
+impl SpiffeId {
+    /// The trust domain, e.g. `example.org`.
+    pub fn trust_domain(&self) -> &str {
+        &self.trust_domain
+    }
+
+    /// The path including its leading `/`, or an empty string for the ID of
+    /// the trust domain itself.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl fmt::Display for SpiffeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "spiffe://{}{}", self.trust_domain, self.path)
+    }
+}
+
+impl FromStr for SpiffeId {
+    type Err = SpiffeIdError;
+
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        if id.len() > MAX_ID_LENGTH {
+            return Err(SpiffeIdError::TooLong);
+        }
+
+        let rest = id
+            .strip_prefix("spiffe://")
+            .ok_or(SpiffeIdError::WrongScheme)?;
+        let (trust_domain, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+
+        if trust_domain.is_empty() {
+            return Err(SpiffeIdError::MissingTrustDomain);
+        }
+        if !trust_domain
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"-._".contains(&b))
+        {
+            return Err(SpiffeIdError::InvalidTrustDomain(trust_domain.to_owned()));
+        }
+
+        if !path.is_empty() {
+            for segment in path[1..].split('/') {
+                if segment.is_empty() || segment == "." || segment == ".." {
+                    return Err(SpiffeIdError::InvalidPath(path.to_owned()));
+                }
+                if !segment
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-._".contains(&b))
+                {
+                    return Err(SpiffeIdError::InvalidPath(path.to_owned()));
+                }
+            }
+        }
+
+        Ok(Self {
+            trust_domain: trust_domain.to_owned(),
+            path: path.to_owned(),
+        })
+    }
+}
+
+/// An error parsing a [`SpiffeId`].
+#[derive(Debug, Error, PartialEq)]
+pub enum SpiffeIdError {
+    #[error("the SPIFFE ID does not use the `spiffe` scheme")]
+    WrongScheme,
+
+    #[error("the SPIFFE ID does not contain a trust domain")]
+    MissingTrustDomain,
+
+    #[error("the trust domain {0:?} contains invalid characters")]
+    InvalidTrustDomain(String),
+
+    #[error("the path {0:?} contains an empty, relative or invalid segment")]
+    InvalidPath(String),
+
+    #[error("the SPIFFE ID is longer than {MAX_ID_LENGTH} bytes")]
+    TooLong,
+}
+
+/// The JSON form of a SPIFFE trust bundle.
+#[derive(Deserialize)]
+struct BundleDocument {
+    keys: Vec<Jwk>,
+}
+
+/// The JWT-SVID signing keys for a set of SPIFFE trust domains.
+///
+/// Tokens are verified with the keys of the trust domain named in their `sub`
+/// claim, so a key from one trust domain can never vouch for an identity in
+/// another.
+#[derive(Clone)]
+pub struct SpiffeBundles {
+    audience: String,
+    bundles: Arc<HashMap<String, Jwks>>,
+}
+
+impl SpiffeBundles {
+    /// Create an empty set of bundles.
+    ///
+    /// # Arguments
+    /// * `audience` - The identifier of this workload. This will be matched
+    ///   against the `aud` claim of every JWT-SVID.
+    pub fn new(audience: impl Into<String>) -> Self {
+        Self {
+            audience: audience.into(),
+            bundles: Arc::default(),
+        }
+    }
+
+    /// Add the bundle for a trust domain from its JSON representation.
+    ///
+    /// Only keys with `"use": "jwt-svid"` are kept; X.509 authorities in the
+    /// same bundle are ignored. Bundles usually omit the `alg` of their keys,
+    /// so it is inferred from the key type and curve. Adding a bundle for a
+    /// trust domain that already has one replaces it.
+    pub fn add_bundle(&mut self, trust_domain: &str, bundle: &[u8]) -> Result<(), JwksError> {
+        let document: BundleDocument = serde_json::from_slice(bundle)?;
+        let keys = document
+            .keys
+            .into_iter()
+            .filter(|key| match &key.common.public_key_use {
+                Some(PublicKeyUse::Other(key_use)) => key_use == JWT_SVID_USE,
+                _ => false,
+            })
+            .map(|mut key| {
+                if key.common.key_algorithm.is_none() {
+                    key.common.key_algorithm = key_algorithm(&key.algorithm);
+                }
+                key
+            })
+            .collect();
+        let jwks = Jwks::from_jwk_set(JwkSet { keys }, Some(&self.audience), None)?;
+
+        Arc::make_mut(&mut self.bundles).insert(trust_domain.to_owned(), jwks);
+
+        Ok(())
+    }
+
+    /// Add the bundle for a trust domain from a file on disk.
+    pub fn add_bundle_file(
+        &mut self,
+        trust_domain: &str,
+        path: impl AsRef<Path>,
+    ) -> Result<(), JwksError> {
+        let bundle = std::fs::read(path)?;
+
+        self.add_bundle(trust_domain, &bundle)
+    }
+
+    /// Add the bundle for a trust domain from a bundle endpoint using Web PKI
+    /// authentication.
+    pub async fn add_bundle_url(&mut self, trust_domain: &str, url: &str) -> Result<(), JwksError> {
+        self.add_bundle_url_with_client(&reqwest::Client::default(), trust_domain, url)
+            .await
+    }
+
+    /// A version of [`add_bundle_url`][Self::add_bundle_url] that allows for
+    /// passing in a custom [`Client`][reqwest::Client].
+    pub async fn add_bundle_url_with_client(
+        &mut self,
+        client: &reqwest::Client,
+        trust_domain: &str,
+        url: &str,
+    ) -> Result<(), JwksError> {
+        debug!(%url, %trust_domain, "Fetching SPIFFE bundle.");
+        let bundle = client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        self.add_bundle(trust_domain, &bundle)?;
+        info!(%url, %trust_domain, "Successfully pulled SPIFFE bundle.");
+
+        Ok(())
+    }
+
+    /// Validate a JWT-SVID and return the SPIFFE ID it was issued to along
+    /// with its claims.
+    pub fn validate<C>(&self, token: &str) -> Result<JwtSvid<C>, SpiffeRejection>
+    where
+        C: DeserializeOwned,
+    {
+        let (id, jwks) = self.bundle_for(token)?;
+        let claims = jwks.validate_claims(token)?.claims;
+
+        Ok(JwtSvid { id, claims })
+    }
+
+    /// The SPIFFE ID a JWT-SVID claims to be issued to, and the bundle of its
+    /// trust domain.
+    fn bundle_for(&self, token: &str) -> Result<(SpiffeId, &Jwks), SpiffeRejection> {
+        #[derive(Deserialize)]
+        struct Subject {
+            sub: String,
+        }
+
+        let Subject { sub } = decode_unverified(token)?;
+        let id: SpiffeId = sub.parse()?;
+        let jwks = self.bundles.get(id.trust_domain()).ok_or_else(|| {
+            debug!(%id, "JWT-SVID belongs to an untrusted trust domain.");
+
+            SpiffeRejection::UntrustedDomain(id.trust_domain().to_owned())
+        })?;
+
+        Ok((id, jwks))
+    }
+}
+
+/// A validated JWT-SVID.
+///
+/// When used as an extractor, the token is read from the `Authorization`
+/// header and verified with the [`SpiffeBundles`] in the application state.
+#[derive(Debug)]
+pub struct JwtSvid<C = serde_json::Value> {
+    /// The identity from the token's `sub` claim.
+    pub id: SpiffeId,
+
+    /// The token's claims.
+    pub claims: C,
+}
+
+/// The rejection for [`JwtSvid`].
+#[derive(Debug, Error, PartialEq)]
+pub enum SpiffeRejection {
+    #[error(transparent)]
+    Token(#[from] TokenError),
+
+    /// The `sub` claim is not a valid SPIFFE ID.
+    #[error("the subject is not a valid SPIFFE ID: {0}")]
+    InvalidId(#[from] SpiffeIdError),
+
+    /// There is no bundle for the trust domain in the `sub` claim.
+    #[error("the trust domain {0:?} is not trusted")]
+    UntrustedDomain(String),
+}
+
+impl IntoResponse for SpiffeRejection {
+    fn into_response(self) -> Response {
+        StatusCode::UNAUTHORIZED.into_response()
+    }
+}
+
+impl<S, C> FromRequestParts<S> for JwtSvid<C>
+where
+    C: DeserializeOwned,
+    SpiffeBundles: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = SpiffeRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let token = Token::from_request_parts(parts, state).await?;
+        let bundles = SpiffeBundles::from_ref(state);
+        let (id, jwks) = bundles.bundle_for(token.value())?;
+
+        let verified = validate_token(parts, jwks, &token).await?;
+        let verified = cache_token(parts, jwks, verified).await?;
+        let claims = verified.raw().deserialize()?;
+        accept_token(parts, &verified)?;
+
+        Ok(JwtSvid { id, claims })
+    }
+}
+
+/// The algorithm a SPIFFE bundle key without an `alg` is used with.
+fn key_algorithm(parameters: &AlgorithmParameters) -> Option<KeyAlgorithm> {
+    match parameters {
+        AlgorithmParameters::RSA(_) => Some(KeyAlgorithm::RS256),
+        AlgorithmParameters::EllipticCurve(ec) => match ec.curve {
+            EllipticCurve::P256 => Some(KeyAlgorithm::ES256),
+            EllipticCurve::P384 => Some(KeyAlgorithm::ES384),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_util;
+
+    fn bundle() -> Vec<u8> {
+        let mut keys = serde_json::to_value(test_util::jwk_set()).unwrap()["keys"].take();
+        for key in keys.as_array_mut().unwrap() {
+            // SPIRE doesn't include the algorithm of its keys.
+            key.as_object_mut().unwrap().remove("alg");
+            key["use"] = json!(JWT_SVID_USE);
+        }
+        keys.as_array_mut().unwrap().push(json!({
+            "use": "x509-svid",
+            "kty": "EC",
+            "crv": "P-256",
+            "x": "8VioVgWQrRj05IXNsGCW6Pf4B4authTKCV4ktxr_7Ic",
+            "y": "p-8IeCCmYRKTo1qOKR5pBu2lXm5ID85jkwwMgGVF6_Y",
+            "x5c": ["MIIB"],
+        }));
+
+        serde_json::to_vec(&json!({ "keys": keys, "spiffe_sequence": 1 })).unwrap()
+    }
+
+    fn svid(sub: &str, aud: &str) -> String {
+        test_util::sign(&json!({
+            "sub": sub,
+            "aud": [aud],
+            "exp": test_util::timestamp(60),
+        }))
+    }
+
+    #[test]
+    fn parse_valid_ids() {
+        let id: SpiffeId = "spiffe://example.org/ns/default/sa/api".parse().unwrap();
+        assert_eq!("example.org", id.trust_domain());
+        assert_eq!("/ns/default/sa/api", id.path());
+        assert_eq!("spiffe://example.org/ns/default/sa/api", id.to_string());
+
+        let id: SpiffeId = "spiffe://example.org".parse().unwrap();
+        assert_eq!("", id.path());
+    }
+
+    #[test]
+    fn parse_invalid_ids() {
+        let cases = [
+            ("https://example.org/a", SpiffeIdError::WrongScheme),
+            ("spiffe:///a", SpiffeIdError::MissingTrustDomain),
+            (
+                "spiffe://Example.org/a",
+                SpiffeIdError::InvalidTrustDomain("Example.org".to_owned()),
+            ),
+            (
+                "spiffe://example.org:8080/a",
+                SpiffeIdError::InvalidTrustDomain("example.org:8080".to_owned()),
+            ),
+            (
+                "spiffe://example.org/a/../b",
+                SpiffeIdError::InvalidPath("/a/../b".to_owned()),
+            ),
+            (
+                "spiffe://example.org/a/",
+                SpiffeIdError::InvalidPath("/a/".to_owned()),
+            ),
+            (
+                "spiffe://example.org/a?b",
+                SpiffeIdError::InvalidPath("/a?b".to_owned()),
+            ),
+        ];
+
+        for (id, expected) in cases {
+            assert_eq!(Err(expected), id.parse::<SpiffeId>(), "{id}");
+        }
+    }
+
+    #[test]
+    fn validate_svid() {
+        let mut bundles = SpiffeBundles::new("spiffe://example.org/api");
+        bundles.add_bundle("example.org", &bundle()).unwrap();
+
+        let token = svid("spiffe://example.org/client", "spiffe://example.org/api");
+        let svid = bundles.validate::<serde_json::Value>(&token).unwrap();
+
+        assert_eq!("/client", svid.id.path());
+
+        let token = test_util::sign_ec(&json!({
+            "sub": "spiffe://example.org/client",
+            "aud": ["spiffe://example.org/api"],
+            "exp": test_util::timestamp(60),
+        }));
+        assert!(bundles.validate::<serde_json::Value>(&token).is_ok());
+    }
+
+    #[test]
+    fn reject_malformed_bundle() {
+        let mut bundles = SpiffeBundles::new("spiffe://example.org/api");
+
+        assert!(matches!(
+            bundles.add_bundle("example.org", b"{\"keys\": "),
+            Err(JwksError::InvalidDocument(_))
+        ));
+    }
+
+    #[test]
+    fn reject_untrusted_domain_and_wrong_audience() {
+        let mut bundles = SpiffeBundles::new("spiffe://example.org/api");
+        bundles.add_bundle("example.org", &bundle()).unwrap();
+
+        let token = svid("spiffe://other.org/client", "spiffe://example.org/api");
+        assert_eq!(
+            SpiffeRejection::UntrustedDomain("other.org".to_owned()),
+            bundles.validate::<serde_json::Value>(&token).unwrap_err()
+        );
+
+        let token = svid("spiffe://example.org/client", "spiffe://example.org/other");
+        assert!(matches!(
+            bundles.validate::<serde_json::Value>(&token),
+            Err(SpiffeRejection::Token(TokenError::Invalid(_)))
+        ));
+    }
+
+    #[test]
+    fn reject_keys_without_jwt_svid_use() {
+        let mut bundles = SpiffeBundles::new("spiffe://example.org/api");
+        let bundle = serde_json::to_vec(&test_util::jwk_set()).unwrap();
+        bundles.add_bundle("example.org", &bundle).unwrap();
+
+        let token = svid("spiffe://example.org/client", "spiffe://example.org/api");
+
+        assert!(matches!(
+            bundles.validate::<serde_json::Value>(&token),
+            Err(SpiffeRejection::Token(TokenError::UnknownKeyId(_)))
+        ));
+    }
+}
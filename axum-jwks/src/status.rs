@@ -0,0 +1,519 @@
+use std::{
+    collections::HashMap,
+    io::Read,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use flate2::read::ZlibDecoder;
+use reqwest::header::ACCEPT;
+use serde::Deserialize;
+use serde_json::Value;
+use thiserror::Error;
+use tracing::{debug, warn};
+
+use crate::{Jwks, TokenError, ValidationOptions};
+
+/// The `typ` header of status list tokens.
+const STATUS_LIST_TYPE: &str = "statuslist+jwt";
+
+/// How long status lists are cached unless configured otherwise.
+const DEFAULT_MAX_CACHE_AGE: Duration = Duration::from_secs(5 * 60);
+
+/// The maximum size of a decompressed status list.
+const MAX_LIST_SIZE: u64 = 16 * 1024 * 1024;
+
+const STATUS_VALID: u8 = 0;
+const STATUS_SUSPENDED: u8 = 2;
+
+/// What happens to a token whose status list cannot be fetched or verified.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FailureMode {
+    /// Accept the token as if it was valid.
+    Open,
+
+    /// Reject the token with [`TokenError::StatusUnavailable`].
+    #[default]
+    Closed,
+}
+
+/// Rejects tokens that were revoked or suspended through a [Token Status
+/// List](https://datatracker.ietf.org/doc/draft-ietf-oauth-status-list/).
+///
+/// Tokens refer to their status with a `status.status_list` claim containing
+/// the `uri` of a status list token and their `idx` in that list. Status list
+/// tokens are fetched, verified with the same [`Jwks`] as the token itself and
+/// cached for the duration given by their `ttl` and `exp` claims, but no
+/// longer than [`max_cache_age`][Self::max_cache_age]. Tokens without the
+/// claim are not affected.
+///
+/// A status of `0x00` (valid) is accepted. `0x02` (suspended) is rejected with
+/// [`TokenError::Suspended`] and every other status with
+/// [`TokenError::Revoked`].
+///
+/// Insert the checker into the request's extensions with
+/// [`Extension`][axum::Extension] to check every token validated by the
+/// extractors in this crate. Otherwise, call [`check`][Self::check] after
+/// validating a token yourself. The checker is cheap to clone, and clones
+/// share their cache.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+///
+/// use axum::{routing::get, Extension, Router};
+/// use axum_jwks::{Claims, FailureMode, Jwks, StatusListChecker};
+/// # use axum_jwks::{ParseTokenClaims, TokenError};
+/// # #[derive(serde::Deserialize)]
+/// # struct TokenClaims {}
+/// # impl<S: Send + Sync> ParseTokenClaims<S> for TokenClaims { type Rejection = TokenError; }
+///
+/// async fn handler(Claims(claims): Claims<TokenClaims>) {}
+///
+/// # fn app(jwks: Jwks) -> Router {
+/// let checker = StatusListChecker::new()
+///     .max_cache_age(Duration::from_secs(60))
+///     .on_failure(FailureMode::Open);
+///
+/// Router::new()
+///     .route("/", get(handler))
+///     .layer(Extension(checker))
+///     .with_state(jwks)
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct StatusListChecker {
+    client: reqwest::Client,
+    max_cache_age: Duration,
+    failure_mode: FailureMode,
+    cache: Arc<Mutex<HashMap<String, CachedList>>>,
+}
+
+impl StatusListChecker {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::default(),
+            max_cache_age: DEFAULT_MAX_CACHE_AGE,
+            failure_mode: FailureMode::default(),
+            cache: Arc::default(),
+        }
+    }
+
+    /// Fetch status lists with a custom [`Client`][reqwest::Client].
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Cache status lists for at most the given duration. Defaults to five
+    /// minutes.
+    ///
+    /// A duration of zero fetches the status list for every token.
+    pub fn max_cache_age(mut self, max_age: Duration) -> Self {
+        self.max_cache_age = max_age;
+        self
+    }
+
+    /// Choose what happens when a status list cannot be fetched or verified.
+    /// Defaults to [`FailureMode::Closed`].
+    pub fn on_failure(mut self, mode: FailureMode) -> Self {
+        self.failure_mode = mode;
+        self
+    }
+
+    /// Check the status of validated claims.
+    pub async fn check(&self, jwks: &Jwks, claims: &Value) -> Result<(), TokenError> {
+        let Some(reference) = claims.pointer("/status/status_list") else {
+            return Ok(());
+        };
+        let reference = StatusReference::deserialize(reference).map_err(|error| {
+            debug!(%error, "Token has a malformed status list reference.");
+
+            TokenError::StatusUnavailable
+        })?;
+
+        let list = match self.list(jwks, &reference.uri).await {
+            Ok(list) => list,
+            Err(error) => {
+                warn!(%error, uri = %reference.uri, "Could not get status list.");
+
+                return match self.failure_mode {
+                    FailureMode::Open => Ok(()),
+                    FailureMode::Closed => Err(TokenError::StatusUnavailable),
+                };
+            }
+        };
+
+        match list.get(reference.idx) {
+            Some(STATUS_VALID) => Ok(()),
+            Some(STATUS_SUSPENDED) => Err(TokenError::Suspended),
+            Some(status) => {
+                debug!(status, "Token is not valid according to its status list.");
+
+                Err(TokenError::Revoked)
+            }
+            None => {
+                debug!(idx = reference.idx, "Status list index is out of range.");
+
+                Err(TokenError::StatusUnavailable)
+            }
+        }
+    }
+
+    /// Get a status list from the cache, or fetch it if it is not cached.
+    async fn list(&self, jwks: &Jwks, uri: &str) -> Result<Arc<StatusList>, StatusListError> {
+        if let Some(cached) = self.cache().get(uri) {
+            if cached.expires_at > Instant::now() {
+                return Ok(cached.list.clone());
+            }
+        }
+
+        let token = self
+            .client
+            .get(uri)
+            .header(ACCEPT, "application/statuslist+jwt")
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let data = jwks.validate_claims_with::<StatusListClaims>(
+            token.trim(),
+            &ValidationOptions::default().relaxed(),
+        )?;
+
+        if data.header.typ.as_deref() != Some(STATUS_LIST_TYPE) {
+            return Err(StatusListError::Type(data.header.typ));
+        }
+        if data.claims.sub != uri {
+            return Err(StatusListError::Subject(data.claims.sub));
+        }
+
+        let list = Arc::new(StatusList::decode(&data.claims.status_list)?);
+
+        let mut max_age = self.max_cache_age;
+        if let Some(ttl) = data.claims.ttl {
+            max_age = max_age.min(Duration::from_secs(ttl));
+        }
+        let expires_at = data
+            .claims
+            .exp
+            .and_then(|exp| SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(exp)));
+        if let Some(expires_at) = expires_at {
+            let remaining = expires_at
+                .duration_since(SystemTime::now())
+                .unwrap_or_default();
+            max_age = max_age.min(remaining);
+        }
+
+        let now = Instant::now();
+        let mut cache = self.cache();
+        cache.retain(|_, cached| cached.expires_at > now);
+        cache.insert(
+            uri.to_owned(),
+            CachedList {
+                list: list.clone(),
+                expires_at: now + max_age,
+            },
+        );
+
+        Ok(list)
+    }
+
+    fn cache(&self) -> MutexGuard<'_, HashMap<String, CachedList>> {
+        self.cache.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+impl Default for StatusListChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The `status.status_list` claim of a referenced token.
+#[derive(Deserialize)]
+struct StatusReference {
+    idx: usize,
+    uri: String,
+}
+
+#[derive(Deserialize)]
+struct StatusListClaims {
+    sub: String,
+    exp: Option<u64>,
+    ttl: Option<u64>,
+    status_list: EncodedStatusList,
+}
+
+#[derive(Deserialize)]
+struct EncodedStatusList {
+    bits: u8,
+    lst: String,
+}
+
+struct CachedList {
+    list: Arc<StatusList>,
+    expires_at: Instant,
+}
+
+/// A decompressed list of statuses with a fixed number of bits each.
+#[derive(Debug, PartialEq)]
+struct StatusList {
+    bits: u8,
+    statuses: Vec<u8>,
+}
+
+impl StatusList {
+    fn decode(encoded: &EncodedStatusList) -> Result<Self, StatusListError> {
+        if !matches!(encoded.bits, 1 | 2 | 4 | 8) {
+            return Err(StatusListError::Bits(encoded.bits));
+        }
+
+        let compressed = URL_SAFE_NO_PAD
+            .decode(&encoded.lst)
+            .map_err(|_| StatusListError::Encoding)?;
+        let mut statuses = Vec::new();
+        ZlibDecoder::new(compressed.as_slice())
+            .take(MAX_LIST_SIZE)
+            .read_to_end(&mut statuses)
+            .map_err(|_| StatusListError::Encoding)?;
+
+        Ok(Self {
+            bits: encoded.bits,
+            statuses,
+        })
+    }
+
+    /// The status at an index, starting from the least significant bits of
+    /// the first byte.
+    fn get(&self, index: usize) -> Option<u8> {
+        let offset = index.checked_mul(usize::from(self.bits))?;
+        let byte = self.statuses.get(offset / 8)?;
+        let mask = ((1u16 << self.bits) - 1) as u8;
+
+        Some((byte >> (offset % 8)) & mask)
+    }
+}
+
+#[derive(Debug, Error)]
+enum StatusListError {
+    #[error("unsupported number of bits per status: {0}")]
+    Bits(u8),
+
+    #[error("the status list is not valid compressed base64url")]
+    Encoding,
+
+    #[error("could not fetch the status list: {0}")]
+    Fetch(#[from] reqwest::Error),
+
+    #[error("the status list token's subject {0:?} does not match its URI")]
+    Subject(String),
+
+    #[error("invalid status list token: {0}")]
+    Token(#[from] TokenError),
+
+    #[error("unexpected status list token type {0:?}")]
+    Type(Option<String>),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request, StatusCode},
+        routing::get,
+        Extension, Router,
+    };
+    use flate2::{write::ZlibEncoder, Compression};
+    use jsonwebtoken::{Algorithm, Header};
+    use serde_json::json;
+    use tokio::net::TcpListener;
+    use tower_service::Service;
+
+    use super::*;
+    use crate::{test_util, Claims, ParseTokenClaims};
+
+    #[derive(serde::Deserialize)]
+    struct TestClaims {}
+
+    impl<S: Send + Sync> ParseTokenClaims<S> for TestClaims {
+        type Rejection = TokenError;
+    }
+
+    fn decode(bits: u8, lst: &str) -> StatusList {
+        StatusList::decode(&EncodedStatusList {
+            bits,
+            lst: lst.to_owned(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn decode_draft_examples() {
+        let list = decode(1, "eNrbuRgAAhcBXQ");
+        let statuses: Vec<_> = (0..16).filter_map(|index| list.get(index)).collect();
+
+        assert_eq!(
+            vec![1, 0, 0, 1, 1, 1, 0, 1, 1, 1, 0, 0, 0, 1, 0, 1],
+            statuses
+        );
+        assert_eq!(None, list.get(16));
+
+        let list = decode(2, "eNo76fITAAPfAgc");
+        let statuses: Vec<_> = (0..12).filter_map(|index| list.get(index)).collect();
+
+        assert_eq!(vec![1, 2, 0, 3, 0, 1, 0, 1, 1, 2, 3, 3], statuses);
+    }
+
+    #[test]
+    fn reject_unsupported_bits() {
+        let encoded = EncodedStatusList {
+            bits: 3,
+            lst: "eNrbuRgAAhcBXQ".to_owned(),
+        };
+
+        assert!(matches!(
+            StatusList::decode(&encoded),
+            Err(StatusListError::Bits(3))
+        ));
+    }
+
+    /// Serve a status list with one bit per status where only the token at
+    /// index 1 is revoked and the list expires at `exp`, counting how often it
+    /// is fetched.
+    async fn status_list_server(fetches: Arc<AtomicUsize>, exp: Value) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("http://{}/statuslists/1", listener.local_addr().unwrap());
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[0b0000_0010]).unwrap();
+        let lst = URL_SAFE_NO_PAD.encode(encoder.finish().unwrap());
+
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(test_util::RSA_KID.to_owned());
+        header.typ = Some(STATUS_LIST_TYPE.to_owned());
+        let token = test_util::sign_with_header(
+            &header,
+            &json!({
+                "sub": uri,
+                "iat": test_util::timestamp(0),
+                "exp": exp,
+                "status_list": { "bits": 1, "lst": lst },
+            }),
+        );
+
+        let router = Router::new().route(
+            "/statuslists/1",
+            get(move || async move {
+                fetches.fetch_add(1, Ordering::SeqCst);
+                token
+            }),
+        );
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        uri
+    }
+
+    fn token(uri: &str, idx: usize) -> String {
+        test_util::sign(&json!({
+            "exp": test_util::timestamp(60),
+            "status": { "status_list": { "idx": idx, "uri": uri } },
+        }))
+    }
+
+    async fn status(router: &mut Router, token: &str) -> StatusCode {
+        let request = Request::builder()
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+
+        router.call(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn reject_revoked_tokens() {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let uri = status_list_server(fetches.clone(), json!(test_util::timestamp(600))).await;
+        let mut router = Router::new()
+            .route("/", get(|_: Claims<TestClaims>| async {}))
+            .layer(Extension(StatusListChecker::new()))
+            .with_state(test_util::jwks(Some("some-audience")));
+
+        let without_status = test_util::sign(&json!({
+            "aud": "some-audience",
+            "exp": test_util::timestamp(60),
+        }));
+        let with_audience = |idx| {
+            test_util::sign(&json!({
+                "aud": "some-audience",
+                "exp": test_util::timestamp(60),
+                "status": { "status_list": { "idx": idx, "uri": uri } },
+            }))
+        };
+
+        assert_eq!(StatusCode::OK, status(&mut router, &without_status).await);
+        assert_eq!(StatusCode::OK, status(&mut router, &with_audience(0)).await);
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            status(&mut router, &with_audience(1)).await
+        );
+        assert_eq!(
+            StatusCode::SERVICE_UNAVAILABLE,
+            status(&mut router, &with_audience(8)).await
+        );
+        assert_eq!(1, fetches.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn check_reports_status() {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let uri = status_list_server(fetches.clone(), json!(test_util::timestamp(600))).await;
+        let checker = StatusListChecker::new().max_cache_age(Duration::ZERO);
+        let jwks = test_util::jwks(None);
+        let claims = |idx| json!({ "status": { "status_list": { "idx": idx, "uri": uri } } });
+
+        assert_eq!(Ok(()), checker.check(&jwks, &claims(0)).await);
+        assert_eq!(
+            Err(TokenError::Revoked),
+            checker.check(&jwks, &claims(1)).await
+        );
+        assert_eq!(2, fetches.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn far_future_list_expiry() {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let uri = status_list_server(fetches.clone(), json!(u64::MAX)).await;
+        let checker = StatusListChecker::new();
+        let claims = json!({ "status": { "status_list": { "idx": 0, "uri": uri } } });
+
+        assert_eq!(Ok(()), checker.check(&test_util::jwks(None), &claims).await);
+    }
+
+    #[tokio::test]
+    async fn failure_mode() {
+        // Nothing listens on the port once the listener is dropped.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("http://{}/statuslists/1", listener.local_addr().unwrap());
+        drop(listener);
+
+        for (mode, expected) in [
+            (FailureMode::Open, StatusCode::OK),
+            (FailureMode::Closed, StatusCode::SERVICE_UNAVAILABLE),
+        ] {
+            let mut router = Router::new()
+                .route("/", get(|_: Claims<TestClaims>| async {}))
+                .layer(Extension(StatusListChecker::new().on_failure(mode)))
+                .with_state(test_util::jwks(None));
+
+            assert_eq!(expected, status(&mut router, &token(&uri, 0)).await);
+        }
+    }
+}
@@ -0,0 +1,372 @@
+use axum::{
+    extract::{FromRef, FromRequestParts, RawPathParams},
+    http::{header::HOST, request::Parts, HeaderName, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use thiserror::Error;
+use tracing::debug;
+
+use crate::{
+    claims::{accept_token, verified_token},
+    principal::string_list,
+    Jwks, TokenError,
+};
+
+/// Where the tenant a request is addressed to comes from.
+#[derive(Clone, Debug)]
+pub enum TenantSource {
+    /// A path parameter of the matched route, e.g. `id` for
+    /// `/tenants/{id}/...`.
+    PathParam(String),
+
+    /// The subdomain of the `Host` header below a base domain, e.g. `acme` for
+    /// `acme.example.com` with the base domain `example.com`.
+    Subdomain { base_domain: String },
+
+    /// The value of a request header.
+    Header(HeaderName),
+}
+
+/// How a token's tenant claim is bound to the tenant a request addresses.
+///
+/// Make this available from the application state with
+/// [`FromRef`][axum::extract::FromRef] to use the [`TenantScoped`]
+/// extractor.
+///
+/// # Example
+/// ```
+/// use axum_jwks::{TenantBinding, TenantSource};
+///
+/// let binding = TenantBinding::new(TenantSource::PathParam("id".to_owned()))
+///     .claim("org_id")
+///     .cross_tenant_role("support");
+/// ```
+#[derive(Clone, Debug)]
+pub struct TenantBinding {
+    claims: Vec<String>,
+    source: TenantSource,
+    roles_claim: String,
+    cross_tenant_roles: Vec<String>,
+}
+
+impl TenantBinding {
+    /// Bind the `tenant_id` claim to the given source.
+    pub fn new(source: TenantSource) -> Self {
+        Self {
+            claims: Vec::new(),
+            source,
+            roles_claim: "roles".to_owned(),
+            cross_tenant_roles: Vec::new(),
+        }
+    }
+
+    /// Read the tenant from the given claim instead of `tenant_id`.
+    ///
+    /// Calling this multiple times adds fallbacks, which are tried in order.
+    /// The claim can be a top-level name or a JSON pointer.
+    pub fn claim(mut self, claim: impl Into<String>) -> Self {
+        self.claims.push(claim.into());
+        self
+    }
+
+    /// Allow callers with the given role to access every tenant.
+    pub fn cross_tenant_role(mut self, role: impl Into<String>) -> Self {
+        self.cross_tenant_roles.push(role.into());
+        self
+    }
+
+    /// Read roles for [`cross_tenant_role`][Self::cross_tenant_role] from the
+    /// given claim instead of `roles`.
+    pub fn roles_claim(mut self, claim: impl Into<String>) -> Self {
+        self.roles_claim = claim.into();
+        self
+    }
+
+    fn claimed_tenant(&self, claims: &Value) -> Option<String> {
+        let default = ["tenant_id".to_owned()];
+        let names = if self.claims.is_empty() {
+            &default[..]
+        } else {
+            &self.claims
+        };
+
+        names.iter().find_map(|claim| {
+            let value = if claim.starts_with('/') {
+                claims.pointer(claim)
+            } else {
+                claims.get(claim)
+            };
+
+            match value? {
+                Value::String(tenant) => Some(tenant.clone()),
+                Value::Number(tenant) => Some(tenant.to_string()),
+                _ => None,
+            }
+        })
+    }
+
+    fn is_cross_tenant(&self, claims: &Value) -> bool {
+        let roles_claim = if self.roles_claim.starts_with('/') {
+            claims.pointer(&self.roles_claim)
+        } else {
+            claims.get(&self.roles_claim)
+        };
+
+        string_list(roles_claim).any(|role| self.cross_tenant_roles.contains(&role))
+    }
+
+    async fn requested_tenant(&self, parts: &mut Parts) -> Option<String> {
+        match &self.source {
+            TenantSource::PathParam(name) => {
+                let params = RawPathParams::from_request_parts(parts, &()).await.ok()?;
+                params
+                    .iter()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.to_owned())
+            }
+            TenantSource::Subdomain { base_domain } => {
+                let host = parts
+                    .headers
+                    .get(HOST)
+                    .and_then(|host| host.to_str().ok())
+                    .or_else(|| parts.uri.host())?;
+                let host = host.split(':').next()?.to_ascii_lowercase();
+                let subdomain =
+                    host.strip_suffix(&format!(".{}", base_domain.to_ascii_lowercase()))?;
+
+                (!subdomain.is_empty() && !subdomain.contains('.')).then(|| subdomain.to_owned())
+            }
+            TenantSource::Header(name) => parts
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned),
+        }
+    }
+}
+
+/// Claims of a token that belongs to the tenant the request addresses.
+///
+/// The token is validated with the [`Jwks`] from the application state, and
+/// its tenant claim is compared to the requested tenant as configured by the
+/// [`TenantBinding`] from the application state. Callers with a cross-tenant
+/// role may access any tenant.
+///
+/// When binding to a path parameter, use the extractor in handlers or with
+/// [`Router::route_layer`][axum::Router::route_layer] so the route has been
+/// matched.
+#[derive(Debug)]
+pub struct TenantScoped<C = Value> {
+    /// The tenant the request addresses.
+    pub tenant: String,
+    pub claims: C,
+}
+
+impl<S, C> FromRequestParts<S> for TenantScoped<C>
+where
+    C: DeserializeOwned,
+    Jwks: FromRef<S>,
+    TenantBinding: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = TenantRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let verified = verified_token(parts, state).await?;
+        let claims = verified.claims()?;
+        let binding = TenantBinding::from_ref(state);
+
+        let tenant = binding
+            .requested_tenant(parts)
+            .await
+            .ok_or(TenantRejection::MissingTenant)?;
+
+        if !binding.is_cross_tenant(&claims) {
+            let claimed = binding
+                .claimed_tenant(&claims)
+                .ok_or(TenantRejection::MissingTenantClaim)?;
+
+            if claimed != tenant {
+                debug!(%claimed, requested = %tenant, "Token belongs to another tenant.");
+
+                return Err(TenantRejection::Mismatch {
+                    claimed,
+                    requested: tenant,
+                });
+            }
+        }
+
+        let claims = verified.raw().deserialize()?;
+        accept_token(parts, &verified)?;
+
+        Ok(Self { tenant, claims })
+    }
+}
+
+/// The rejection for [`TenantScoped`].
+#[derive(Debug, Error, PartialEq)]
+pub enum TenantRejection {
+    #[error(transparent)]
+    Token(#[from] TokenError),
+
+    /// The request does not identify a tenant.
+    #[error("the request does not identify a tenant")]
+    MissingTenant,
+
+    /// The token does not contain a tenant claim.
+    #[error("the token does not identify a tenant")]
+    MissingTenantClaim,
+
+    /// The token belongs to a different tenant than the request addresses.
+    #[error("the token belongs to tenant {claimed:?} but tenant {requested:?} was requested")]
+    Mismatch { claimed: String, requested: String },
+}
+
+impl IntoResponse for TenantRejection {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Token(error) => error.into_response(),
+            Self::MissingTenant => StatusCode::BAD_REQUEST.into_response(),
+            Self::MissingTenantClaim | Self::Mismatch { .. } => {
+                StatusCode::FORBIDDEN.into_response()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request},
+        routing::get,
+        Router,
+    };
+    use serde_json::json;
+    use tower_service::Service;
+
+    use super::*;
+    use crate::test_util;
+
+    #[derive(Clone)]
+    struct State {
+        jwks: Jwks,
+        binding: TenantBinding,
+    }
+
+    impl FromRef<State> for Jwks {
+        fn from_ref(state: &State) -> Self {
+            state.jwks.clone()
+        }
+    }
+
+    impl FromRef<State> for TenantBinding {
+        fn from_ref(state: &State) -> Self {
+            state.binding.clone()
+        }
+    }
+
+    fn state(source: TenantSource) -> State {
+        State {
+            jwks: test_util::jwks(None),
+            binding: TenantBinding::new(source)
+                .claim("tenant_id")
+                .claim("org_id")
+                .cross_tenant_role("support"),
+        }
+    }
+
+    fn bearer(claims: Value) -> String {
+        let mut claims = claims;
+        claims["exp"] = json!(test_util::timestamp(60));
+
+        format!("Bearer {}", test_util::sign(&claims))
+    }
+
+    #[tokio::test]
+    async fn path_param_binding() {
+        async fn handler(scoped: TenantScoped) -> String {
+            scoped.tenant
+        }
+
+        let mut router = Router::new()
+            .route("/tenants/{id}/things", get(handler))
+            .with_state(state(TenantSource::PathParam("id".to_owned())));
+
+        let cases = [
+            (json!({ "tenant_id": "t1" }), StatusCode::OK),
+            (json!({ "org_id": "t1" }), StatusCode::OK),
+            (json!({ "tenant_id": "t2" }), StatusCode::FORBIDDEN),
+            (json!({}), StatusCode::FORBIDDEN),
+            (
+                json!({ "tenant_id": "t2", "roles": ["support"] }),
+                StatusCode::OK,
+            ),
+        ];
+
+        for (claims, status) in cases {
+            let request = Request::builder()
+                .uri("/tenants/t1/things")
+                .header(AUTHORIZATION, bearer(claims.clone()))
+                .body(Body::empty())
+                .unwrap();
+
+            let response = router.call(request).await.unwrap();
+
+            assert_eq!(status, response.status(), "{claims}");
+        }
+    }
+
+    #[tokio::test]
+    async fn subdomain_binding() {
+        let state = state(TenantSource::Subdomain {
+            base_domain: "example.com".to_owned(),
+        });
+
+        for (host, expected) in [
+            ("acme.example.com:8080", Ok("acme")),
+            ("other.example.com", Err(StatusCode::FORBIDDEN)),
+            ("example.com", Err(StatusCode::BAD_REQUEST)),
+            ("a.acme.example.com", Err(StatusCode::BAD_REQUEST)),
+        ] {
+            let request = Request::builder()
+                .header(HOST, host)
+                .header(AUTHORIZATION, bearer(json!({ "tenant_id": "acme" })))
+                .body(())
+                .unwrap();
+            let (mut parts, _) = request.into_parts();
+
+            let result = TenantScoped::<Value>::from_request_parts(&mut parts, &state)
+                .await
+                .map(|scoped| scoped.tenant)
+                .map_err(|rejection| rejection.into_response().status());
+
+            assert_eq!(expected.map(str::to_owned), result, "{host}");
+        }
+    }
+
+    #[tokio::test]
+    async fn header_binding() {
+        let state = state(TenantSource::Header(HeaderName::from_static("x-tenant")));
+        let request = Request::builder()
+            .header("x-tenant", "acme")
+            .header(AUTHORIZATION, bearer(json!({ "tenant_id": "other" })))
+            .body(())
+            .unwrap();
+        let (mut parts, _) = request.into_parts();
+
+        let rejection = TenantScoped::<Value>::from_request_parts(&mut parts, &state)
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            TenantRejection::Mismatch {
+                claimed: "other".to_owned(),
+                requested: "acme".to_owned(),
+            },
+            rejection
+        );
+    }
+}
@@ -1,24 +1,44 @@
 use axum::{
     extract::FromRequestParts,
-    http::{request::Parts, StatusCode},
+    http::{header::AsHeaderName, request::Parts, StatusCode},
     response::IntoResponse,
-    RequestPartsExt,
-};
-use axum_extra::{
-    headers::{authorization::Bearer, Authorization},
-    TypedHeader,
 };
 use thiserror::Error;
 
-/// A JWT provided as a bearer token in an `Authorization` header.
+use crate::TokenLocator;
+
+/// A JWT provided with a request.
+///
+/// When used as an extractor, the token is found with the [`TokenLocator`] in
+/// the request's extensions. Without one, the token is read from the
+/// `Authorization` header as a bearer token.
 #[derive(PartialEq)]
 pub struct Token(String);
 
 impl Token {
+    pub(crate) fn new(value: String) -> Self {
+        Self(value)
+    }
+
     /// Get the token's value.
     pub fn value(&self) -> &str {
         &self.0
     }
+
+    /// Read a raw token from a custom request header.
+    ///
+    /// This is used for proxies that pass their assertion in a dedicated
+    /// header rather than as a bearer token.
+    pub fn from_header<K: AsHeaderName>(parts: &Parts, name: K) -> Result<Self, TokenError> {
+        parts
+            .headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(|value| Self(value.to_owned()))
+            .ok_or(TokenError::Missing)
+    }
 }
 
 impl core::fmt::Debug for Token {
@@ -35,18 +55,32 @@ where
     type Rejection = TokenError;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        let TypedHeader(Authorization(bearer)) = parts
-            .extract::<TypedHeader<Authorization<Bearer>>>()
-            .await
-            .map_err(|_| TokenError::Missing)?;
-
-        Ok(Self(bearer.token().to_owned()))
+        TokenLocator::from_extensions(parts).locate(parts)
     }
 }
 
 /// An error with a JWT.
 #[derive(Debug, Error, PartialEq)]
 pub enum TokenError {
+    /// The request contains more than one token.
+    #[error("the request contains more than one token")]
+    Ambiguous,
+
+    /// The token was read from a cookie, but the request failed the
+    /// [`CsrfProtection`][crate::CsrfProtection] checks.
+    #[error("the request failed CSRF protection checks")]
+    CsrfCheckFailed,
+
+    /// The token introspection endpoint reported that the token is not
+    /// active.
+    #[error("the token is not active")]
+    Inactive,
+
+    /// The token could not be introspected because the introspection
+    /// endpoint failed or returned a malformed response.
+    #[error("the token could not be introspected")]
+    IntrospectionFailed,
+
     /// The token is either malformed or did not pass validation.
     #[error("the token is invalid or malformed: {0:?}")]
     Invalid(jsonwebtoken::errors::Error),
@@ -55,8 +89,8 @@ pub enum TokenError {
     #[error("the token header is malformed: {0:?}")]
     InvalidHeader(jsonwebtoken::errors::Error),
 
-    /// No bearer token found in the `Authorization` header.
-    #[error("no bearer token found")]
+    /// No token found in the request.
+    #[error("no token found")]
     Missing,
 
     /// The token's header does not contain the `kid` attribute used to identify
@@ -64,6 +98,27 @@ pub enum TokenError {
     #[error("the token header does not specify a `kid`")]
     MissingKeyId,
 
+    /// The token does not have the `jti` claim required by a
+    /// [`ReplayGuard`][crate::ReplayGuard].
+    #[error("the token does not have a `jti` claim")]
+    MissingTokenId,
+
+    /// The token's `jti` was already used.
+    #[error("the token was already used")]
+    Replayed,
+
+    /// The token was revoked through its status list.
+    #[error("the token was revoked")]
+    Revoked,
+
+    /// The status of the token could not be determined from its status list.
+    #[error("the status of the token could not be determined")]
+    StatusUnavailable,
+
+    /// The token was suspended through its status list.
+    #[error("the token is suspended")]
+    Suspended,
+
     /// The token's `kid` attribute specifies a key that is unknown.
     #[error("token uses the unknown key {0:?}")]
     UnknownKeyId(String),
@@ -71,7 +126,14 @@ pub enum TokenError {
 
 impl IntoResponse for TokenError {
     fn into_response(self) -> axum::response::Response {
-        StatusCode::UNAUTHORIZED.into_response()
+        match self {
+            Self::Ambiguous => StatusCode::BAD_REQUEST.into_response(),
+            Self::CsrfCheckFailed => StatusCode::FORBIDDEN.into_response(),
+            Self::IntrospectionFailed | Self::StatusUnavailable => {
+                StatusCode::SERVICE_UNAVAILABLE.into_response()
+            }
+            _ => StatusCode::UNAUTHORIZED.into_response(),
+        }
     }
 }
 
@@ -126,4 +188,21 @@ mod tests {
 
         assert_eq!(TokenError::Missing, err);
     }
+
+    #[test]
+    fn parse_from_custom_header() {
+        let request = Request::builder()
+            .header("x-assertion", " some-token-value ")
+            .body(())
+            .unwrap();
+        let (parts, _) = request.into_parts();
+
+        let parsed = Token::from_header(&parts, "x-assertion").unwrap();
+
+        assert_eq!(Token("some-token-value".to_owned()), parsed);
+        assert_eq!(
+            Err(TokenError::Missing),
+            Token::from_header(&parts, "x-other")
+        );
+    }
 }
@@ -0,0 +1,464 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use ring::digest::{digest, SHA256};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::Value;
+use thiserror::Error;
+use tracing::{debug, warn};
+
+use crate::{
+    claims::{accept_token, deserialize_claims, verified_token},
+    Jwks, JwksError, ParseTokenClaims, TokenError, TokenLocator,
+};
+
+/// Fetches profile data for access tokens from an OIDC `userinfo_endpoint`.
+///
+/// The endpoint is called with the access token itself. Responses are cached
+/// until the token's `exp`, and only by the token's SHA-256 digest. Tokens
+/// without an `exp` claim, or with one too far in the future to represent, are
+/// not cached.
+///
+/// The client is cheap to clone, and clones share their cache. Add it to the
+/// application state to use the [`Enriched`] extractor.
+#[derive(Clone)]
+pub struct UserInfo {
+    client: reqwest::Client,
+    endpoint: String,
+    cache: Arc<Mutex<HashMap<Vec<u8>, CachedUserInfo>>>,
+}
+
+#[derive(Deserialize)]
+struct Oid {
+    userinfo_endpoint: Option<String>,
+}
+
+impl UserInfo {
+    /// Use the given userinfo endpoint.
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::default(),
+            endpoint: endpoint.into(),
+            cache: Arc::default(),
+        }
+    }
+
+    /// Use the userinfo endpoint from an authority's openid-configuration.
+    pub async fn from_oidc_url(oidc_url: &str) -> Result<Self, JwksError> {
+        Self::from_oidc_url_with_client(&reqwest::Client::default(), oidc_url).await
+    }
+
+    /// A version of [`from_oidc_url`][Self::from_oidc_url] that allows for
+    /// passing in a custom [`Client`][reqwest::Client].
+    pub async fn from_oidc_url_with_client(
+        client: &reqwest::Client,
+        oidc_url: &str,
+    ) -> Result<Self, JwksError> {
+        debug!(%oidc_url, "Fetching openid-configuration.");
+        let oidc = client.get(oidc_url).send().await?.json::<Oid>().await?;
+        let endpoint = oidc
+            .userinfo_endpoint
+            .ok_or(JwksError::MissingEndpoint("userinfo_endpoint"))?;
+
+        Ok(Self::new(endpoint).client(client.clone()))
+    }
+
+    /// Call the endpoint with a custom [`Client`][reqwest::Client].
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Fetch the userinfo for a token whose claims were already validated.
+    ///
+    /// The `sub` of the userinfo response must match the `sub` claim of the
+    /// token.
+    pub async fn fetch(&self, token: &str, claims: &Value) -> Result<Value, UserInfoRejection> {
+        let subject = claims
+            .get("sub")
+            .and_then(Value::as_str)
+            .ok_or(UserInfoRejection::SubjectMismatch)?;
+
+        let key = digest(&SHA256, token.as_bytes()).as_ref().to_vec();
+        if let Some(cached) = self.cache().get(&key) {
+            if cached.expires_at > SystemTime::now() {
+                return Ok(cached.userinfo.clone());
+            }
+        }
+
+        let response = self
+            .client
+            .get(&self.endpoint)
+            .bearer_auth(token)
+            .send()
+            .await
+            .map_err(|error| {
+                warn!(%error, "Could not reach the userinfo endpoint.");
+
+                UserInfoRejection::Unavailable
+            })?;
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            debug!("Userinfo endpoint rejected the token.");
+
+            return Err(UserInfoRejection::Rejected);
+        }
+        let userinfo = response
+            .error_for_status()
+            .map_err(|error| {
+                warn!(%error, "Userinfo request failed.");
+
+                UserInfoRejection::Unavailable
+            })?
+            .json::<Value>()
+            .await
+            .map_err(|error| {
+                warn!(%error, "Userinfo response is malformed.");
+
+                UserInfoRejection::Unavailable
+            })?;
+
+        if userinfo.get("sub").and_then(Value::as_str) != Some(subject) {
+            debug!(%subject, "Userinfo is for a different subject than the token.");
+
+            return Err(UserInfoRejection::SubjectMismatch);
+        }
+
+        let expires_at = claims
+            .get("exp")
+            .and_then(Value::as_u64)
+            .and_then(|exp| SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(exp)));
+        if let Some(expires_at) = expires_at {
+            let now = SystemTime::now();
+            let mut cache = self.cache();
+            cache.retain(|_, cached| cached.expires_at > now);
+            cache.insert(
+                key,
+                CachedUserInfo {
+                    userinfo: userinfo.clone(),
+                    expires_at,
+                },
+            );
+        }
+
+        Ok(userinfo)
+    }
+
+    fn cache(&self) -> MutexGuard<'_, HashMap<Vec<u8>, CachedUserInfo>> {
+        self.cache.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+struct CachedUserInfo {
+    userinfo: Value,
+    expires_at: SystemTime,
+}
+
+/// Token claims enriched with the token's userinfo.
+///
+/// The token is validated with the [`Jwks`] from the application state, and
+/// its userinfo is fetched with the [`UserInfo`] from the application state.
+/// Both are merged into a single object before being deserialized into `C`.
+/// Claims of the token take precedence over userinfo with the same name.
+///
+/// [`ParseTokenClaims::check_claims`] checks the claims of the token alone,
+/// and [`ParseTokenClaims::validate`] the merged claims.
+///
+/// # Example
+/// ```
+/// use axum::{extract::FromRef, routing::get, Router};
+/// use axum_jwks::{Enriched, Jwks, ParseTokenClaims, TokenError, UserInfo};
+/// use serde::Deserialize;
+///
+/// #[derive(Clone)]
+/// struct AppState {
+///     jwks: Jwks,
+///     userinfo: UserInfo,
+/// }
+///
+/// impl FromRef<AppState> for Jwks {
+///     fn from_ref(state: &AppState) -> Self {
+///         state.jwks.clone()
+///     }
+/// }
+///
+/// impl FromRef<AppState> for UserInfo {
+///     fn from_ref(state: &AppState) -> Self {
+///         state.userinfo.clone()
+///     }
+/// }
+///
+/// #[derive(Deserialize)]
+/// struct Profile {
+///     sub: String,
+///     email: String,
+///     #[serde(default)]
+///     groups: Vec<String>,
+/// }
+///
+/// impl<S: Send + Sync> ParseTokenClaims<S> for Profile {
+///     type Rejection = TokenError;
+/// }
+///
+/// async fn handler(Enriched(profile): Enriched<Profile>) -> String {
+///     profile.email
+/// }
+///
+/// # fn app(state: AppState) -> Router {
+/// Router::new().route("/", get(handler)).with_state(state)
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Enriched<C = Value>(pub C);
+
+impl<S, C> FromRequestParts<S> for Enriched<C>
+where
+    C: DeserializeOwned + ParseTokenClaims<S> + Send,
+    Jwks: FromRef<S>,
+    UserInfo: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = UserInfoRejection<C::Rejection>;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let verified = verified_token(parts, state).await?;
+        C::check_claims(verified.raw()).map_err(UserInfoRejection::Claims)?;
+        let claims = verified.claims()?;
+        let token = TokenLocator::from_extensions(parts).locate(parts)?;
+        let userinfo = UserInfo::from_ref(state)
+            .fetch(token.value(), &claims)
+            .await
+            .map_err(UserInfoRejection::for_claims)?;
+
+        let mut merged = match userinfo {
+            Value::Object(userinfo) => userinfo,
+            _ => return Err(UserInfoRejection::Unavailable),
+        };
+        if let Value::Object(claims) = claims.as_ref() {
+            merged.extend(claims.clone());
+        }
+
+        let claims: C = deserialize_claims(&Value::Object(merged))?;
+        claims
+            .validate(state)
+            .await
+            .map_err(UserInfoRejection::Claims)?;
+        accept_token(parts, &verified)?;
+
+        Ok(Self(claims))
+    }
+}
+
+/// The rejection for [`Enriched`].
+///
+/// `R` is the rejection of the claims type's [`ParseTokenClaims`]
+/// implementation.
+#[derive(Debug, Error, PartialEq)]
+pub enum UserInfoRejection<R = TokenError> {
+    #[error(transparent)]
+    Token(#[from] TokenError),
+
+    /// The claims were rejected by their [`ParseTokenClaims`] implementation.
+    #[error("the token's claims were rejected")]
+    Claims(R),
+
+    /// The userinfo endpoint rejected the token.
+    #[error("the userinfo endpoint rejected the token")]
+    Rejected,
+
+    /// The userinfo is for a different subject than the token, or the token
+    /// has no `sub` claim.
+    #[error("the userinfo subject does not match the token")]
+    SubjectMismatch,
+
+    /// The userinfo endpoint could not be reached or returned a malformed
+    /// response.
+    #[error("the userinfo endpoint is unavailable")]
+    Unavailable,
+}
+
+impl UserInfoRejection {
+    /// Convert a rejection from fetching userinfo for any claims type.
+    fn for_claims<R>(self) -> UserInfoRejection<R> {
+        match self {
+            Self::Token(error) | Self::Claims(error) => UserInfoRejection::Token(error),
+            Self::Rejected => UserInfoRejection::Rejected,
+            Self::SubjectMismatch => UserInfoRejection::SubjectMismatch,
+            Self::Unavailable => UserInfoRejection::Unavailable,
+        }
+    }
+}
+
+impl<R: IntoResponse> IntoResponse for UserInfoRejection<R> {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Token(error) => error.into_response(),
+            Self::Claims(rejection) => rejection.into_response(),
+            Self::Rejected | Self::SubjectMismatch => StatusCode::UNAUTHORIZED.into_response(),
+            Self::Unavailable => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, HeaderMap, Request},
+        routing::get,
+        Json, Router,
+    };
+    use serde_json::json;
+    use tokio::net::TcpListener;
+    use tower_service::Service;
+
+    use super::*;
+    use crate::test_util;
+
+    #[derive(Clone)]
+    struct State {
+        jwks: Jwks,
+        userinfo: UserInfo,
+    }
+
+    impl FromRef<State> for Jwks {
+        fn from_ref(state: &State) -> Self {
+            state.jwks.clone()
+        }
+    }
+
+    impl FromRef<State> for UserInfo {
+        fn from_ref(state: &State) -> Self {
+            state.userinfo.clone()
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct Profile {
+        sub: String,
+        email: String,
+        role: String,
+    }
+
+    impl<S: Send + Sync> ParseTokenClaims<S> for Profile {
+        type Rejection = TokenError;
+    }
+
+    fn token(sub: &str) -> String {
+        test_util::sign(&json!({
+            "sub": sub,
+            "role": "admin",
+            "exp": test_util::timestamp(60),
+        }))
+    }
+
+    /// Serve a stand-in for an authorization server whose userinfo endpoint
+    /// returns a profile for "alice" for any token, counting how often it is
+    /// called.
+    async fn fake_authorization_server(calls: Arc<AtomicUsize>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+
+        let discovery = {
+            let base = base.clone();
+            move || async move { Json(json!({ "userinfo_endpoint": format!("{base}/userinfo") })) }
+        };
+        let userinfo = move |headers: HeaderMap| {
+            calls.fetch_add(1, Ordering::SeqCst);
+
+            async move {
+                if headers.get(AUTHORIZATION).is_none() {
+                    return Err(StatusCode::UNAUTHORIZED);
+                }
+
+                Ok(Json(json!({
+                    "sub": "alice",
+                    "email": "alice@example.com",
+                    "role": "guest",
+                })))
+            }
+        };
+
+        let router = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/userinfo", get(userinfo));
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        format!("{base}/.well-known/openid-configuration")
+    }
+
+    #[tokio::test]
+    async fn enrich_claims_with_userinfo() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let oidc_url = fake_authorization_server(calls.clone()).await;
+        let state = State {
+            jwks: test_util::jwks(None),
+            userinfo: UserInfo::from_oidc_url(&oidc_url).await.unwrap(),
+        };
+        let handler = |Enriched(profile): Enriched<Profile>| async move {
+            format!("{} {} {}", profile.sub, profile.email, profile.role)
+        };
+        let mut router = Router::new().route("/", get(handler)).with_state(state);
+
+        let alice = token("alice");
+        for _ in 0..2 {
+            let request = Request::builder()
+                .header(AUTHORIZATION, format!("Bearer {alice}"))
+                .body(Body::empty())
+                .unwrap();
+            let response = router.call(request).await.unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+
+            assert_eq!(&b"alice alice@example.com admin"[..], &body[..]);
+        }
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+
+        let request = Request::builder()
+            .header(AUTHORIZATION, format!("Bearer {}", token("bob")))
+            .body(Body::empty())
+            .unwrap();
+
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            router.call(request).await.unwrap().status()
+        );
+    }
+
+    #[tokio::test]
+    async fn far_future_expiry_is_not_cached() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let oidc_url = fake_authorization_server(calls.clone()).await;
+        let userinfo = UserInfo::from_oidc_url(&oidc_url).await.unwrap();
+        let claims = json!({ "sub": "alice", "exp": u64::MAX });
+
+        for _ in 0..2 {
+            assert!(userinfo.fetch("token", &claims).await.is_ok());
+        }
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn unreachable_endpoint() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/userinfo", listener.local_addr().unwrap());
+        drop(listener);
+
+        assert_eq!(
+            Err(UserInfoRejection::Unavailable),
+            UserInfo::new(endpoint)
+                .fetch("token", &json!({ "sub": "alice" }))
+                .await
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use jsonwebtoken::{errors::ErrorKind, Header};
+use serde::de::DeserializeOwned;
+use tracing::debug;
+
+use crate::{
+    claims::{accept_token, verified_token, VerifiedToken},
+    Jwks, KeyInfo, ParseTokenClaims, TokenError,
+};
+
+/// Claims of a validated token together with its header and the key that
+/// verified it.
+///
+/// This is validated exactly like [`Claims`][crate::Claims], and is useful
+/// for audit logs or for watching traffic move to a new signing key. Tokens
+/// that were accepted through [`Introspection`][crate::Introspection] have no
+/// header and are rejected with [`TokenError::InvalidHeader`].
+///
+/// # Example
+/// ```
+/// use axum_jwks::{RegisteredClaims, Verified};
+///
+/// async fn handler(verified: Verified<RegisteredClaims>) {
+///     tracing::info!(
+///         sub = ?verified.claims.subject,
+///         kid = %verified.key.key_id,
+///         alg = ?verified.header.alg,
+///         "Request authenticated."
+///     );
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Verified<C> {
+    pub claims: C,
+    pub header: Header,
+    pub key: KeyInfo,
+}
+
+impl<S, C> FromRequestParts<S> for Verified<C>
+where
+    C: DeserializeOwned + ParseTokenClaims<S> + Send,
+    Jwks: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = C::Rejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let verified = verified_token(parts, state).await?;
+        let (header, key) = verified_key(&verified, &Jwks::from_ref(state))?;
+
+        C::check_claims(verified.raw())?;
+        let claims: C = verified.raw().deserialize()?;
+        claims.validate(state).await?;
+        accept_token(parts, &verified)?;
+
+        Ok(Self {
+            claims,
+            header,
+            key,
+        })
+    }
+}
+
+/// Look up the header and key of a validated token.
+fn verified_key(verified: &VerifiedToken, jwks: &Jwks) -> Result<(Header, KeyInfo), TokenError> {
+    let header = verified.header().cloned().ok_or_else(|| {
+        debug!("Verified token has no JOSE header.");
+
+        TokenError::InvalidHeader(ErrorKind::InvalidToken.into())
+    })?;
+    let kid = header.kid.as_deref().ok_or(TokenError::MissingKeyId)?;
+    let key = jwks
+        .key_info(kid)
+        .cloned()
+        .ok_or_else(|| TokenError::UnknownKeyId(kid.to_owned()))?;
+
+    Ok((header, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use jsonwebtoken::Algorithm;
+    use serde_json::json;
+    use tower_service::Service;
+
+    use super::*;
+    use crate::{test_util, RegisteredClaims};
+
+    async fn call(token: &str) -> (StatusCode, String) {
+        let handler = |verified: Verified<RegisteredClaims>| async move {
+            format!(
+                "{} {} {:?} {:?}",
+                verified.claims.subject.unwrap(),
+                verified.key.key_id,
+                verified.key.algorithm,
+                verified.header.typ,
+            )
+        };
+        let mut router = Router::new()
+            .route("/", get(handler))
+            .with_state(test_util::jwks(None));
+
+        let request = Request::builder()
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+        let response = router.call(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn expose_header_and_key() {
+        let claims = json!({ "sub": "alice", "exp": test_util::timestamp(60) });
+
+        assert_eq!(
+            (
+                StatusCode::OK,
+                format!("alice {} RS256 Some(\"JWT\")", test_util::RSA_KID)
+            ),
+            call(&test_util::sign(&claims)).await
+        );
+        assert_eq!(
+            (
+                StatusCode::OK,
+                format!("alice {} ES256 Some(\"JWT\")", test_util::EC_KID)
+            ),
+            call(&test_util::sign_ec(&claims)).await
+        );
+        assert_eq!(StatusCode::UNAUTHORIZED, call("opaque").await.0);
+    }
+
+    #[test]
+    fn key_info() {
+        let jwks = test_util::jwks(None);
+        let key = jwks.key_info(test_util::RSA_KID).unwrap();
+
+        assert_eq!(Algorithm::RS256, key.algorithm);
+        assert_eq!(None, key.jwks_url);
+        assert!(jwks.key_info("unknown").is_none());
+    }
+}
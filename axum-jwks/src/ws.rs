@@ -0,0 +1,350 @@
+use std::time::{Duration, SystemTime};
+
+use axum::{
+    extract::{
+        ws::{rejection::WebSocketUpgradeRejection, WebSocketUpgrade},
+        FromRef, FromRequestParts,
+    },
+    http::{header::SEC_WEBSOCKET_PROTOCOL, request::Parts},
+    response::{IntoResponse, Response},
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::{
+    claims::{accept_token, cache_token, validate_token},
+    Jwks, ParseTokenClaims, Token, TokenError,
+};
+
+/// The subprotocol that precedes the token in a WebSocket upgrade request.
+///
+/// Browsers cannot set an `Authorization` header when opening a WebSocket, so
+/// clients offer this protocol followed by the token instead:
+///
+/// ```js
+/// new WebSocket(url, ["bearer", token]);
+/// ```
+pub const WEBSOCKET_BEARER_PROTOCOL: &str = "bearer";
+
+/// An authenticated WebSocket upgrade.
+///
+/// The token is read from the `Sec-WebSocket-Protocol` header, where it
+/// follows the [`WEBSOCKET_BEARER_PROTOCOL`], and validated with the
+/// [`Jwks`] from the application state. The upgrade selects the
+/// [`WEBSOCKET_BEARER_PROTOCOL`], so it is echoed back to the client and the
+/// token never is. The claims are checked with [`ParseTokenClaims`] like
+/// [`Claims`][crate::Claims] does.
+///
+/// Connections usually outlive the token they were opened with. Use
+/// [`TokenExpiry::wait`] to close the connection, or ask the client to
+/// re-authenticate, once the token expires.
+///
+/// # Example
+/// ```no_run
+/// use axum::{extract::ws::Message, response::Response};
+/// use axum_jwks::AuthenticatedUpgrade;
+///
+/// async fn handler(upgrade: AuthenticatedUpgrade) -> Response {
+///     let AuthenticatedUpgrade { upgrade, expiry, .. } = upgrade;
+///
+///     upgrade.on_upgrade(move |mut socket| async move {
+///         loop {
+///             tokio::select! {
+///                 _ = expiry.wait() => {
+///                     let _ = socket.send(Message::Close(None)).await;
+///                     break;
+///                 }
+///                 message = socket.recv() => match message {
+///                     Some(Ok(message)) => {
+///                         if socket.send(message).await.is_err() {
+///                             break;
+///                         }
+///                     }
+///                     _ => break,
+///                 },
+///             }
+///         }
+///     })
+/// }
+/// ```
+#[derive(Debug)]
+pub struct AuthenticatedUpgrade<C = Value> {
+    pub upgrade: WebSocketUpgrade,
+    pub claims: C,
+    pub expiry: TokenExpiry,
+}
+
+impl<S, C> FromRequestParts<S> for AuthenticatedUpgrade<C>
+where
+    C: DeserializeOwned + ParseTokenClaims<S> + Send,
+    Jwks: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = WebSocketRejection<C::Rejection>;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        // Reject other requests before the token is used up.
+        let upgrade = WebSocketUpgrade::from_request_parts(parts, state)
+            .await?
+            .protocols([WEBSOCKET_BEARER_PROTOCOL]);
+
+        let token = protocol_token(parts)?;
+        let jwks = Jwks::from_ref(state);
+        let verified = validate_token(parts, &jwks, &token).await?;
+        let verified = cache_token(parts, &jwks, verified).await?;
+        C::check_claims(verified.raw()).map_err(WebSocketRejection::Claims)?;
+        let claims: C = verified.raw().deserialize()?;
+        claims
+            .validate(state)
+            .await
+            .map_err(WebSocketRejection::Claims)?;
+        accept_token(parts, &verified)?;
+
+        Ok(Self {
+            upgrade,
+            expiry: TokenExpiry::from_claims(&*verified.claims()?),
+            claims,
+        })
+    }
+}
+
+/// Read the token that follows the bearer protocol.
+fn protocol_token(parts: &Parts) -> Result<Token, TokenError> {
+    let mut protocols = parts
+        .headers
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim);
+
+    protocols
+        .find(|protocol| *protocol == WEBSOCKET_BEARER_PROTOCOL)
+        .and_then(|_| protocols.next())
+        .filter(|token| !token.is_empty())
+        .map(|token| Token::new(token.to_owned()))
+        .ok_or(TokenError::Missing)
+}
+
+/// The time at which the token of a connection expires.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TokenExpiry {
+    expires_at: Option<SystemTime>,
+}
+
+impl TokenExpiry {
+    fn from_claims(claims: &Value) -> Self {
+        let expires_at = claims
+            .get("exp")
+            .and_then(Value::as_u64)
+            .and_then(|exp| SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(exp)));
+
+        Self { expires_at }
+    }
+
+    /// When the token expires, or `None` if it has no `exp` claim or one too
+    /// far in the future to represent.
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.expires_at
+    }
+
+    /// Wait until the token expires.
+    ///
+    /// This never completes for tokens without an `exp` claim, or with one too
+    /// far in the future to represent.
+    pub async fn wait(&self) {
+        match self.expires_at {
+            Some(expires_at) => {
+                let remaining = expires_at
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default();
+
+                tokio::time::sleep(remaining).await
+            }
+            None => std::future::pending().await,
+        }
+    }
+}
+
+/// The rejection for [`AuthenticatedUpgrade`].
+///
+/// `R` is the rejection of the claims type's [`ParseTokenClaims`]
+/// implementation.
+#[derive(Debug, Error)]
+pub enum WebSocketRejection<R = TokenError> {
+    #[error(transparent)]
+    Token(#[from] TokenError),
+
+    /// The claims were rejected by their [`ParseTokenClaims`] implementation.
+    #[error("the token's claims were rejected")]
+    Claims(R),
+
+    /// The request is not a valid WebSocket upgrade.
+    #[error(transparent)]
+    Upgrade(#[from] WebSocketUpgradeRejection),
+}
+
+impl<R: IntoResponse> IntoResponse for WebSocketRejection<R> {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Token(error) => error.into_response(),
+            Self::Claims(rejection) => rejection.into_response(),
+            Self::Upgrade(rejection) => rejection.into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        extract::ws::Message,
+        http::{header::AUTHORIZATION, Request},
+        routing::get,
+        Router,
+    };
+    use serde_json::json;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+    use crate::{claims::verified_token, test_util, InMemoryReplayStore, ReplayGuard};
+
+    #[test]
+    fn token_follows_bearer_protocol() {
+        let request = Request::builder()
+            .header(SEC_WEBSOCKET_PROTOCOL, "graphql-ws, bearer")
+            .header(SEC_WEBSOCKET_PROTOCOL, "some-token")
+            .body(())
+            .unwrap();
+        let (parts, _) = request.into_parts();
+
+        assert_eq!(
+            Ok(Token::new("some-token".to_owned())),
+            protocol_token(&parts)
+        );
+
+        let request = Request::builder()
+            .header(SEC_WEBSOCKET_PROTOCOL, "some-token, bearer")
+            .body(())
+            .unwrap();
+        let (parts, _) = request.into_parts();
+
+        assert_eq!(Err(TokenError::Missing), protocol_token(&parts));
+    }
+
+    #[tokio::test]
+    async fn non_upgrade_request_keeps_token() {
+        let token = test_util::sign(&json!({ "jti": "1", "exp": test_util::timestamp(60) }));
+        let guard = ReplayGuard::new(InMemoryReplayStore::new());
+        let (mut parts, _) = Request::builder()
+            .header(SEC_WEBSOCKET_PROTOCOL, format!("bearer, {token}"))
+            .body(())
+            .unwrap()
+            .into_parts();
+        parts.extensions.insert(guard.clone());
+        let jwks = test_util::jwks(None);
+
+        let result = AuthenticatedUpgrade::<Value>::from_request_parts(&mut parts, &jwks).await;
+
+        assert!(matches!(result, Err(WebSocketRejection::Upgrade(_))));
+        assert_eq!(Ok(()), guard.check(&json!({ "jti": "1" })));
+    }
+
+    #[tokio::test]
+    async fn protocol_token_is_not_shared() {
+        let token = test_util::sign(&json!({ "sub": "alice", "exp": test_util::timestamp(60) }));
+        let (mut parts, _) = Request::builder()
+            .header(SEC_WEBSOCKET_PROTOCOL, format!("bearer, {token}"))
+            .body(())
+            .unwrap()
+            .into_parts();
+        let jwks = test_util::jwks(None);
+        let verified = validate_token(&parts, &jwks, &protocol_token(&parts).unwrap())
+            .await
+            .unwrap();
+        cache_token(&mut parts, &jwks, verified).await.unwrap();
+
+        // Extractors reading the `Authorization` header do not see the claims
+        // of the protocol token.
+        assert_eq!(
+            Some(TokenError::Missing),
+            verified_token(&mut parts, &jwks).await.err()
+        );
+
+        let other = test_util::sign(&json!({ "sub": "bob", "exp": test_util::timestamp(60) }));
+        parts
+            .headers
+            .insert(AUTHORIZATION, format!("Bearer {other}").try_into().unwrap());
+
+        assert_eq!(
+            Some("bob"),
+            verified_token(&mut parts, &jwks)
+                .await
+                .ok()
+                .unwrap()
+                .claims()
+                .unwrap()["sub"]
+                .as_str()
+        );
+    }
+
+    #[test]
+    fn far_future_expiry() {
+        let expiry = TokenExpiry::from_claims(&json!({ "exp": u64::MAX }));
+
+        assert_eq!(None, expiry.expires_at());
+    }
+
+    #[tokio::test]
+    async fn upgrade_and_notify_on_expiry() {
+        async fn handler(upgrade: AuthenticatedUpgrade) -> Response {
+            let AuthenticatedUpgrade {
+                upgrade, expiry, ..
+            } = upgrade;
+
+            upgrade.on_upgrade(move |mut socket| async move {
+                expiry.wait().await;
+                let _ = socket.send(Message::Text("expired".into())).await;
+            })
+        }
+
+        let router = Router::new()
+            .route("/ws", get(handler))
+            .with_state(test_util::jwks(None));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        let token = test_util::sign(&json!({ "exp": test_util::timestamp(1) }));
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let request = format!(
+            "GET /ws HTTP/1.1\r\n\
+             Host: {address}\r\n\
+             Connection: Upgrade\r\n\
+             Upgrade: websocket\r\n\
+             Sec-WebSocket-Version: 13\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+             Sec-WebSocket-Protocol: bearer, {token}\r\n\r\n"
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            response.push(stream.read_u8().await.unwrap());
+        }
+        let response = String::from_utf8(response).unwrap().to_lowercase();
+
+        assert!(response.starts_with("http/1.1 101"), "{response}");
+        assert!(response.contains("sec-websocket-protocol: bearer\r\n"));
+        assert!(!response.contains(&token.to_lowercase()));
+
+        // An unmasked text frame with the 7 byte payload "expired".
+        let mut frame = [0; 9];
+        stream.read_exact(&mut frame).await.unwrap();
+
+        assert_eq!(b"\x81\x07expired", &frame);
+    }
+}